@@ -1,6 +1,6 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use rayrs::{renderer::Renderer, camera::{Camera, PerspectiveCamera}, integrator::{self, TestIntegrator}, sampler::{self, RandomSampler}, scene::Scene, material::{Lambertian, Material, Metal, Dielectric}, hittable::{Sphere, Hittable}, object::Object};
+use rayrs::{renderer::Renderer, camera::PerspectiveCamera, integrator::TestIntegrator, scene::Scene, material::{Lambertian, Material, Metal, Dielectric}, hittable::{Sphere, Hittable}, object::Object};
 
 fn main() {
     let width = 400;
@@ -19,15 +19,15 @@ fn main() {
     let integrator = TestIntegrator::new();
     let mut scene = Scene::new();
 
-    let material_ground = Rc::new(Box::new(Lambertian::new(glam::DVec3::new(0.8, 0.8, 0.0))) as Box<dyn Material>) ;
-    let material_center = Rc::new(Box::new(Lambertian::new(glam::DVec3::new(0.1, 0.2, 0.5))) as Box<dyn Material>);
-    let material_left = Rc::new(Box::new(Dielectric::new(1.5)) as Box<dyn Material>);
-    let material_right = Rc::new(Box::new(Metal::new(glam::DVec3::new(0.8, 0.6, 0.2), 0.0)) as Box<dyn Material>);
+    let material_ground = Arc::new(Lambertian::new(glam::DVec3::new(0.8, 0.8, 0.0))) as Arc<dyn Material> ;
+    let material_center = Arc::new(Lambertian::new(glam::DVec3::new(0.1, 0.2, 0.5))) as Arc<dyn Material>;
+    let material_left = Arc::new(Dielectric::new(1.5)) as Arc<dyn Material>;
+    let material_right = Arc::new(Metal::new(glam::DVec3::new(0.8, 0.6, 0.2), 0.0)) as Arc<dyn Material>;
 
-    let sphere1 = Rc::new(Box::new(Sphere::new(glam::DVec3::new(0.0, -100.5, -1.0), 100.0)) as Box<dyn Hittable>);
-    let sphere2 = Rc::new(Box::new(Sphere::new(glam::DVec3::new(0.0,    0.0, -1.0), 0.5)) as Box<dyn Hittable>);
-    let sphere3 = Rc::new(Box::new(Sphere::new(glam::DVec3::new(-1.0,   0.0, -1.0), 0.5)) as Box<dyn Hittable>);
-    let sphere4 = Rc::new(Box::new(Sphere::new(glam::DVec3::new(1.0,    0.0, -1.0), 0.5)) as Box<dyn Hittable>);
+    let sphere1 = Arc::new(Sphere::new(glam::DVec3::new(0.0, -100.5, -1.0), 100.0)) as Arc<dyn Hittable>;
+    let sphere2 = Arc::new(Sphere::new(glam::DVec3::new(0.0,    0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
+    let sphere3 = Arc::new(Sphere::new(glam::DVec3::new(-1.0,   0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
+    let sphere4 = Arc::new(Sphere::new(glam::DVec3::new(1.0,    0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
     let object1 = Object::new(sphere1, material_ground);
     let object2 = Object::new(sphere2, material_center);
     let object3 = Object::new(sphere3, material_left);
@@ -1,7 +1,7 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use glam::{ DVec3};
-use rayrs::{renderer::Renderer, camera::{PerspectiveCamera}, integrator::TestIntegrator, sampler::{self, RandomSampler, Sampler}, scene::Scene, material::{Lambertian, Material, Metal, Dielectric}, hittable::{Sphere, Hittable}, object::Object};
+use rayrs::{renderer::Renderer, camera::{PerspectiveCamera}, integrator::TestIntegrator, sampler::{RandomSampler, Sampler}, scene::Scene, material::{Lambertian, Material, Metal, Dielectric}, hittable::{Sphere, Hittable}, object::Object};
 
 fn main() {
     let width = 400;
@@ -21,8 +21,8 @@ fn main() {
     let mut sampler = RandomSampler::new();
     let mut scene = Scene::new();
 
-    let ground_material = Rc::new(Box::new(Lambertian::new(glam::DVec3::new(0.5, 0.5, 0.5))) as Box<dyn Material>);
-    scene.add(Object::new(Rc::new(Box::new(Sphere::new(glam::DVec3::new(0.0, -1000.0, 0.0), 1000.0)) as Box<dyn Hittable>), ground_material));
+    let ground_material = Arc::new(Lambertian::new(glam::DVec3::new(0.5, 0.5, 0.5))) as Arc<dyn Material>;
+    scene.add(Object::new(Arc::new(Sphere::new(glam::DVec3::new(0.0, -1000.0, 0.0), 1000.0)) as Arc<dyn Hittable>, ground_material));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -30,32 +30,32 @@ fn main() {
             let center = glam::DVec3::new(a as f64 + 0.9 * sampler.get_1d(), 0.2, b as f64 + 0.9 * sampler.get_1d());
 
             if (center - DVec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let sphere_material: Rc<Box<dyn Material>>;
+                let sphere_material: Arc<dyn Material>;
                 if choose_mat < 0.8 {
                     let albedo = DVec3::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d()) * DVec3::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d());
-                    sphere_material = Rc::new(Box::new(Lambertian::new(albedo)) as Box<dyn Material>);
-                    scene.add(Object::new(Rc::new(Box::new(Sphere::new(center, 0.2)) as Box<dyn Hittable>), sphere_material));
+                    sphere_material = Arc::new(Lambertian::new(albedo)) as Arc<dyn Material>;
+                    scene.add(Object::new(Arc::new(Sphere::new(center, 0.2)) as Arc<dyn Hittable>, sphere_material));
                 } else if choose_mat < 0.95 {
                     let albedo = DVec3::new(1.0, 1.0, 1.0) * sampler.get_1d();
                     let fuzz = sampler.get_1d() * 0.5;
-                    sphere_material = Rc::new(Box::new(Metal::new(albedo, fuzz)) as Box<dyn Material>);
-                    scene.add(Object::new(Rc::new(Box::new(Sphere::new(center, 0.2)) as Box<dyn Hittable>), sphere_material));
+                    sphere_material = Arc::new(Metal::new(albedo, fuzz)) as Arc<dyn Material>;
+                    scene.add(Object::new(Arc::new(Sphere::new(center, 0.2)) as Arc<dyn Hittable>, sphere_material));
                 } else {
-                    sphere_material = Rc::new(Box::new(Dielectric::new(1.5)) as Box<dyn Material>);
-                    scene.add(Object::new(Rc::new(Box::new(Sphere::new(center, 0.2)) as Box<dyn Hittable>), sphere_material));
+                    sphere_material = Arc::new(Dielectric::new(1.5)) as Arc<dyn Material>;
+                    scene.add(Object::new(Arc::new(Sphere::new(center, 0.2)) as Arc<dyn Hittable>, sphere_material));
                 }
             }
         }
     }
 
-    let material1 = Rc::new(Box::new(Dielectric::new(1.5)) as Box<dyn Material>);
-    scene.add(Object::new(Rc::new(Box::new(Sphere::new(glam::DVec3::new(0.0, 1.0, 0.0), 1.0)) as Box<dyn Hittable>), material1));
+    let material1 = Arc::new(Dielectric::new(1.5)) as Arc<dyn Material>;
+    scene.add(Object::new(Arc::new(Sphere::new(glam::DVec3::new(0.0, 1.0, 0.0), 1.0)) as Arc<dyn Hittable>, material1));
 
-    let material2 = Rc::new(Box::new(Lambertian::new(glam::DVec3::new(0.4, 0.2, 0.1))) as Box<dyn Material>);
-    scene.add(Object::new(Rc::new(Box::new(Sphere::new(glam::DVec3::new(-4.0, 1.0, 0.0), 1.0)) as Box<dyn Hittable>), material2));
+    let material2 = Arc::new(Lambertian::new(glam::DVec3::new(0.4, 0.2, 0.1))) as Arc<dyn Material>;
+    scene.add(Object::new(Arc::new(Sphere::new(glam::DVec3::new(-4.0, 1.0, 0.0), 1.0)) as Arc<dyn Hittable>, material2));
 
-    let material3 = Rc::new(Box::new(Metal::new(glam::DVec3::new(0.7, 0.6, 0.5), 0.0)) as Box<dyn Material>);
-    scene.add(Object::new(Rc::new(Box::new(Sphere::new(glam::DVec3::new(4.0, 1.0, 0.0), 1.0)) as Box<dyn Hittable>), material3));
+    let material3 = Arc::new(Metal::new(glam::DVec3::new(0.7, 0.6, 0.5), 0.0)) as Arc<dyn Material>;
+    scene.add(Object::new(Arc::new(Sphere::new(glam::DVec3::new(4.0, 1.0, 0.0), 1.0)) as Arc<dyn Hittable>, material3));
 
     renderer.render(&camera, &scene, &integrator);
 
@@ -1,3 +1,51 @@
+use std::sync::Arc;
+
+use rayrs::{renderer::Renderer, camera::PerspectiveCamera, registry::integrator_from_name, scene::Scene, material::{Lambertian, Material, Metal, Dielectric}, hittable::{Sphere, Hittable}, object::Object};
+
+/// The scene of `first` rendered with an integrator picked on the command line,
+/// e.g. `cargo run --release --example third -- ao:0.5:32` or `-- normal`.
 fn main() {
-    
-}
\ No newline at end of file
+    let name = std::env::args().nth(1).unwrap_or_else(|| "path".to_string());
+    let integrator = match integrator_from_name(&name) {
+        Ok(integrator) => integrator,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let width = 400;
+    let height = 300;
+    let samples = 100;
+    let depth = 10;
+    let mut renderer = Renderer::new(width, height, samples, depth);
+    let camera = PerspectiveCamera::new(
+        glam::DVec3::new(0.0, 0.0, 2.0),
+        glam::DVec3::new(0.0, 0.0, -1.0),
+        glam::DVec3::new(0.0, 1.0, 0.0),
+        90.0,
+        width as f64 / height as f64,
+    );
+
+    let mut scene = Scene::new();
+
+    let material_ground = Arc::new(Lambertian::new(glam::DVec3::new(0.8, 0.8, 0.0))) as Arc<dyn Material>;
+    let material_center = Arc::new(Lambertian::new(glam::DVec3::new(0.1, 0.2, 0.5))) as Arc<dyn Material>;
+    let material_left = Arc::new(Dielectric::new(1.5)) as Arc<dyn Material>;
+    let material_right = Arc::new(Metal::new(glam::DVec3::new(0.8, 0.6, 0.2), 0.0)) as Arc<dyn Material>;
+
+    let sphere1 = Arc::new(Sphere::new(glam::DVec3::new(0.0, -100.5, -1.0), 100.0)) as Arc<dyn Hittable>;
+    let sphere2 = Arc::new(Sphere::new(glam::DVec3::new(0.0,    0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
+    let sphere3 = Arc::new(Sphere::new(glam::DVec3::new(-1.0,   0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
+    let sphere4 = Arc::new(Sphere::new(glam::DVec3::new(1.0,    0.0, -1.0), 0.5)) as Arc<dyn Hittable>;
+    scene.add(Object::new(sphere1, material_ground));
+    scene.add(Object::new(sphere2, material_center));
+    scene.add(Object::new(sphere3, material_left));
+    scene.add(Object::new(sphere4, material_right));
+
+    renderer.render(&camera, &scene, integrator.as_ref());
+
+    // settings like "ao:2.5:64" don't belong in a file name
+    let file = name.replace([':', '.'], "_");
+    renderer.save(&format!("third_{file}.png"));
+}
@@ -1,7 +1,9 @@
-use crate::{ray::Ray, hittable::{HitRecord, Hittable}, bbox::BBox, object::{Object, self}, scene::Scene};
+use crate::{ray::Ray, hittable::{HitRecord, Hittable}, bbox::BBox, object::Object};
 
+/// Acceleration structure over the objects of a scene. Its `hit` returns the
+/// closest hit already completed by `HitRecord::finalize`.
 pub trait Accel : Hittable {
-    fn build(&mut self, objects: &Vec<Object>);
+    fn build(&mut self, objects: &[Object]);
 }
 
 const MAX_PRIMITIVES: usize = 4;
@@ -17,28 +19,20 @@ pub struct BVHNode {
 pub struct BVH<'scene> {
     root: usize,
     nodes: Vec<BVHNode>,
-    objects: &'scene Vec<Object>,
+    objects: &'scene [Object],
 }
 
 impl <'scene> BVH<'scene> {
-    pub fn new(objects: &Vec<Object>) -> BVH {
+    pub fn new(objects: &'scene [Object]) -> BVH<'scene> {
         let mut bvh = BVH { root: 0, nodes: Vec::new(), objects };
         bvh.build(objects);
         bvh
     }
 
-    pub fn build(&mut self, objects: &Vec<Object>) {
-        let mut indexes = Vec::new();
-        for i in 0..objects.len() {
-            indexes.push(i);
-        }
-        self.root = self.build_from(&objects, indexes);
-    }
-
-    pub fn build_from(&mut self, objects: &Vec<Object>, indexes: Vec<usize>) -> usize {
+    pub fn build_from(&mut self, objects: &[Object], indexes: Vec<usize>) -> usize {
         let mut indexes = indexes;
         let mut bbox = BBox::default();
-        for i in 0..indexes.len() {
+        for &i in &indexes {
             bbox = bbox.union(&objects[i].bbox());
         }
         if indexes.len() <= MAX_PRIMITIVES {
@@ -54,19 +48,19 @@ impl <'scene> BVH<'scene> {
             a_center.partial_cmp(&b_center).unwrap()
         });
        
-        let left = self.build_from(&objects, indexes[0..mid].to_vec());
-        let right = self.build_from(&objects, indexes[mid..indexes.len()].to_vec());
+        let left = self.build_from(objects, indexes[0..mid].to_vec());
+        let right = self.build_from(objects, indexes[mid..indexes.len()].to_vec());
         let node = BVHNode { left: Some(left), right: Some(right), bbox, primitive: Vec::new() };
         self.nodes.push(node);
         self.nodes.len() - 1
     }
 
-    fn hit_node(&self, node: usize, ray: &Ray) -> Option<HitRecord> {
+    fn hit_node(&self, node: usize, ray: &Ray) -> Option<HitRecord<'_>> {
         if !self.nodes[node].bbox.hit(ray) {
             return None
         } 
         let mut hit = None;
-        let mut closest = std::f64::MAX;
+        let mut closest = f64::MAX;
         if self.nodes[node].left.is_none() && self.nodes[node].right.is_none() {
             for i in 0..self.nodes[node].primitive.len() {
                 let object = &self.objects[self.nodes[node].primitive[i]];
@@ -99,25 +93,21 @@ impl <'scene> BVH<'scene> {
 
 
 impl<'scene> Accel for BVH<'scene> {
-    fn build(&mut self, objects: &Vec<Object>) {
-        let mut indexes = Vec::new();
-        for i in 0..objects.len() {
-            indexes.push(i);
-        }
-        let mut bvh = BVH::new(objects);
-        bvh.build_from(&objects, indexes);
-    }    
+    fn build(&mut self, objects: &[Object]) {
+        self.nodes.clear();
+        let indexes = (0..objects.len()).collect();
+        self.root = self.build_from(objects, indexes);
+    }
 }
 
 impl<'scene> Hittable for BVH<'scene> {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
-        self.hit_node(self.root, ray)
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let mut hit = self.hit_node(self.root, ray)?;
+        hit.finalize(ray);
+        Some(hit)
     }
 
     fn bbox(&self) -> BBox {
         self.nodes[self.root].bbox
     }
 }
-    
-unsafe impl<'scene> Send for BVH<'scene> {}
-unsafe impl<'scene> Sync for BVH<'scene> {}
\ No newline at end of file
@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use glam::DVec3;
+
+use crate::{
+    accel::Accel,
+    integrator::Integrator,
+    ray::Ray,
+    sampler::Sampler,
+    sampling::sample_hemisphere_cosine,
+    scene::Scene,
+};
+
+/// Ambient occlusion: the cosine weighted fraction of the hemisphere above the
+/// first hit that is not blocked within `distance`. Escaping rays are black.
+pub struct AmbientOcclusionIntegrator {
+    pub distance: f64,
+    /// occlusion rays per camera sample
+    pub samples: usize,
+}
+
+impl Default for AmbientOcclusionIntegrator {
+    fn default() -> Self {
+        Self::new(1.0, 16)
+    }
+}
+
+impl AmbientOcclusionIntegrator {
+    pub fn new(distance: f64, samples: usize) -> AmbientOcclusionIntegrator {
+        AmbientOcclusionIntegrator { distance, samples }
+    }
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, ray: &Ray, _scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, _depth: i32) -> DVec3 {
+        let Some(hit) = accel.hit(ray) else {
+            return DVec3::ZERO;
+        };
+        let (tangent, bitangent, mut normal) = hit.tangent_frame();
+        if normal.dot(ray.direction) > 0.0 {
+            normal = -normal;
+        }
+        let samples = self.samples.max(1);
+        let unoccluded = (0..samples)
+            .filter(|_| {
+                let (x, y) = sampler.get_2d();
+                let local = sample_hemisphere_cosine(x, y);
+                let wi = tangent * local.x + bitangent * local.y + normal * local.z;
+                accel.hit(&hit.spawn_shadow_ray(wi, self.distance)).is_none()
+            })
+            .count();
+        DVec3::splat(unoccluded as f64 / samples as f64)
+    }
+}
+
+/// Quantities an `AovIntegrator` shows for the first surface along each camera
+/// ray, mapped into [0, 1] so they can be saved as an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// shading normal, from [-1, 1] to [0, 1] per component
+    ShadingNormal,
+    GeometricNormal,
+    /// distance along the ray divided by the integrator's `far`
+    Depth,
+    /// surface coordinates in the red and green channels
+    Uv,
+    /// reflectance of the material towards the viewer, estimated from one BSDF
+    /// sample per camera sample
+    Albedo,
+    /// a color per object
+    ObjectId,
+    /// a color per distinct material
+    MaterialId,
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Aov, String> {
+        match name {
+            "normal" | "shading_normal" => Ok(Aov::ShadingNormal),
+            "geometric_normal" => Ok(Aov::GeometricNormal),
+            "depth" => Ok(Aov::Depth),
+            "uv" => Ok(Aov::Uv),
+            "albedo" => Ok(Aov::Albedo),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            _ => Err(format!("unknown aov `{name}`")),
+        }
+    }
+}
+
+/// Debug integrator writing one `Aov` of the first hit instead of radiance.
+/// Escaping rays are black.
+pub struct AovIntegrator {
+    pub aov: Aov,
+    /// distance shown as white by `Aov::Depth`
+    pub far: f64,
+}
+
+impl AovIntegrator {
+    pub fn new(aov: Aov) -> AovIntegrator {
+        AovIntegrator { aov, far: 100.0 }
+    }
+}
+
+/// well spread color for an id, so that neighbouring ids are easy to tell apart
+fn id_color(id: usize) -> DVec3 {
+    let mut x = (id as u64).wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    DVec3::new((x & 0xff) as f64, ((x >> 8) & 0xff) as f64, ((x >> 16) & 0xff) as f64) / 255.0
+}
+
+impl Integrator for AovIntegrator {
+    fn li(&self, ray: &Ray, _scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, _depth: i32) -> DVec3 {
+        let Some(hit) = accel.hit(ray) else {
+            return DVec3::ZERO;
+        };
+        match self.aov {
+            Aov::ShadingNormal => hit.shading_normal * 0.5 + 0.5,
+            Aov::GeometricNormal => hit.normal * 0.5 + 0.5,
+            Aov::Depth => DVec3::splat(hit.t * ray.direction.length() / self.far),
+            Aov::Uv => hit.uv.extend(0.0),
+            Aov::Albedo => {
+                let Some(object) = hit.object else {
+                    return DVec3::ZERO;
+                };
+                match object.material.sample(-ray.direction, &hit, sampler) {
+                    Some(sample) if sample.pdf > 0.0 => sample.weight(hit.shading_normal),
+                    _ => DVec3::ZERO,
+                }
+            }
+            Aov::ObjectId => id_color(hit.instance_id),
+            Aov::MaterialId => hit.object.map_or(DVec3::ZERO, |object| id_color(object.material_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        accel::BVH,
+        hittable::Hittable,
+        material::{Lambertian, Material},
+        object::Object,
+        sampler::RandomSampler,
+        test_scenes::square,
+    };
+
+    fn quad(y: f64) -> Arc<dyn Hittable> {
+        square(DVec3::new(0.0, y, 0.0), 20.0, true)
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let mut scene = Scene::new();
+        scene.add(Object::new(quad(0.0), Arc::new(Lambertian::new(DVec3::ONE))));
+        let ray = Ray::new(DVec3::new(0.0, 0.2, 0.0), -DVec3::Y);
+        let mut sampler = RandomSampler::seeded(3);
+
+        let accel = BVH::new(&scene.objects);
+        let open = AmbientOcclusionIntegrator::new(1.0, 64).li(&ray, &scene, &accel, &mut sampler, 1);
+        assert_eq!(open, DVec3::ONE);
+
+        // a ceiling right above the camera blocks everything within reach
+        scene.add(Object::new(quad(0.5), Arc::new(Lambertian::new(DVec3::ONE))));
+        let accel = BVH::new(&scene.objects);
+        let covered = AmbientOcclusionIntegrator::new(100.0, 64).li(&ray, &scene, &accel, &mut sampler, 1);
+        assert!(covered.x < 0.05, "{covered}");
+        let short = AmbientOcclusionIntegrator::new(0.4, 64).li(&ray, &scene, &accel, &mut sampler, 1);
+        assert_eq!(short, DVec3::ONE);
+    }
+
+    #[test]
+    fn test_aovs() {
+        let mut scene = Scene::new();
+        let grey = Arc::new(Lambertian::new(DVec3::splat(0.5))) as Arc<dyn Material>;
+        scene.add(Object::new(quad(0.0), grey.clone()));
+        scene.add(Object::new(quad(-1.0), Arc::new(Lambertian::new(DVec3::ONE))));
+        scene.add(Object::new(quad(-2.0), grey));
+        assert_eq!(scene.objects.iter().map(|object| object.material_id).collect::<Vec<_>>(), [0, 1, 0]);
+
+        let accel = BVH::new(&scene.objects);
+        let ray = Ray::new(DVec3::new(0.0, 2.0, 0.0), -DVec3::Y);
+        let mut sampler = RandomSampler::seeded(5);
+        let mut aov = |name: &str| {
+            let mut integrator = AovIntegrator::new(name.parse().unwrap());
+            integrator.far = 4.0;
+            integrator.li(&ray, &scene, &accel, &mut sampler, 1)
+        };
+        assert_eq!(aov("geometric_normal"), DVec3::new(0.5, 1.0, 0.5));
+        assert_eq!(aov("depth"), DVec3::splat(0.5));
+        assert!((aov("albedo") - DVec3::splat(0.5)).abs().max_element() < 1e-9);
+        assert_eq!(aov("object_id"), id_color(0));
+        assert_eq!(aov("material_id"), id_color(0));
+        assert_ne!(id_color(0), id_color(1));
+        assert!("beauty".parse::<Aov>().is_err());
+    }
+}
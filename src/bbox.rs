@@ -59,6 +59,14 @@ impl BBox {
         (self.min + self.max) * 0.5
     }
 
+    /// (center, radius) of a sphere around the box, the unit sphere for an empty box
+    pub fn bounding_sphere(&self) -> (DVec3, f64) {
+        if self.min.cmpgt(self.max).any() {
+            return (DVec3::ZERO, 1.0);
+        }
+        (self.center(), (0.5 * self.diagonal().length()).max(1e-6))
+    }
+
     pub fn diagonal(&self) -> DVec3 {
         self.max - self.min
     }
@@ -85,6 +93,12 @@ impl BBox {
     }
 
     pub fn hit(&self, ray: &Ray) -> bool {
+        self.ray_interval(ray).is_some()
+    }
+
+    /// parameter range over which `ray` is inside the box, clipped to the ray's
+    /// own range
+    pub fn ray_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
         let mut t0 = ray.min_t;
         let mut t1 = ray.max_t;
         for a in 0..3 {
@@ -95,15 +109,15 @@ impl BBox {
                 std::mem::swap(&mut t_near, &mut t_far);
             }
             if t0 > t_far || t_near > t1 {
-                return false;
+                return None;
             }
             t0 = t_near.max(t0);
             t1 = t_far.min(t1);
             if t0 > t1 {
-                return false;
+                return None;
             }
         }
-        true
+        Some((t0, t1))
     }
 
 }
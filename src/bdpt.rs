@@ -0,0 +1,784 @@
+use std::f64::consts::PI;
+
+use glam::{DVec2, DVec3};
+
+use crate::{
+    accel::Accel,
+    bbox::BBox,
+    camera::Camera,
+    film::Film,
+    hittable::HitRecord,
+    integrator::{Integrator, RenderContext},
+    light::{LeSample, Light},
+    material::Material,
+    ray::Ray,
+    sampler::Sampler,
+    scene::Scene,
+};
+
+/// Bidirectional path tracer. Every camera sample traces one subpath from the
+/// camera and one from a uniformly picked light, and connects every prefix of
+/// one to every prefix of the other. The strategies are weighted with the
+/// balance heuristic. Connections of light subpaths straight to the camera
+/// (light tracing) land on other pixels and are splatted onto the film, so
+/// they are only used when rendering through `li_camera`.
+pub struct BdptIntegrator {}
+
+impl Default for BdptIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BdptIntegrator {
+    pub fn new() -> BdptIntegrator {
+        BdptIntegrator {}
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TransportMode {
+    /// traced from the camera, carrying radiance
+    Radiance,
+    /// traced from a light, carrying importance
+    Importance,
+}
+
+#[derive(Clone)]
+enum VertexKind<'a> {
+    Camera,
+    /// point on a light, the start of a light subpath or sampled for a connection
+    Light(&'a dyn Light),
+    /// end of a camera subpath that left the scene, lit by the lights at infinity
+    Infinite,
+    Surface(Box<HitRecord<'a>>),
+}
+
+#[derive(Clone)]
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    p: DVec3,
+    /// geometric and shading normals, zero for points that aren't on a surface
+    ng: DVec3,
+    ns: DVec3,
+    /// direction towards the previous vertex of the subpath
+    wo: DVec3,
+    /// throughput of the subpath up to and including this vertex
+    beta: DVec3,
+    /// densities per unit area of sampling this vertex from the previous one,
+    /// and of sampling it in reverse from the next one
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    /// scattered by a specular lobe, can't be connected to
+    delta: bool,
+    mode: TransportMode,
+}
+
+/// What the strategies need besides the subpaths themselves.
+pub(crate) struct Context<'a> {
+    scene: &'a Scene,
+    accel: &'a dyn Accel,
+    /// `None` when rendering through `li`, light tracing is disabled then
+    camera: Option<&'a dyn Camera>,
+    bounds: BBox,
+    /// probability of picking any single light
+    light_pdf: f64,
+    /// moment both subpaths are traced at, the time of the camera ray
+    time: f64,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(scene: &'a Scene, accel: &'a dyn Accel, camera: Option<&'a dyn Camera>, time: f64) -> Context<'a> {
+        Context {
+            scene,
+            accel,
+            camera,
+            bounds: accel.bbox(),
+            light_pdf: 1.0 / scene.lights.len().max(1) as f64,
+            time,
+        }
+    }
+
+    /// density of a light subpath leaving the lights at infinity in direction `w`
+    fn infinite_light_density(&self, w: DVec3) -> f64 {
+        self.scene.lights.iter()
+            .filter(|light| light.is_infinite())
+            .map(|light| light.pdf_li(DVec3::ZERO, -w))
+            .sum::<f64>() * self.light_pdf
+    }
+
+    /// whether nothing blocks the segment between `a` and `b`
+    fn unoccluded(&self, a: &Vertex, b: &Vertex) -> bool {
+        // trace from a surface when there is one, its origin needs the offset
+        let (from, to) = if matches!(a.kind, VertexKind::Surface(_)) { (a, b) } else { (b, a) };
+        let d = to.p - from.p;
+        let distance = d.length();
+        if distance == 0.0 {
+            return false;
+        }
+        let w = d / distance;
+        let ray = match &from.kind {
+            VertexKind::Surface(hit) => hit.spawn_shadow_ray(w, distance),
+            _ => {
+                let mut ray = Ray::new(from.p, w).with_time(self.time);
+                ray.max_t = distance * (1.0 - 1e-4);
+                ray
+            }
+        };
+        self.accel.hit(&ray).is_none()
+    }
+
+    /// geometry term between two vertices, including their visibility
+    fn g(&self, a: &Vertex, b: &Vertex) -> f64 {
+        let d = a.p - b.p;
+        let distance_squared = d.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let w = d / distance_squared.sqrt();
+        let mut g = 1.0 / distance_squared;
+        if a.is_on_surface() {
+            g *= a.ns.dot(w).abs();
+        }
+        if b.is_on_surface() {
+            g *= b.ns.dot(w).abs();
+        }
+        if g == 0.0 || !self.unoccluded(a, b) {
+            return 0.0;
+        }
+        g
+    }
+}
+
+/// Scattering of importance doesn't match the adjoint of the BSDF: refraction
+/// compresses radiance by 1 / eta^2 but not importance, and shading normals
+/// make the BSDF asymmetric.
+pub(crate) fn importance_correction(hit: &HitRecord, material: &dyn Material, wo: DVec3, wi: DVec3) -> f64 {
+    let denominator = wo.dot(hit.normal).abs() * wi.dot(hit.shading_normal).abs();
+    if denominator == 0.0 {
+        return 0.0;
+    }
+    let mut correction = wo.dot(hit.shading_normal).abs() * wi.dot(hit.normal).abs() / denominator;
+    let cos_o = wo.dot(hit.shading_normal);
+    if cos_o * wi.dot(hit.shading_normal) < 0.0 {
+        let eta = if cos_o > 0.0 { material.eta() } else { 1.0 / material.eta() };
+        correction *= eta * eta;
+    }
+    correction
+}
+
+/// Ray leaving a light, the start of every path traced from the lights.
+pub(crate) struct Emission<'a> {
+    pub light: &'a dyn Light,
+    /// probability of having picked `light`
+    pub pick_pdf: f64,
+    pub sample: LeSample,
+    /// |cos| between the ray and the light's surface, one for lights without one
+    pub cosine: f64,
+    /// throughput the path starts with, le * cos over the density of the ray
+    pub beta: DVec3,
+}
+
+impl Emission<'_> {
+    /// density of the ray, per unit area and solid angle, including the pick
+    pub(crate) fn pdf(&self) -> f64 {
+        self.pick_pdf * self.sample.pdf_pos * self.sample.pdf_dir
+    }
+}
+
+/// picks a light and samples a ray leaving it, `None` if it carries nothing.
+/// The ray has no time yet, that is up to the caller.
+pub(crate) fn sample_emission<'a>(scene: &'a Scene, bounds: &BBox, sampler: &mut dyn Sampler) -> Option<Emission<'a>> {
+    let (light, pick_pdf) = scene.pick_light(sampler.get_1d())?;
+    let (u1, u2) = (sampler.get_2d(), sampler.get_2d());
+    let sample = light.sample_le(bounds, u1, u2)?;
+    if sample.pdf_pos <= 0.0 || sample.pdf_dir <= 0.0 || sample.le == DVec3::ZERO {
+        return None;
+    }
+    let cosine = if sample.normal == DVec3::ZERO { 1.0 } else { sample.normal.dot(sample.ray.direction.normalize()).abs() };
+    let beta = sample.le * cosine / (pick_pdf * sample.pdf_pos * sample.pdf_dir);
+    Some(Emission { light: light.as_ref(), pick_pdf, sample, cosine, beta })
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: VertexKind<'a>, p: DVec3, beta: DVec3, pdf_fwd: f64, mode: TransportMode) -> Vertex<'a> {
+        Vertex {
+            kind,
+            p,
+            ng: DVec3::ZERO,
+            ns: DVec3::ZERO,
+            wo: DVec3::ZERO,
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            delta: false,
+            mode,
+        }
+    }
+
+    fn camera(p: DVec3, beta: DVec3) -> Vertex<'a> {
+        Vertex::new(VertexKind::Camera, p, beta, 0.0, TransportMode::Radiance)
+    }
+
+    fn light(light: &'a dyn Light, p: DVec3, normal: DVec3, beta: DVec3, pdf_fwd: f64) -> Vertex<'a> {
+        let mut vertex = Vertex::new(VertexKind::Light(light), p, beta, pdf_fwd, TransportMode::Importance);
+        vertex.ng = normal;
+        vertex.ns = normal;
+        vertex
+    }
+
+    fn infinite(ray: &Ray, beta: DVec3, pdf_fwd: f64) -> Vertex<'a> {
+        Vertex::new(VertexKind::Infinite, ray.origin + ray.direction.normalize(), beta, pdf_fwd, TransportMode::Radiance)
+    }
+
+    fn surface(hit: HitRecord<'a>, wo: DVec3, beta: DVec3, mode: TransportMode) -> Vertex<'a> {
+        let (p, ng, ns) = (hit.p, hit.normal, hit.shading_normal);
+        let mut vertex = Vertex::new(VertexKind::Surface(Box::new(hit)), p, beta, 0.0, mode);
+        vertex.ng = ng;
+        vertex.ns = ns;
+        vertex.wo = wo;
+        vertex
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.ng != DVec3::ZERO
+    }
+
+    fn material(&self) -> Option<&'a dyn Material> {
+        match &self.kind {
+            VertexKind::Surface(hit) => hit.object.map(|object| object.material.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// the light emitting from this vertex, if any
+    fn emitter(&self) -> Option<&'a dyn Light> {
+        match &self.kind {
+            VertexKind::Light(light) => Some(*light),
+            VertexKind::Surface(hit) => hit.object.and_then(|object| object.area_light.as_deref()).map(|light| light as &dyn Light),
+            _ => None,
+        }
+    }
+
+    fn is_light(&self) -> bool {
+        matches!(self.kind, VertexKind::Infinite) || self.emitter().is_some()
+    }
+
+    fn is_delta_light(&self) -> bool {
+        matches!(self.kind, VertexKind::Light(light) if light.is_delta())
+    }
+
+    fn is_infinite_light(&self) -> bool {
+        match self.kind {
+            VertexKind::Infinite => true,
+            VertexKind::Light(light) => light.is_infinite(),
+            _ => false,
+        }
+    }
+
+    /// whether a connection can end at this vertex, which rules out delta
+    /// directions: specular surfaces and directional lights
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera | VertexKind::Infinite => true,
+            VertexKind::Light(light) => !(light.is_delta() && light.is_infinite()),
+            VertexKind::Surface(ref hit) => self.material().is_some_and(|material| material.flags(hit).is_non_specular()),
+        }
+    }
+
+    /// BSDF scattering from the previous vertex towards `next`
+    fn f(&self, next: &Vertex) -> DVec3 {
+        let (VertexKind::Surface(hit), Some(material)) = (&self.kind, self.material()) else {
+            return DVec3::ZERO;
+        };
+        let wi = (next.p - self.p).normalize();
+        let f = material.eval(self.wo, wi, hit);
+        match self.mode {
+            TransportMode::Radiance => f,
+            TransportMode::Importance => f * importance_correction(hit, material, self.wo, wi),
+        }
+    }
+
+    /// converts a solid angle density at this vertex into an area density at `next`
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if next.is_infinite_light() {
+            return pdf;
+        }
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.is_on_surface() {
+            pdf *= next.ng.dot(w / distance_squared.sqrt()).abs();
+        }
+        pdf
+    }
+
+    /// area density of sampling `next` from this vertex, having arrived from `prev`
+    fn pdf(&self, ctx: &Context, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        if matches!(self.kind, VertexKind::Light(_) | VertexKind::Infinite) {
+            return self.pdf_light(ctx, next);
+        }
+        let wn = next.p - self.p;
+        if wn.length_squared() == 0.0 {
+            return 0.0;
+        }
+        let wn = wn.normalize();
+        let pdf = match &self.kind {
+            VertexKind::Camera => ctx.camera.map_or(0.0, |camera| camera.pdf_we(&Ray::new(self.p, wn)).1),
+            VertexKind::Surface(hit) => {
+                let (Some(prev), Some(material)) = (prev, self.material()) else {
+                    return 0.0;
+                };
+                let wp = (prev.p - self.p).normalize();
+                material.pdf(wp, wn, hit)
+            }
+            _ => 0.0,
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// area density of a light subpath starting at this vertex reaching `next`
+    fn pdf_light(&self, ctx: &Context, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let w = w / distance_squared.sqrt();
+        let mut pdf = if self.is_infinite_light() {
+            // rays leave the lights at infinity from a disk as wide as the scene
+            let (_, radius) = ctx.bounds.bounding_sphere();
+            1.0 / (PI * radius * radius)
+        } else {
+            let Some(light) = self.emitter() else {
+                return 0.0;
+            };
+            let (_, pdf_dir) = light.pdf_le(&ctx.bounds, &Ray::new(self.p, w), self.ng);
+            pdf_dir / distance_squared
+        };
+        if next.is_on_surface() {
+            pdf *= next.ng.dot(w).abs();
+        }
+        pdf
+    }
+
+    /// area density of the light sampling picking this vertex as the start of
+    /// a light subpath heading to `next`
+    fn pdf_light_origin(&self, ctx: &Context, next: &Vertex) -> f64 {
+        let w = (next.p - self.p).normalize();
+        if self.is_infinite_light() {
+            return ctx.infinite_light_density(w);
+        }
+        let Some(light) = self.emitter() else {
+            return 0.0;
+        };
+        let (pdf_pos, _) = light.pdf_le(&ctx.bounds, &Ray::new(self.p, w), self.ng);
+        ctx.light_pdf * pdf_pos
+    }
+
+    /// radiance this vertex emits towards `v`
+    fn le(&self, ctx: &Context, v: &Vertex) -> DVec3 {
+        let w = (v.p - self.p).normalize();
+        match &self.kind {
+            VertexKind::Infinite => ctx.scene.lights.iter().map(|light| light.le(&Ray::new(v.p, -w))).sum(),
+            VertexKind::Surface(hit) => hit.object.map_or(DVec3::ZERO, |object| object.emitted(hit, w)),
+            _ => DVec3::ZERO,
+        }
+    }
+}
+
+/// extends `path` by up to `max_depth` vertices, following BSDF samples from `ray`
+#[allow(clippy::too_many_arguments)]
+fn random_walk<'a>(
+    ctx: &Context<'a>,
+    ray: &Ray,
+    beta: DVec3,
+    pdf: f64,
+    max_depth: usize,
+    mode: TransportMode,
+    path: &mut Vec<Vertex<'a>>,
+    sampler: &mut dyn Sampler,
+) {
+    if max_depth == 0 {
+        return;
+    }
+    let mut ray = ray.clone();
+    let mut beta = beta;
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+    loop {
+        let Some(hit) = ctx.accel.hit(&ray) else {
+            // only radiance finds the lights at infinity
+            if mode == TransportMode::Radiance {
+                path.push(Vertex::infinite(&ray, beta, pdf_fwd));
+            }
+            break;
+        };
+        let Some(object) = hit.object else {
+            break;
+        };
+        let wo = -ray.direction.normalize();
+        let mut vertex = Vertex::surface(hit, wo, beta, mode);
+        vertex.pdf_fwd = path.last().map_or(0.0, |prev| prev.convert_density(pdf_fwd, &vertex));
+        path.push(vertex);
+        bounces += 1;
+        if bounces >= max_depth {
+            break;
+        }
+
+        let n = path.len();
+        let VertexKind::Surface(hit) = &path[n - 1].kind else {
+            break;
+        };
+        let material = object.material.as_ref();
+        let Some(sample) = material.sample(wo, hit, sampler) else {
+            break;
+        };
+        if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+            break;
+        }
+        beta *= sample.weight(hit.shading_normal);
+        pdf_fwd = sample.pdf;
+        let mut pdf_rev = material.pdf(sample.wi, wo, hit);
+        if mode == TransportMode::Importance {
+            beta *= importance_correction(hit, material, wo, sample.wi);
+        }
+        ray = hit.spawn_ray_with_differentials(&ray, &sample);
+        if sample.flags.is_specular() {
+            path[n - 1].delta = true;
+            pdf_fwd = 0.0;
+            pdf_rev = 0.0;
+        }
+        path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+        if beta == DVec3::ZERO {
+            break;
+        }
+    }
+}
+
+fn camera_subpath<'a>(ctx: &Context<'a>, ray: &Ray, sampler: &mut dyn Sampler, max_depth: usize) -> Vec<Vertex<'a>> {
+    let mut path = vec![Vertex::camera(ray.origin, DVec3::ONE)];
+    // without a camera the density only feeds the light tracing strategy, which is off
+    let pdf_dir = ctx.camera.map_or(1.0, |camera| camera.pdf_we(ray).1);
+    random_walk(ctx, ray, DVec3::ONE, pdf_dir, max_depth - 1, TransportMode::Radiance, &mut path, sampler);
+    path
+}
+
+/// light subpath at the time of the camera path it gets joined with
+fn light_subpath<'a>(ctx: &Context<'a>, sampler: &mut dyn Sampler, max_depth: usize) -> Vec<Vertex<'a>> {
+    let Some(Emission { light, sample, beta, .. }) = sample_emission(ctx.scene, &ctx.bounds, sampler) else {
+        return Vec::new();
+    };
+    let ray = sample.ray.with_time(ctx.time);
+    let direction = ray.direction.normalize();
+
+    let mut path = vec![Vertex::light(light, ray.origin, sample.normal, sample.le, sample.pdf_pos * ctx.light_pdf)];
+    random_walk(ctx, &ray, beta, sample.pdf_dir, max_depth - 1, TransportMode::Importance, &mut path, sampler);
+
+    // the disk rays leave the lights at infinity from is a stand in, the
+    // densities are per direction and per area of the disk
+    if light.is_infinite() {
+        if path.len() > 1 {
+            path[1].pdf_fwd = sample.pdf_pos;
+            if path[1].is_on_surface() {
+                path[1].pdf_fwd *= direction.dot(path[1].ng).abs();
+            }
+        }
+        path[0].pdf_fwd = ctx.infinite_light_density(direction);
+    }
+    path
+}
+
+/// balance heuristic weight of the strategy joining `s` light and `t` camera
+/// vertices, with `sampled` replacing the endpoint picked for the connection
+fn mis_weight(ctx: &Context, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    let mut light = light_path[..s].to_vec();
+    let mut camera = camera_path[..t].to_vec();
+    if let Some(sampled) = sampled {
+        if s == 1 {
+            light[0] = sampled.clone();
+        } else if t == 1 {
+            camera[0] = sampled.clone();
+        }
+    }
+
+    // densities of sampling the connection vertices in reverse
+    let pt_rev = match s {
+        0 => camera[t - 1].pdf_light_origin(ctx, &camera[t - 2]),
+        _ => light[s - 1].pdf(ctx, s.checked_sub(2).map(|i| &light[i]), &camera[t - 1]),
+    };
+    let pt_minus_rev = (t > 1).then(|| match s {
+        0 => camera[t - 1].pdf_light(ctx, &camera[t - 2]),
+        _ => camera[t - 1].pdf(ctx, Some(&light[s - 1]), &camera[t - 2]),
+    });
+    let qs_rev = (s > 0).then(|| camera[t - 1].pdf(ctx, t.checked_sub(2).map(|i| &camera[i]), &light[s - 1]));
+    let qs_minus_rev = (s > 1).then(|| light[s - 1].pdf(ctx, Some(&camera[t - 1]), &light[s - 2]));
+
+    camera[t - 1].pdf_rev = pt_rev;
+    camera[t - 1].delta = false;
+    if let Some(pdf) = pt_minus_rev {
+        camera[t - 2].pdf_rev = pdf;
+    }
+    if let Some(pdf) = qs_rev {
+        light[s - 1].pdf_rev = pdf;
+        light[s - 1].delta = false;
+    }
+    if let Some(pdf) = qs_minus_rev {
+        light[s - 2].pdf_rev = pdf;
+    }
+
+    // delta densities are zero and cancel in the ratios
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let light_tracing = ctx.camera.is_some();
+    let mut sum = 0.0;
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        ri *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta && (light_tracing || i > 1) {
+            sum += ri;
+        }
+    }
+    ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let delta_light = if i > 0 { light[i - 1].delta } else { light[0].is_delta_light() };
+        if !light[i].delta && !delta_light {
+            sum += ri;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// weighted contribution of the strategy joining `s` light and `t` camera
+/// vertices, with the image position it lands on for light tracing
+fn connect(ctx: &Context, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize, sampler: &mut dyn Sampler) -> (DVec3, Option<DVec2>) {
+    // lights at infinity only end camera subpaths
+    if t > 1 && s != 0 && matches!(camera_path[t - 1].kind, VertexKind::Infinite) {
+        return (DVec3::ZERO, None);
+    }
+    let mut sampled = None;
+    let mut uv = None;
+    let radiance = if s == 0 {
+        let pt = &camera_path[t - 1];
+        if !pt.is_light() {
+            return (DVec3::ZERO, None);
+        }
+        pt.le(ctx, &camera_path[t - 2]) * pt.beta
+    } else if t == 1 {
+        let qs = &light_path[s - 1];
+        let Some(camera) = ctx.camera else {
+            return (DVec3::ZERO, None);
+        };
+        if !qs.is_connectible() {
+            return (DVec3::ZERO, None);
+        }
+        let Some(sample) = camera.sample_wi(qs.p, sampler.get_2d()) else {
+            return (DVec3::ZERO, None);
+        };
+        if sample.pdf <= 0.0 || sample.importance <= 0.0 {
+            return (DVec3::ZERO, None);
+        }
+        let vertex = Vertex::camera(sample.position, DVec3::splat(sample.importance / sample.pdf));
+        let mut radiance = qs.beta * qs.f(&vertex) * vertex.beta;
+        if qs.is_on_surface() {
+            radiance *= sample.wi.dot(qs.ns).abs();
+        }
+        if radiance != DVec3::ZERO && !ctx.unoccluded(qs, &vertex) {
+            radiance = DVec3::ZERO;
+        }
+        uv = Some(sample.uv);
+        sampled = Some(vertex);
+        radiance
+    } else if s == 1 {
+        let pt = &camera_path[t - 1];
+        if !pt.is_connectible() {
+            return (DVec3::ZERO, None);
+        }
+        let Some((light, _)) = ctx.scene.pick_light(sampler.get_1d()) else {
+            return (DVec3::ZERO, None);
+        };
+        let light = light.as_ref();
+        let Some(sample) = light.sample_li(pt.p, sampler.get_2d()) else {
+            return (DVec3::ZERO, None);
+        };
+        if sample.pdf <= 0.0 || sample.li == DVec3::ZERO {
+            return (DVec3::ZERO, None);
+        }
+        let p = if sample.distance.is_finite() {
+            pt.p + sample.wi * sample.distance
+        } else {
+            // far enough outside the scene for the shadow ray to clear it
+            let (_, radius) = ctx.bounds.bounding_sphere();
+            pt.p + sample.wi * 2.0 * radius
+        };
+        let mut vertex = Vertex::light(light, p, sample.normal, sample.li / (sample.pdf * ctx.light_pdf), 0.0);
+        vertex.pdf_fwd = vertex.pdf_light_origin(ctx, pt);
+        let mut radiance = pt.beta * pt.f(&vertex) * vertex.beta;
+        if pt.is_on_surface() {
+            radiance *= sample.wi.dot(pt.ns).abs();
+        }
+        if radiance != DVec3::ZERO && !ctx.unoccluded(pt, &vertex) {
+            radiance = DVec3::ZERO;
+        }
+        sampled = Some(vertex);
+        radiance
+    } else {
+        let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+        if !qs.is_connectible() || !pt.is_connectible() {
+            return (DVec3::ZERO, None);
+        }
+        let radiance = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+        if radiance == DVec3::ZERO {
+            return (DVec3::ZERO, None);
+        }
+        radiance * ctx.g(qs, pt)
+    };
+    if radiance == DVec3::ZERO {
+        return (DVec3::ZERO, None);
+    }
+    let weight = mis_weight(ctx, light_path, camera_path, sampled.as_ref(), s, t);
+    (radiance * weight, uv)
+}
+
+/// A single strategy on its own, joining exactly `s` light and `t` camera
+/// vertices of subpaths started along `ray`, for Metropolis sampling which
+/// picks the strategies itself. The camera subpath, the light subpath and the
+/// connection draw from separate sampler streams. Returns the weighted
+/// contribution, and the image position for light tracing (`t == 1`).
+pub(crate) fn sample_strategy(ctx: &Context, ray: &Ray, s: usize, t: usize, sampler: &mut dyn Sampler) -> (DVec3, Option<DVec2>) {
+    let camera_path = camera_subpath(ctx, ray, sampler, t);
+    if camera_path.len() != t {
+        return (DVec3::ZERO, None);
+    }
+    sampler.start_stream(1);
+    let light_path = if s > 0 { light_subpath(ctx, sampler, s) } else { Vec::new() };
+    if light_path.len() != s {
+        return (DVec3::ZERO, None);
+    }
+    sampler.start_stream(2);
+    connect(ctx, &light_path, &camera_path, s, t, sampler)
+}
+
+impl BdptIntegrator {
+    fn trace(&self, ray: &Ray, ctx: &Context, film: Option<&Film>, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        if depth < 0 {
+            return DVec3::ZERO;
+        }
+        let depth = depth as usize;
+        let camera_path = camera_subpath(ctx, ray, sampler, depth + 2);
+        let light_path = light_subpath(ctx, sampler, depth + 1);
+
+        let mut radiance = DVec3::ZERO;
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > depth {
+                    continue;
+                }
+                if t == 1 && film.is_none() {
+                    continue;
+                }
+                let (value, uv) = connect(ctx, &light_path, &camera_path, s, t, sampler);
+                match (film, uv) {
+                    (Some(film), Some(uv)) if t == 1 => film.add_splat(uv.x, uv.y, value),
+                    _ => radiance += value,
+                }
+            }
+        }
+        radiance
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        self.trace(ray, &Context::new(scene, accel, None, ray.time), None, sampler, depth)
+    }
+
+    fn li_camera(&self, ray: &Ray, ctx: &RenderContext, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        if !ctx.camera.has_importance() {
+            return self.li(ray, ctx.scene, ctx.accel, sampler, depth);
+        }
+        let context = Context::new(ctx.scene, ctx.accel, Some(ctx.camera), ray.time);
+        self.trace(ray, &context, Some(ctx.film), sampler, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        camera::{Aperture, PerspectiveCamera},
+        hittable::{Hittable, Moving, Sphere},
+        integrator::PathIntegrator,
+        light::GradientSky,
+        material::Lambertian,
+        object::Object,
+        renderer::Renderer,
+        test_scenes::{average, floor_and_lamp},
+    };
+
+    #[test]
+    fn test_bdpt_furnace() {
+        let mut scene = Scene::new();
+        scene.set_environment(Some(Arc::new(GradientSky::new(DVec3::ONE, DVec3::ONE))));
+        let sphere = Arc::new(Sphere::new(DVec3::ZERO, 1.0)) as Arc<dyn Hittable>;
+        scene.add(Object::new(sphere, Arc::new(Lambertian::new(DVec3::splat(0.5)))));
+        let accel = crate::accel::BVH::new(&scene.objects);
+        let mut sampler = crate::sampler::RandomSampler::seeded(3);
+        let ray = Ray::new(DVec3::new(0.3, 0.2, 5.0), -DVec3::Z);
+        let integrator = BdptIntegrator::new();
+        let samples = 4000;
+        let value = (0..samples).map(|_| integrator.li(&ray, &scene, &accel, &mut sampler, 8)).sum::<DVec3>() / samples as f64;
+        assert!((value - DVec3::splat(0.5)).abs().max_element() < 1e-2, "{value}");
+    }
+
+    #[test]
+    fn test_bdpt_matches_path_tracing() {
+        // a floor lit by a quad light out of view, light tracing splats have
+        // to add up with the camera paths to the same image
+        let (scene, pinhole) = floor_and_lamp();
+        // light tracing has to hit the lens and land where the lens focuses it
+        let thin_lens = PerspectiveCamera::new(DVec3::new(0.0, 1.5, 4.0), DVec3::ZERO, DVec3::Y, 40.0, 4.0 / 3.0)
+            .with_lens(0.3, 2.0)
+            .with_aperture(Aperture::polygon(6, 0.0));
+
+        for camera in [pinhole, thin_lens] {
+            let mut reference = Renderer::new(16, 12, 64, 4);
+            reference.render(&camera, &scene, &PathIntegrator::new());
+            let mut bdpt = Renderer::new(16, 12, 64, 4);
+            bdpt.render(&camera, &scene, &BdptIntegrator::new());
+            let (expected, value) = (average(&reference), average(&bdpt));
+            assert!(expected.x > 0.05, "{expected}");
+            assert!(((value - expected) / expected).abs().max_element() < 0.03, "{value} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn test_connections_use_the_path_time() {
+        // a sphere crossing the segment between two vertices off any surface
+        // blocks it only at the time the paths are traced at
+        use crate::{light::PointLight, transform::{AnimatedTransform, Interpolation}};
+        use glam::DMat4;
+
+        let mut scene = Scene::new();
+        let motion = AnimatedTransform::new(
+            vec![(0.0, DMat4::from_translation(DVec3::X * 5.0)), (1.0, DMat4::IDENTITY)],
+            Interpolation::Slerp,
+        );
+        let sphere = Arc::new(Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 1.0)), motion));
+        scene.add(Object::new(sphere, Arc::new(Lambertian::new(DVec3::ONE))));
+        let accel = crate::accel::BVH::new(&scene.objects);
+        let light = PointLight::new(DVec3::new(0.0, 0.0, -5.0), DVec3::ONE);
+        let a = Vertex::camera(DVec3::new(0.0, 0.0, 5.0), DVec3::ONE);
+        let b = Vertex::light(&light, light.position, DVec3::ZERO, DVec3::ONE, 1.0);
+        assert!(Context::new(&scene, &accel, None, 0.0).unoccluded(&a, &b));
+        assert!(!Context::new(&scene, &accel, None, 1.0).unoccluded(&a, &b));
+    }
+}
@@ -0,0 +1,373 @@
+use std::f64::consts::PI;
+use std::ops::BitOr;
+
+use glam::DVec3;
+
+use crate::{hittable::HitRecord, sampling::sample_disk};
+
+/// Lobes a BSDF scatters into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const NONE: BsdfFlags = BsdfFlags(0);
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(1 << 1);
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(1 << 2);
+    pub const GLOSSY: BsdfFlags = BsdfFlags(1 << 3);
+    /// delta distribution, only reachable through `sample`
+    pub const SPECULAR: BsdfFlags = BsdfFlags(1 << 4);
+
+    pub fn contains(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(BsdfFlags::SPECULAR)
+    }
+
+    pub fn is_non_specular(self) -> bool {
+        self.intersects(BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY)
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = BsdfFlags;
+
+    fn bitor(self, rhs: BsdfFlags) -> BsdfFlags {
+        BsdfFlags(self.0 | rhs.0)
+    }
+}
+
+/// A direction sampled from a BSDF, in world space.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: DVec3,
+    pub f: DVec3,
+    /// solid angle density, or the discrete probability for specular lobes
+    pub pdf: f64,
+    /// the lobe the sample was drawn from
+    pub flags: BsdfFlags,
+    /// relative index of refraction n_t / n_i for transmission, 1 otherwise
+    pub eta: f64,
+}
+
+impl BsdfSample {
+    /// f * |cos| / pdf for the given shading normal
+    pub fn weight(&self, normal: DVec3) -> DVec3 {
+        self.f * self.wi.dot(normal).abs() / self.pdf
+    }
+}
+
+/// Orthonormal shading frame, the local z axis is the shading normal.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub s: DVec3,
+    pub t: DVec3,
+    pub n: DVec3,
+}
+
+impl Frame {
+    pub fn from_hit(hit: &HitRecord) -> Frame {
+        let (s, t, n) = hit.tangent_frame();
+        Frame { s, t, n }
+    }
+
+    pub fn from_normal(n: DVec3) -> Frame {
+        let s = if n.x.abs() > 0.9 { DVec3::Y.cross(n) } else { DVec3::X.cross(n) }.normalize();
+        let t = n.cross(s);
+        Frame { s, t, n }
+    }
+
+    pub fn to_local(&self, v: DVec3) -> DVec3 {
+        DVec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(&self, v: DVec3) -> DVec3 {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+pub fn cos_theta(w: DVec3) -> f64 {
+    w.z
+}
+
+pub fn abs_cos_theta(w: DVec3) -> f64 {
+    w.z.abs()
+}
+
+pub fn cos2_theta(w: DVec3) -> f64 {
+    w.z * w.z
+}
+
+pub fn sin2_theta(w: DVec3) -> f64 {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+
+pub fn tan2_theta(w: DVec3) -> f64 {
+    sin2_theta(w) / cos2_theta(w)
+}
+
+pub fn cos_phi(w: DVec3) -> f64 {
+    let sin_theta = sin2_theta(w).sqrt();
+    if sin_theta == 0.0 { 1.0 } else { (w.x / sin_theta).clamp(-1.0, 1.0) }
+}
+
+pub fn sin_phi(w: DVec3) -> f64 {
+    let sin_theta = sin2_theta(w).sqrt();
+    if sin_theta == 0.0 { 0.0 } else { (w.y / sin_theta).clamp(-1.0, 1.0) }
+}
+
+pub fn same_hemisphere(a: DVec3, b: DVec3) -> bool {
+    a.z * b.z > 0.0
+}
+
+/// `v` flipped onto the side of `n`
+pub fn face_forward(v: DVec3, n: DVec3) -> DVec3 {
+    if v.dot(n) < 0.0 { -v } else { v }
+}
+
+/// mirror `wo` about `n`, both pointing away from the surface
+pub fn reflect(wo: DVec3, n: DVec3) -> DVec3 {
+    -wo + 2.0 * wo.dot(n) * n
+}
+
+/// refract `wi` (pointing away from the surface, on the side of `n`) with
+/// relative index `eta` = n_i / n_t, or None on total internal reflection
+pub fn refract(wi: DVec3, n: DVec3, eta: f64) -> Option<DVec3> {
+    let cos_i = wi.dot(n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-wi * eta + (eta * cos_i - cos_t) * n).normalize())
+}
+
+pub fn schlick(cosine: f64, eta: f64) -> f64 {
+    let r0 = (1.0 - eta) / (1.0 + eta);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+/// unpolarized Fresnel reflectance of a dielectric interface, `eta` = n_t / n_i
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+
+    fn div(self, o: Complex) -> Complex {
+        let scale = 1.0 / (o.re * o.re + o.im * o.im);
+        Complex::new(
+            scale * (self.re * o.re + self.im * o.im),
+            scale * (self.im * o.re - self.re * o.im),
+        )
+    }
+
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+fn fresnel_complex_1(cos_i: f64, eta: Complex) -> f64 {
+    let cos_i = Complex::new(cos_i.clamp(0.0, 1.0), 0.0);
+    let one = Complex::new(1.0, 0.0);
+    let sin2_i = one.sub(cos_i.mul(cos_i));
+    let sin2_t = sin2_i.div(eta.mul(eta));
+    let cos_t = one.sub(sin2_t).sqrt();
+    let r_parl = eta.mul(cos_i).sub(cos_t).div(eta.mul(cos_i).add(cos_t));
+    let r_perp = cos_i.sub(eta.mul(cos_t)).div(cos_i.add(eta.mul(cos_t)));
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction eta + i k,
+/// per color channel
+pub fn fresnel_complex(cos_i: f64, eta: DVec3, k: DVec3) -> DVec3 {
+    DVec3::new(
+        fresnel_complex_1(cos_i, Complex::new(eta.x, k.x)),
+        fresnel_complex_1(cos_i, Complex::new(eta.y, k.y)),
+        fresnel_complex_1(cos_i, Complex::new(eta.z, k.z)),
+    )
+}
+
+/// perceptual roughness in [0, 1] to the microfacet alpha parameter
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    roughness * roughness
+}
+
+/// Trowbridge-Reitz (GGX) microfacet distribution, with visible normal sampling.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> TrowbridgeReitz {
+        TrowbridgeReitz { alpha_x, alpha_y }
+    }
+
+    /// below this the surface is treated as a perfect specular one
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: DVec3) -> f64 {
+        let tan2 = tan2_theta(wm);
+        if tan2.is_infinite() || tan2.is_nan() {
+            return 0.0;
+        }
+        let cos4 = cos2_theta(wm) * cos2_theta(wm);
+        if cos4 < 1e-16 {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = (cos_phi(wm), sin_phi(wm));
+        let e = tan2 * (cos_phi * cos_phi / (self.alpha_x * self.alpha_x) + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4 * (1.0 + e) * (1.0 + e))
+    }
+
+    pub fn lambda(&self, w: DVec3) -> f64 {
+        let tan2 = tan2_theta(w);
+        if tan2.is_infinite() || tan2.is_nan() {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = (cos_phi(w), sin_phi(w));
+        let alpha2 = cos_phi * cos_phi * self.alpha_x * self.alpha_x + sin_phi * sin_phi * self.alpha_y * self.alpha_y;
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: DVec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: DVec3, wi: DVec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// distribution of normals visible from `w`
+    pub fn d_visible(&self, w: DVec3, wm: DVec3) -> f64 {
+        let cos = abs_cos_theta(w);
+        if cos == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / cos * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// density of `sample_wm` picking `wm`
+    pub fn pdf(&self, w: DVec3, wm: DVec3) -> f64 {
+        self.d_visible(w, wm)
+    }
+
+    /// samples a microfacet normal visible from `w`
+    pub fn sample_wm(&self, w: DVec3, u: (f64, f64)) -> DVec3 {
+        let mut wh = DVec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z < 0.99999 { DVec3::Z.cross(wh).normalize() } else { DVec3::X };
+        let t2 = wh.cross(t1);
+        let mut p = sample_disk(u.0, u.1);
+        let h = (1.0 - p.x * p.x).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        p.y = (1.0 - s) * h + s * p.y;
+        let pz = (1.0 - p.length_squared()).max(0.0).sqrt();
+        let nh = p.x * t1 + p.y * t2 + pz * wh;
+        DVec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refract_matches_snell() {
+        let wi = DVec3::new(0.6, 0.0, 0.8);
+        let wt = refract(wi, DVec3::Z, 1.0 / 1.5).unwrap();
+        assert!(wt.z < 0.0);
+        assert!((wi.x - 1.5 * -wt.x).abs() < 1e-12);
+        assert!(refract(DVec3::new(0.8, 0.0, 0.6), DVec3::Z, 1.5).is_none());
+    }
+
+    #[test]
+    fn test_fresnel_complex_reduces_to_dielectric() {
+        for cos_i in [0.1, 0.5, 0.9, 1.0] {
+            let f = fresnel_complex(cos_i, DVec3::splat(1.5), DVec3::ZERO);
+            assert!((f.x - fresnel_dielectric(cos_i, 1.5)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_trowbridge_reitz_normalized() {
+        // the projected area of the microfacets equals the macro surface: integral of D cos = 1
+        let distribution = TrowbridgeReitz::new(0.3, 0.6);
+        let (n_theta, n_phi) = (400, 400);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * PI / 2.0;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * 2.0 * PI;
+                let wm = DVec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                let area = theta.sin() * (PI / 2.0 / n_theta as f64) * (2.0 * PI / n_phi as f64);
+                total += distribution.d(wm) * wm.z * area;
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert_eq!(fresnel_dielectric(-0.1, 1.5), 1.0);
+    }
+}
@@ -1,34 +1,989 @@
-use glam::DVec3;
+use std::f64::consts::PI;
 
-use crate::{ray::Ray, transform::Transform};
+use glam::{DVec2, DVec3};
+
+use crate::{
+    ray::{Ray, RayDifferential},
+    sampling::{Distribution1D, Distribution2D, sample_disk, sample_disk_pdf, sample_triangle},
+    scene::Scene,
+    texture::{Image, luminance},
+    transform::Transform,
+};
+
+/// Connection from a point in the scene to the camera, see `Camera::sample_wi`.
+pub struct CameraSample {
+    /// unit direction from the reference point to the camera
+    pub wi: DVec3,
+    pub position: DVec3,
+    pub importance: f64,
+    /// density per unit solid angle at the reference point
+    pub pdf: f64,
+    /// image coordinates the connection lands on, in [-1, 1]^2
+    pub uv: DVec2,
+}
 
 pub trait Camera : Send + Sync{
-    fn get_ray(&self, u: f64, v: f64) -> Ray;
+    /// ray through image coordinates (u, v) in [-1, 1]^2, leaving the lens at
+    /// the point picked by the sample `lens` in [0, 1)^2. Pinholes ignore it.
+    fn get_ray(&self, u: f64, v: f64, lens: (f64, f64)) -> Ray;
+
+    /// ray through (u, v) with differentials towards (u + du, v) and (u, v + dv),
+    /// taken from the rays through those points with the same lens sample
+    fn get_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64, lens: (f64, f64)) -> Ray {
+        let mut ray = self.get_ray(u, v, lens);
+        let rx = self.get_ray(u + du, v, lens);
+        let ry = self.get_ray(u, v + dv, lens);
+        ray.differential = Some(RayDifferential {
+            rx_origin: rx.origin,
+            rx_direction: rx.direction,
+            ry_origin: ry.origin,
+            ry_direction: ry.direction,
+        });
+        ray
+    }
+
+    /// whether the camera implements `we`, `pdf_we` and `sample_wi`, integrators
+    /// only trace light into cameras that do
+    fn has_importance(&self) -> bool {
+        false
+    }
+
+    /// importance the camera emits along `ray` and the image coordinates the ray
+    /// passes through, normalized over the whole image. Only cameras that
+    /// implement this can be reached by light tracing.
+    fn we(&self, _ray: &Ray) -> Option<(f64, DVec2)> {
+        None
+    }
+
+    /// (positional, directional) densities of `get_ray` generating `ray` for
+    /// uniformly distributed image coordinates
+    fn pdf_we(&self, _ray: &Ray) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    /// connects `reference` to a point on the camera's lens picked by `lens`
+    fn sample_wi(&self, _reference: DVec3, _lens: (f64, f64)) -> Option<CameraSample> {
+        None
+    }
+
+    /// interval the camera's rays, and the light paths joined with them, pick
+    /// their times from. Closed at time zero unless the camera has one.
+    fn shutter(&self) -> &Shutter {
+        static CLOSED: Shutter = Shutter::new(0.0, 0.0);
+        &CLOSED
+    }
 }
 
+/// Shape of a lens opening, which out of focus highlights take on. Openings
+/// live in [-1, 1]^2 and are scaled by the lens radius.
+pub enum Aperture {
+    Circle,
+    /// regular polygon with `blades` corners on the unit circle, the first one
+    /// `rotation` radians counterclockwise from +x
+    Polygon { blades: usize, rotation: f64 },
+    /// opening that lets light through in proportion to the brightness of an
+    /// image stretched over [-1, 1]^2
+    Image(Distribution2D),
+}
+
+impl Aperture {
+    pub fn polygon(blades: usize, rotation: f64) -> Aperture {
+        Aperture::Polygon { blades: blades.max(3), rotation }
+    }
+
+    /// opening shaped like `image`, `None` if no pixel lets light through
+    pub fn image(image: &Image) -> Option<Aperture> {
+        let func: Vec<f64> = image.pixels.iter().map(|&pixel| luminance(pixel).max(0.0)).collect();
+        if func.iter().sum::<f64>() <= 0.0 {
+            return None;
+        }
+        Some(Aperture::Image(Distribution2D::new(&func, image.width, image.height)))
+    }
+
+    /// point on the opening and its density per unit area
+    pub fn sample(&self, u: (f64, f64)) -> (DVec2, f64) {
+        match self {
+            Aperture::Circle => {
+                (sample_disk(u.0, u.1), sample_disk_pdf())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // one of the triangles between the center and two neighbouring corners
+                let n = *blades as f64;
+                let scaled = u.0 * n;
+                let i = (scaled as usize).min(blades - 1);
+                let corner = |i: usize| {
+                    let angle = rotation + 2.0 * PI * i as f64 / n;
+                    DVec2::new(angle.cos(), angle.sin())
+                };
+                let (b0, b1) = sample_triangle(scaled - i as f64, u.1);
+                let p = corner(i) * b0 + corner(i + 1) * b1;
+                (p, 1.0 / polygon_area(*blades))
+            }
+            Aperture::Image(distribution) => {
+                let (q, pdf) = distribution.sample_continuous(u);
+                (DVec2::new(2.0 * q.x - 1.0, 1.0 - 2.0 * q.y), pdf / 4.0)
+            }
+        }
+    }
+
+    /// density per unit area of `sample` picking `p`
+    pub fn pdf(&self, p: DVec2) -> f64 {
+        match self {
+            Aperture::Circle => {
+                if p.length_squared() <= 1.0 { sample_disk_pdf() } else { 0.0 }
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // inside if within the apothem along every edge's normal
+                let n = *blades as f64;
+                let apothem = (PI / n).cos();
+                let inside = (0..*blades).all(|i| {
+                    let angle = rotation + 2.0 * PI * (i as f64 + 0.5) / n;
+                    p.dot(DVec2::new(angle.cos(), angle.sin())) <= apothem + 1e-12
+                });
+                if inside { 1.0 / polygon_area(*blades) } else { 0.0 }
+            }
+            Aperture::Image(distribution) => {
+                if p.x.abs() > 1.0 || p.y.abs() > 1.0 {
+                    return 0.0;
+                }
+                distribution.pdf(DVec2::new((p.x + 1.0) / 2.0, (1.0 - p.y) / 2.0)) / 4.0
+            }
+        }
+    }
+}
+
+/// area of a regular polygon with its corners on the unit circle
+fn polygon_area(blades: usize) -> f64 {
+    let n = blades as f64;
+    n / 2.0 * (2.0 * PI / n).sin()
+}
+
+/// How far the shutter is open over its interval, which weighs the times
+/// light is gathered at.
+pub enum ShutterCurve {
+    /// fully open from start to end
+    Box,
+    /// opening linearly over the first `opening` and closing over the last
+    /// `closing` fraction of the interval
+    Trapezoid { opening: f64, closing: f64 },
+    /// openness in evenly spaced steps over the interval
+    Tabulated(Distribution1D),
+}
+
+impl ShutterCurve {
+    pub fn tabulated(openness: Vec<f64>) -> ShutterCurve {
+        ShutterCurve::Tabulated(Distribution1D::new(openness))
+    }
+
+    /// fraction of the interval sampled proportional to the openness
+    fn sample(&self, u: f64) -> f64 {
+        match self {
+            ShutterCurve::Box => u,
+            ShutterCurve::Trapezoid { opening, closing } => {
+                let a = opening.clamp(0.0, 1.0);
+                let b = closing.clamp(0.0, 1.0 - a);
+                let area = u * (1.0 - (a + b) / 2.0);
+                if area < a / 2.0 {
+                    (2.0 * a * area).sqrt()
+                } else if area < 1.0 - a / 2.0 - b {
+                    area + a / 2.0
+                } else {
+                    1.0 - (2.0 * b * (1.0 - (a + b) / 2.0 - area)).max(0.0).sqrt()
+                }
+            }
+            ShutterCurve::Tabulated(distribution) => distribution.sample_continuous(u).0,
+        }
+    }
+}
+
+/// Interval the shutter is open for, which camera rays sample their time
+/// from. The default opens and closes at time zero, leaving nothing blurred.
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+    pub curve: ShutterCurve,
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Self::new(0.0, 0.0)
+    }
+}
+
+impl Shutter {
+    /// shutter fully open from `open` to `close`
+    pub const fn new(open: f64, close: f64) -> Shutter {
+        Shutter { open, close, curve: ShutterCurve::Box }
+    }
+
+    pub fn with_curve(self, curve: ShutterCurve) -> Shutter {
+        Shutter { curve, ..self }
+    }
+
+    pub fn sample(&self, u: f64) -> f64 {
+        self.open + (self.close - self.open) * self.curve.sample(u)
+    }
+}
+
+/// Perspective camera looking down its local -z axis. With a lens radius of
+/// zero it is a pinhole, otherwise a thin lens that is sharp at
+/// `focus_distance` along the view axis.
 pub struct PerspectiveCamera {
     pub transform: Transform,
     pub vfov: f64,
     pub aspect_ratio: f64,
+    pub lens_radius: f64,
+    pub focus_distance: f64,
+    pub aperture: Aperture,
+    /// interval the rays pick their times from, closed by default
+    pub shutter: Shutter,
 }
 
 impl PerspectiveCamera {
     pub fn new(lookfrom: DVec3, lookat: DVec3, vup: DVec3, vfov: f64, aspect_ratio: f64) -> PerspectiveCamera {
         let transform = Transform::lookat(lookfrom, lookat, vup);
-        PerspectiveCamera { transform, vfov, aspect_ratio }
+        PerspectiveCamera { transform, vfov, aspect_ratio, lens_radius: 0.0, focus_distance: 1.0, aperture: Aperture::Circle, shutter: Shutter::default() }
+    }
+
+    /// the same camera with a thin lens of `lens_radius`, focused at `focus_distance`
+    pub fn with_lens(self, lens_radius: f64, focus_distance: f64) -> PerspectiveCamera {
+        PerspectiveCamera { lens_radius, focus_distance, ..self }
+    }
+
+    pub fn with_aperture(self, aperture: Aperture) -> PerspectiveCamera {
+        PerspectiveCamera { aperture, ..self }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> PerspectiveCamera {
+        PerspectiveCamera { shutter, ..self }
+    }
+
+    /// puts the plane of focus through `point`
+    pub fn focus_on(&mut self, point: DVec3) {
+        self.focus_distance = (-self.transform.point_to_local(point).z).max(1e-6);
+    }
+
+    /// focuses on whatever the center of the lens sees through image
+    /// coordinates (u, v), returns whether it saw anything
+    pub fn autofocus(&mut self, scene: &Scene, u: f64, v: f64) -> bool {
+        let half = self.half_size();
+        let origin = self.transform.point_to_world(DVec3::ZERO);
+        let direction = self.transform.vector_to_world(DVec3::new(u * half.x, v * half.y, -1.0));
+        match scene.hit(&Ray::new(origin, direction.normalize())) {
+            Some(hit) => {
+                self.focus_on(hit.p);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// point on the lens in camera space and its density per unit area
+    fn sample_lens(&self, u: (f64, f64)) -> (DVec3, f64) {
+        if self.lens_radius <= 0.0 {
+            // a pinhole has no area, it counts as a unit lens
+            return (DVec3::ZERO, 1.0);
+        }
+        let (p, pdf) = self.aperture.sample(u);
+        ((p * self.lens_radius).extend(0.0), pdf / (self.lens_radius * self.lens_radius))
+    }
+
+    fn lens_pdf(&self, p: DVec3) -> f64 {
+        if self.lens_radius <= 0.0 {
+            return 1.0;
+        }
+        self.aperture.pdf(DVec2::new(p.x, p.y) / self.lens_radius) / (self.lens_radius * self.lens_radius)
+    }
+
+    /// half extent of the image plane at distance 1
+    fn half_size(&self) -> DVec2 {
+        let half_height = (self.vfov.to_radians() / 2.0).tan();
+        DVec2::new(self.aspect_ratio * half_height, half_height)
+    }
+
+    /// image coordinates a ray leaving the lens passes through, the cosine of
+    /// its direction to the view axis and where it leaves the lens in camera space
+    fn project(&self, ray: &Ray) -> Option<(DVec2, f64, DVec3)> {
+        let origin = self.transform.point_to_local(ray.origin);
+        let direction = self.transform.vector_to_local(ray.direction).normalize();
+        let cos_theta = -direction.z;
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        // rays from all over the lens meet again on the plane of focus
+        let distance = if self.lens_radius > 0.0 { self.focus_distance } else { 1.0 };
+        let focus = origin + direction * (distance / cos_theta);
+        let uv = DVec2::new(focus.x, focus.y) / distance / self.half_size();
+        if uv.x.abs() > 1.0 || uv.y.abs() > 1.0 {
+            return None;
+        }
+        Some((uv, cos_theta, DVec3::new(origin.x, origin.y, 0.0)))
+    }
+
+    fn image_area(&self) -> f64 {
+        let half = self.half_size();
+        4.0 * half.x * half.y
     }
 }
 
 impl Camera for PerspectiveCamera {
-    fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let theta = self.vfov.to_radians();
-        let half_height = (theta / 2.0).tan();
-        let half_width = self.aspect_ratio * half_height;
-        let x = u * half_width;
-        let y = v * half_height;
+    fn get_ray(&self, u: f64, v: f64, lens: (f64, f64)) -> Ray {
+        let half = self.half_size();
+        let direction = DVec3::new(u * half.x, v * half.y, -1.0);
+        let (origin, direction) = if self.lens_radius > 0.0 {
+            let (lens, _) = self.sample_lens(lens);
+            (lens, direction * self.focus_distance - lens)
+        } else {
+            (DVec3::ZERO, direction)
+        };
+        let origin = self.transform.point_to_world(origin);
+        let direction = self.transform.vector_to_world(direction);
+        Ray::new(origin, direction.normalize())
+    }
+
+    fn we(&self, ray: &Ray) -> Option<(f64, DVec2)> {
+        let (uv, cos_theta, lens) = self.project(ray)?;
+        let importance = self.lens_pdf(lens) / (self.image_area() * cos_theta.powi(4));
+        if importance <= 0.0 {
+            return None;
+        }
+        Some((importance, uv))
+    }
+
+    fn pdf_we(&self, ray: &Ray) -> (f64, f64) {
+        match self.project(ray) {
+            Some((_, cos_theta, lens)) => (self.lens_pdf(lens), 1.0 / (self.image_area() * cos_theta.powi(3))),
+            None => (0.0, 0.0),
+        }
+    }
+
+    fn sample_wi(&self, reference: DVec3, lens: (f64, f64)) -> Option<CameraSample> {
+        let (lens, lens_pdf) = self.sample_lens(lens);
+        let position = self.transform.point_to_world(lens);
+        let to_camera = position - reference;
+        let distance_squared = to_camera.length_squared();
+        if distance_squared == 0.0 || lens_pdf <= 0.0 {
+            return None;
+        }
+        let wi = to_camera / distance_squared.sqrt();
+        let (uv, cos_theta, _) = self.project(&Ray::new(position, -wi))?;
+        let importance = lens_pdf / (self.image_area() * cos_theta.powi(4));
+        // the lens faces down the view axis
+        let pdf = distance_squared / cos_theta * lens_pdf;
+        Some(CameraSample { wi, position, importance, pdf, uv })
+    }
+
+    fn has_importance(&self) -> bool {
+        true
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+/// Parallel projection for technical drawings, showing a `height` tall slice
+/// of the scene around the view axis.
+pub struct OrthographicCamera {
+    pub transform: Transform,
+    pub height: f64,
+    pub aspect_ratio: f64,
+    pub shutter: Shutter,
+}
+
+impl OrthographicCamera {
+    pub fn new(lookfrom: DVec3, lookat: DVec3, vup: DVec3, height: f64, aspect_ratio: f64) -> OrthographicCamera {
+        let transform = Transform::lookat(lookfrom, lookat, vup);
+        OrthographicCamera { transform, height, aspect_ratio, shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> OrthographicCamera {
+        OrthographicCamera { shutter, ..self }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let half_height = self.height / 2.0;
+        let origin = DVec3::new(u * self.aspect_ratio * half_height, v * half_height, 0.0);
+        let direction = self.transform.vector_to_world(-DVec3::Z);
+        Ray::new(self.transform.point_to_world(origin), direction.normalize())
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+/// How a fisheye lens maps the angle to the view axis onto the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeProjection {
+    /// distance from the image center proportional to the angle
+    Equidistant,
+    /// distance proportional to sin(angle / 2), preserving solid angle
+    Equisolid,
+}
+
+/// Fisheye camera whose image circle spans `fov` degrees across the image
+/// height. The corners of wider images see past it, up to straight behind.
+pub struct FisheyeCamera {
+    pub transform: Transform,
+    pub fov: f64,
+    pub aspect_ratio: f64,
+    pub projection: FisheyeProjection,
+    pub shutter: Shutter,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: DVec3,
+        lookat: DVec3,
+        vup: DVec3,
+        fov: f64,
+        aspect_ratio: f64,
+        projection: FisheyeProjection,
+    ) -> FisheyeCamera {
+        let transform = Transform::lookat(lookfrom, lookat, vup);
+        FisheyeCamera { transform, fov, aspect_ratio, projection, shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> FisheyeCamera {
+        FisheyeCamera { shutter, ..self }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let p = DVec2::new(u * self.aspect_ratio, v);
+        let r = p.length();
+        let theta_max = self.fov.to_radians() / 2.0;
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * theta_max,
+            FisheyeProjection::Equisolid => 2.0 * (r * (theta_max / 2.0).sin()).min(1.0).asin(),
+        }
+        .min(PI);
+        let phi = p.y.atan2(p.x);
+        let direction = DVec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), -theta.cos());
+        let origin = self.transform.point_to_world(DVec3::ZERO);
+        Ray::new(origin, self.transform.vector_to_world(direction).normalize())
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+/// 360 degree latitude-longitude camera for 2:1 panoramas. The horizontal
+/// image axis runs around the up axis starting behind the camera, with the
+/// view direction in the middle, and the vertical axis from straight down to
+/// straight up.
+pub struct EquirectangularCamera {
+    pub transform: Transform,
+    pub shutter: Shutter,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: DVec3, lookat: DVec3, vup: DVec3) -> EquirectangularCamera {
+        EquirectangularCamera { transform: Transform::lookat(lookfrom, lookat, vup), shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> EquirectangularCamera {
+        EquirectangularCamera { shutter, ..self }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let phi = PI * u;
+        let latitude = PI / 2.0 * v.clamp(-1.0, 1.0);
+        let direction = DVec3::new(latitude.cos() * phi.sin(), latitude.sin(), -latitude.cos() * phi.cos());
         let origin = self.transform.point_to_world(DVec3::ZERO);
-        let dir_local = DVec3::new(x, y, -1.0);
-        let dir_world = self.transform.vector_to_world(dir_local);
-        Ray::new(origin, dir_world.normalize())
+        Ray::new(origin, self.transform.vector_to_world(direction).normalize())
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
     }
-}
\ No newline at end of file
+}
+
+/// (forward, right, up) of the cubemap faces in camera space, in the order
+/// +x, -x, +y, -y, +z, -z where -z is the view direction
+const CUBE_FACES: [(DVec3, DVec3, DVec3); 6] = [
+    (DVec3::X, DVec3::Z, DVec3::Y),
+    (DVec3::NEG_X, DVec3::NEG_Z, DVec3::Y),
+    (DVec3::Y, DVec3::X, DVec3::Z),
+    (DVec3::NEG_Y, DVec3::X, DVec3::NEG_Z),
+    (DVec3::Z, DVec3::NEG_X, DVec3::Y),
+    (DVec3::NEG_Z, DVec3::X, DVec3::Y),
+];
+
+/// Six 90 degree views along the axes of the camera, side by side in a 6:1
+/// image in the order right, left, up, down, back and front.
+pub struct CubemapCamera {
+    pub transform: Transform,
+    pub shutter: Shutter,
+}
+
+impl CubemapCamera {
+    pub fn new(lookfrom: DVec3, lookat: DVec3, vup: DVec3) -> CubemapCamera {
+        CubemapCamera { transform: Transform::lookat(lookfrom, lookat, vup), shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> CubemapCamera {
+        CubemapCamera { shutter, ..self }
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let x = (u + 1.0) * 3.0;
+        let face = (x.max(0.0) as usize).min(5);
+        let a = 2.0 * (x - face as f64) - 1.0;
+        let (forward, right, up) = CUBE_FACES[face];
+        let direction = forward + right * a + up * v;
+        let origin = self.transform.point_to_world(DVec3::ZERO);
+        Ray::new(origin, self.transform.vector_to_world(direction).normalize())
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+/// One of the two views of a stereo camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// side of the view axis the eye sits on, -1 for left and 1 for right
+    fn side(self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+/// How the two views of a stereo camera share one image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// left eye in the left half, right eye in the right half
+    SideBySide,
+    /// left eye in the top half, right eye in the bottom half
+    OverUnder,
+}
+
+impl StereoLayout {
+    /// size of the whole image for views of `width` by `height` pixels
+    pub fn resolution(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            StereoLayout::SideBySide => (2 * width, height),
+            StereoLayout::OverUnder => (width, 2 * height),
+        }
+    }
+
+    /// eye and coordinates within its view of the image point (u, v)
+    pub fn split(self, u: f64, v: f64) -> (Eye, f64, f64) {
+        match self {
+            StereoLayout::SideBySide if u < 0.0 => (Eye::Left, 2.0 * u + 1.0, v),
+            StereoLayout::SideBySide => (Eye::Right, 2.0 * u - 1.0, v),
+            StereoLayout::OverUnder if v >= 0.0 => (Eye::Left, u, 2.0 * v - 1.0),
+            StereoLayout::OverUnder => (Eye::Right, u, 2.0 * v + 1.0),
+        }
+    }
+
+    /// how much larger steps are within a view than across the whole image
+    fn scale(self) -> (f64, f64) {
+        match self {
+            StereoLayout::SideBySide => (2.0, 1.0),
+            StereoLayout::OverUnder => (1.0, 2.0),
+        }
+    }
+}
+
+/// ray of `eye` at (u, v) of its view, with differentials taken within the same
+/// view so that they don't jump across the seam between the two
+fn stereo_ray_differential(
+    layout: StereoLayout,
+    eye_ray: impl Fn(Eye, f64, f64) -> Ray,
+    u: f64,
+    v: f64,
+    du: f64,
+    dv: f64,
+) -> Ray {
+    let (eye, u, v) = layout.split(u, v);
+    let (su, sv) = layout.scale();
+    let mut ray = eye_ray(eye, u, v);
+    let rx = eye_ray(eye, u + du * su, v);
+    let ry = eye_ray(eye, u, v + dv * sv);
+    ray.differential = Some(RayDifferential {
+        rx_origin: rx.origin,
+        rx_direction: rx.direction,
+        ry_origin: ry.origin,
+        ry_direction: ry.direction,
+    });
+    ray
+}
+
+/// Pair of pinhole views `interocular` apart, laid out in one image. The views
+/// look in parallel with their frusta shifted towards each other so that
+/// objects at `convergence` distance appear at the same place in both and sit
+/// on the screen plane when viewed. `aspect_ratio` is that of a single view.
+/// The eyes default to 64 mm apart converging on `lookat`.
+pub struct StereoCamera {
+    pub transform: Transform,
+    pub half_width: f64,
+    pub half_height: f64,
+    pub interocular: f64,
+    pub convergence: f64,
+    pub layout: StereoLayout,
+    pub shutter: Shutter,
+}
+
+impl StereoCamera {
+    pub fn new(
+        lookfrom: DVec3,
+        lookat: DVec3,
+        vup: DVec3,
+        fov: f64,
+        aspect_ratio: f64,
+        layout: StereoLayout,
+    ) -> StereoCamera {
+        let transform = Transform::lookat(lookfrom, lookat, vup);
+        let half_height = (fov.to_radians() / 2.0).tan();
+        let half_width = aspect_ratio * half_height;
+        let convergence = lookfrom.distance(lookat);
+        StereoCamera { transform, half_width, half_height, interocular: 0.064, convergence, layout, shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> StereoCamera {
+        StereoCamera { shutter, ..self }
+    }
+
+    /// eyes `interocular` apart converging at distance `convergence`
+    pub fn with_eyes(mut self, interocular: f64, convergence: f64) -> StereoCamera {
+        self.interocular = interocular;
+        self.convergence = convergence;
+        self
+    }
+
+    /// ray of `eye` through (u, v) of its view
+    pub fn eye_ray(&self, eye: Eye, u: f64, v: f64) -> Ray {
+        let origin = DVec3::new(eye.side() * self.interocular / 2.0, 0.0, 0.0);
+        let target = DVec3::new(u * self.half_width, v * self.half_height, -1.0) * self.convergence;
+        let direction = self.transform.vector_to_world(target - origin);
+        Ray::new(self.transform.point_to_world(origin), direction.normalize())
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let (eye, u, v) = self.layout.split(u, v);
+        self.eye_ray(eye, u, v)
+    }
+
+    fn get_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64, _lens: (f64, f64)) -> Ray {
+        stereo_ray_differential(self.layout, |eye, u, v| self.eye_ray(eye, u, v), u, v, du, dv)
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+/// Omni-directional stereo panorama: a pair of 360 degree latitude-longitude
+/// views as seen by eyes `interocular` apart turning around the up axis. Every
+/// ray starts on the circle the eyes move along, tangent to it, so looking in
+/// any horizontal direction gives a correct stereo pair. The circle shrinks
+/// towards the poles, where the views merge into one, to avoid the swirl a
+/// constant offset leaves there.
+pub struct OdsCamera {
+    pub transform: Transform,
+    pub interocular: f64,
+    pub layout: StereoLayout,
+    pub shutter: Shutter,
+}
+
+impl OdsCamera {
+    pub fn new(lookfrom: DVec3, lookat: DVec3, vup: DVec3, interocular: f64, layout: StereoLayout) -> OdsCamera {
+        let transform = Transform::lookat(lookfrom, lookat, vup);
+        OdsCamera { transform, interocular, layout, shutter: Shutter::default() }
+    }
+
+    pub fn with_shutter(self, shutter: Shutter) -> OdsCamera {
+        OdsCamera { shutter, ..self }
+    }
+
+    /// ray of `eye` through (u, v) of its view, laid out like `EquirectangularCamera`
+    pub fn eye_ray(&self, eye: Eye, u: f64, v: f64) -> Ray {
+        let phi = PI * u;
+        let latitude = PI / 2.0 * v.clamp(-1.0, 1.0);
+        let direction = DVec3::new(latitude.cos() * phi.sin(), latitude.sin(), -latitude.cos() * phi.cos());
+        let right = DVec3::new(phi.cos(), 0.0, phi.sin());
+        let origin = right * eye.side() * self.interocular / 2.0 * latitude.cos();
+        Ray::new(self.transform.point_to_world(origin), self.transform.vector_to_world(direction).normalize())
+    }
+}
+
+impl Camera for OdsCamera {
+    fn get_ray(&self, u: f64, v: f64, _lens: (f64, f64)) -> Ray {
+        let (eye, u, v) = self.layout.split(u, v);
+        self.eye_ray(eye, u, v)
+    }
+
+    fn get_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64, _lens: (f64, f64)) -> Ray {
+        stereo_ray_differential(self.layout, |eye, u, v| self.eye_ray(eye, u, v), u, v, du, dv)
+    }
+
+    fn shutter(&self) -> &Shutter {
+        &self.shutter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{hittable::Sphere, material::Lambertian, object::Object, sampler::{RandomSampler, Sampler}};
+
+    fn ring() -> Image {
+        let pixels = (0..64)
+            .map(|i| {
+                let p = DVec2::new((i % 8) as f64 - 3.5, (i / 8) as f64 - 3.5);
+                if (2.0..3.5).contains(&p.length()) { DVec3::ONE } else { DVec3::ZERO }
+            })
+            .collect();
+        Image::new(8, 8, pixels)
+    }
+
+    #[test]
+    fn test_aperture_densities() {
+        let mut sampler = RandomSampler::seeded(4);
+        assert!(Aperture::image(&Image::new(8, 8, vec![DVec3::ZERO; 64])).is_none());
+        for aperture in [Aperture::Circle, Aperture::polygon(6, 0.3), Aperture::image(&ring()).unwrap()] {
+            // the density integrates to one over [-1, 1]^2
+            let n = 100_000;
+            let integral = (0..n)
+                .map(|_| aperture.pdf(DVec2::new(2.0 * sampler.get_1d() - 1.0, 2.0 * sampler.get_1d() - 1.0)) * 4.0)
+                .sum::<f64>()
+                / n as f64;
+            assert!((integral - 1.0).abs() < 2e-2, "{integral}");
+            for _ in 0..100 {
+                let (p, pdf) = aperture.sample(sampler.get_2d());
+                assert!(pdf > 0.0 && (aperture.pdf(p) - pdf).abs() < 1e-9 * pdf, "{p} {pdf}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_thin_lens() {
+        let camera = PerspectiveCamera::new(DVec3::new(1.0, 2.0, 3.0), DVec3::ZERO, DVec3::Y, 50.0, 1.5)
+            .with_lens(0.3, 2.5)
+            .with_aperture(Aperture::polygon(5, 0.0));
+        let mut sampler = RandomSampler::seeded(8);
+        for _ in 0..20 {
+            let (u, v) = (2.0 * sampler.get_1d() - 1.0, 2.0 * sampler.get_1d() - 1.0);
+            // rays through one image point meet again on the plane of focus
+            let (a, b) = (camera.get_ray(u, v, sampler.get_2d()), camera.get_ray(u, v, sampler.get_2d()));
+            let focus = |ray: &Ray| {
+                let t = -2.5 / camera.transform.vector_to_local(ray.direction).z;
+                ray.at(t)
+            };
+            assert!(a.origin.distance(b.origin) > 0.0);
+            assert!(focus(&a).distance(focus(&b)) < 1e-9);
+            let (_, uv) = camera.we(&a).unwrap();
+            assert!((uv - DVec2::new(u, v)).length() < 1e-9);
+
+            // connecting to the lens agrees with the importance of the ray leaving it
+            let reference = focus(&a) + DVec3::new(0.1, -0.2, 0.3) * sampler.get_1d();
+            if let Some(sample) = camera.sample_wi(reference, sampler.get_2d()) {
+                let ray = Ray::new(sample.position, -sample.wi);
+                let (importance, uv) = camera.we(&ray).unwrap();
+                assert!((importance - sample.importance).abs() < 1e-9 * importance);
+                assert!((uv - sample.uv).length() < 1e-9);
+                assert!(camera.pdf_we(&ray).0 > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_autofocus() {
+        let mut scene = Scene::new();
+        scene.add(Object::new(Arc::new(Sphere::new(DVec3::new(0.0, 0.0, -5.0), 1.0)), Arc::new(Lambertian::new(DVec3::ONE))));
+        let mut camera = PerspectiveCamera::new(DVec3::ZERO, -DVec3::Z, DVec3::Y, 40.0, 1.0).with_lens(0.1, 1.0);
+        assert!(camera.autofocus(&scene, 0.0, 0.0));
+        assert!((camera.focus_distance - 4.0).abs() < 1e-9);
+        assert!(!camera.autofocus(&scene, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_shutter_curves() {
+        // histogram of sampled times against the area under each curve
+        let check = |shutter: Shutter, openness: &dyn Fn(f64) -> f64| {
+            let mut sampler = RandomSampler::seeded(4);
+            let (n, bins) = (100_000, 10);
+            let mut histogram = vec![0.0; bins];
+            for _ in 0..n {
+                let time = shutter.sample(sampler.get_1d());
+                assert!((shutter.open..=shutter.close).contains(&time));
+                let x = (time - shutter.open) / (shutter.close - shutter.open);
+                histogram[((x * bins as f64) as usize).min(bins - 1)] += 1.0 / n as f64;
+            }
+            let steps = 1000;
+            let area: Vec<f64> = (0..bins)
+                .map(|bin| (0..steps).map(|i| openness((bin as f64 + (i as f64 + 0.5) / steps as f64) / bins as f64)).sum())
+                .collect();
+            let total: f64 = area.iter().sum();
+            for (bin, area) in area.iter().enumerate() {
+                assert!((histogram[bin] - area / total).abs() < 5e-3, "bin {bin}: {} vs {}", histogram[bin], area / total);
+            }
+        };
+        check(Shutter::new(1.0, 3.0), &|_| 1.0);
+        check(
+            Shutter::new(0.0, 0.5).with_curve(ShutterCurve::Trapezoid { opening: 0.3, closing: 0.2 }),
+            &|x| (x / 0.3).min(1.0).min((1.0 - x) / 0.2),
+        );
+        check(Shutter::new(-1.0, 1.0).with_curve(ShutterCurve::tabulated(vec![0.0, 1.0, 3.0, 1.0, 0.0])), &|x| {
+            [0.0, 1.0, 3.0, 1.0, 0.0][((x * 5.0) as usize).min(4)]
+        });
+        assert_eq!(Shutter::default().sample(0.7), 0.0);
+    }
+
+    #[test]
+    fn test_camera_shutter_times_rays() {
+        // a black sphere that only arrives in front of the camera at time one
+        use crate::{hittable::Moving, integrator::PathIntegrator, renderer::Renderer, transform::{AnimatedTransform, Interpolation}};
+        use glam::DMat4;
+
+        let mut scene = Scene::new();
+        let motion = AnimatedTransform::new(
+            vec![(0.0, DMat4::from_translation(DVec3::X * 100.0)), (1.0, DMat4::IDENTITY), (2.0, DMat4::IDENTITY)],
+            Interpolation::Slerp,
+        );
+        let sphere = Arc::new(Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 1.0)), motion));
+        scene.add(Object::new(sphere, Arc::new(Lambertian::new(DVec3::ZERO))));
+        let camera = PerspectiveCamera::new(DVec3::new(0.0, 0.0, 3.0), DVec3::ZERO, DVec3::Y, 10.0, 1.0);
+        let render = |camera: &PerspectiveCamera| {
+            let mut renderer = Renderer::new(4, 4, 4, 2);
+            renderer.render(camera, &scene, &PathIntegrator::new());
+            renderer.buffer.iter().sum::<DVec3>()
+        };
+        assert!(render(&camera).min_element() > 0.1);
+        assert_eq!(render(&camera.with_shutter(Shutter::new(1.0, 2.0))), DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_orthographic_camera() {
+        let camera = OrthographicCamera::new(DVec3::new(0.0, 0.0, 5.0), DVec3::ZERO, DVec3::Y, 4.0, 2.0);
+        let a = camera.get_ray(-1.0, -1.0, (0.5, 0.5));
+        let b = camera.get_ray(1.0, 1.0, (0.5, 0.5));
+        assert!((a.direction - -DVec3::Z).length() < 1e-12);
+        assert!((b.direction - -DVec3::Z).length() < 1e-12);
+        assert!((b.origin - a.origin - DVec3::new(8.0, 4.0, 0.0)).length() < 1e-12);
+        assert!(!camera.has_importance());
+    }
+
+    #[test]
+    fn test_fisheye_camera() {
+        for projection in [FisheyeProjection::Equidistant, FisheyeProjection::Equisolid] {
+            let camera = FisheyeCamera::new(DVec3::ZERO, DVec3::X, DVec3::Y, 180.0, 1.5, projection);
+            assert!((camera.get_ray(0.0, 0.0, (0.5, 0.5)).direction - DVec3::X).length() < 1e-12);
+            // the top edge of the image is at half the field of view
+            let top = camera.get_ray(0.0, 1.0, (0.5, 0.5)).direction;
+            assert!((top - DVec3::Y).length() < 1e-9, "{projection:?} {top}");
+            let side = camera.get_ray(0.5 / 1.5, 0.0, (0.5, 0.5)).direction;
+            assert!(side.z > 0.0 && side.dot(DVec3::X) > 0.0);
+        }
+        // equidistant spaces angles evenly, equisolid squeezes the rim
+        let angle = |projection| {
+            let camera = FisheyeCamera::new(DVec3::ZERO, DVec3::X, DVec3::Y, 180.0, 1.0, projection);
+            camera.get_ray(0.0, 0.5, (0.5, 0.5)).direction.dot(DVec3::X).acos().to_degrees()
+        };
+        assert!((angle(FisheyeProjection::Equidistant) - 45.0).abs() < 1e-9);
+        assert!(angle(FisheyeProjection::Equisolid) < 45.0);
+    }
+
+    #[test]
+    fn test_equirectangular_camera() {
+        let camera = EquirectangularCamera::new(DVec3::ONE, DVec3::new(1.0, 1.0, 0.0), DVec3::Y);
+        let direction = |u, v| camera.get_ray(u, v, (0.5, 0.5)).direction;
+        assert!((direction(0.0, 0.0) - -DVec3::Z).length() < 1e-12);
+        assert!((direction(0.5, 0.0) - DVec3::X).length() < 1e-12);
+        assert!((direction(-1.0, 0.0) - DVec3::Z).length() < 1e-12);
+        assert!((direction(1.0, 0.0) - DVec3::Z).length() < 1e-12);
+        assert!((direction(0.3, 1.0) - DVec3::Y).length() < 1e-12);
+        assert_eq!(camera.get_ray(0.0, 0.0, (0.5, 0.5)).origin, DVec3::ONE);
+    }
+
+    #[test]
+    fn test_cubemap_camera() {
+        let camera = CubemapCamera::new(DVec3::ZERO, -DVec3::Z, DVec3::Y);
+        let direction = |u: f64, v| camera.get_ray(u, v, (0.5, 0.5)).direction;
+        let centers = [DVec3::X, -DVec3::X, DVec3::Y, -DVec3::Y, DVec3::Z, -DVec3::Z];
+        for (face, center) in centers.into_iter().enumerate() {
+            let u = (face as f64 + 0.5) / 3.0 - 1.0;
+            assert!((direction(u, 0.0) - center).length() < 1e-12, "face {face}");
+        }
+        // the right edge of the front face is the left edge of the right face
+        let eps = 1e-9;
+        let front = direction(1.0 - eps, 0.3);
+        let right = direction(-1.0 + eps, 0.3);
+        assert!((front - right).length() < 1e-6, "{front} {right}");
+        // and the top of the front face meets the bottom of the up face
+        let front = direction(5.7 / 3.0 - 1.0, 1.0);
+        let up = direction(2.7 / 3.0 - 1.0, -1.0);
+        assert!((front - up).length() < 1e-12, "{front} {up}");
+    }
+
+    #[test]
+    fn test_stereo_layouts() {
+        assert_eq!(StereoLayout::SideBySide.resolution(100, 50), (200, 50));
+        assert_eq!(StereoLayout::OverUnder.resolution(100, 50), (100, 100));
+        assert_eq!(StereoLayout::SideBySide.split(-0.5, 0.2), (Eye::Left, 0.0, 0.2));
+        assert_eq!(StereoLayout::SideBySide.split(1.0, 0.2), (Eye::Right, 1.0, 0.2));
+        assert_eq!(StereoLayout::OverUnder.split(0.3, 1.0), (Eye::Left, 0.3, 1.0));
+        assert_eq!(StereoLayout::OverUnder.split(0.3, -0.5), (Eye::Right, 0.3, 0.0));
+    }
+
+    #[test]
+    fn test_stereo_camera() {
+        let camera = StereoCamera::new(DVec3::ZERO, -DVec3::Z, DVec3::Y, 60.0, 1.0, StereoLayout::SideBySide)
+            .with_eyes(0.064, 3.0);
+        let left = camera.get_ray(-0.5, 0.0, (0.5, 0.5));
+        let right = camera.get_ray(0.5, 0.0, (0.5, 0.5));
+        assert!((left.origin - DVec3::new(-0.032, 0.0, 0.0)).length() < 1e-12);
+        assert!((right.origin - DVec3::new(0.032, 0.0, 0.0)).length() < 1e-12);
+        // the centers of both views converge on the same point
+        let meet = |ray: &Ray| ray.at(-3.0 / ray.direction.z);
+        assert!((meet(&left) - DVec3::new(0.0, 0.0, -3.0)).length() < 1e-12);
+        assert!((meet(&right) - DVec3::new(0.0, 0.0, -3.0)).length() < 1e-12);
+
+        // differentials at the seam stay within the left view
+        let ray = camera.get_ray_differential(-1e-6, 0.0, 0.01, -0.01, (0.5, 0.5));
+        let differential = ray.differential.unwrap();
+        assert_eq!(differential.rx_origin, ray.origin);
+    }
+
+    #[test]
+    fn test_ods_camera() {
+        let camera = OdsCamera::new(DVec3::ZERO, -DVec3::Z, DVec3::Y, 0.064, StereoLayout::OverUnder);
+        for u in [-0.9, -0.3, 0.0, 0.4, 0.8] {
+            let left = camera.get_ray(u, 0.5, (0.5, 0.5));
+            let right = camera.get_ray(u, -0.5, (0.5, 0.5));
+            // both rays leave the circle of the eyes tangentially in the same direction
+            assert!((left.direction - right.direction).length() < 1e-12);
+            assert!((left.origin.length() - 0.032).abs() < 1e-12);
+            assert!(left.origin.dot(left.direction).abs() < 1e-12);
+            assert!((left.origin + right.origin).length() < 1e-12);
+        }
+        // looking forward the left eye is on the left
+        assert!(camera.get_ray(0.0, 0.5, (0.5, 0.5)).origin.x < 0.0);
+        // and at the poles both views meet
+        let (left, right) = (camera.get_ray(0.2, 1.0, (0.5, 0.5)), camera.get_ray(0.2, 0.0, (0.5, 0.5)));
+        assert!((left.origin - right.origin).length() < 1e-12);
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use glam::DVec3;
+
+/// Splat target for light that lands on arbitrary pixels, like light tracing
+/// connections to the camera. Any thread can add to any pixel.
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    /// rgb triples of f64 bit patterns, updated with compare and swap
+    splats: Vec<AtomicU64>,
+}
+
+fn atomic_add(value: &AtomicU64, add: f64) {
+    let mut current = value.load(Ordering::Relaxed);
+    loop {
+        let new = (f64::from_bits(current) + add).to_bits();
+        match value.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        let splats = (0..width * height * 3).map(|_| AtomicU64::new(0.0f64.to_bits())).collect();
+        Film { width, height, splats }
+    }
+
+    /// pixel the camera's (u, v) in [-1, 1]^2 falls into, v pointing up
+    pub fn pixel(&self, u: f64, v: f64) -> Option<(usize, usize)> {
+        let x = ((u + 1.0) * 0.5 * self.width as f64).floor();
+        let y = ((1.0 - v) * 0.5 * self.height as f64).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    pub fn add_splat(&self, u: f64, v: f64, value: DVec3) {
+        if !value.is_finite() {
+            return;
+        }
+        let Some((x, y)) = self.pixel(u, v) else {
+            return;
+        };
+        let i = 3 * (y * self.width + x);
+        atomic_add(&self.splats[i], value.x);
+        atomic_add(&self.splats[i + 1], value.y);
+        atomic_add(&self.splats[i + 2], value.z);
+    }
+
+    pub fn splat(&self, x: usize, y: usize) -> DVec3 {
+        let i = 3 * (y * self.width + x);
+        let load = |i: usize| f64::from_bits(self.splats[i].load(Ordering::Relaxed));
+        DVec3::new(load(i), load(i + 1), load(i + 2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splats_from_many_threads() {
+        let film = Film::new(4, 2);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        film.add_splat(-0.9, 0.9, DVec3::new(1.0, 0.5, 0.25));
+                        film.add_splat(0.9, -0.9, DVec3::ONE);
+                    }
+                });
+            }
+        });
+        assert_eq!(film.splat(0, 0), DVec3::new(4000.0, 2000.0, 1000.0));
+        assert_eq!(film.splat(3, 1), DVec3::splat(4000.0));
+        assert_eq!(film.splat(1, 0), DVec3::ZERO);
+        assert_eq!(film.pixel(1.0, 0.0), None);
+    }
+}
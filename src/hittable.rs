@@ -1,22 +1,322 @@
-use std::rc::Rc;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-use glam::DVec3;
+use glam::{DVec2, DVec3};
 
-use crate::{ray::Ray, object::Object, bbox::BBox};
+use crate::{
+    ray::{Ray, RayDifferential},
+    object::Object,
+    bbox::BBox,
+    bsdf::{BsdfFlags, BsdfSample, Frame, reflect},
+    sampling::{sample_sphere, sample_triangle},
+    transform::AnimatedTransform,
+};
 
+#[derive(Clone)]
 pub struct HitRecord<'object> {
     pub p: DVec3,
+    /// geometric normal, always pointing out of the surface
     pub normal: DVec3,
+    /// normal used for shading, on the same side as `normal`
+    pub shading_normal: DVec3,
+    pub uv: DVec2,
+    pub dpdu: DVec3,
+    pub dpdv: DVec3,
+    /// change of the shading normal along u and v
+    pub dndu: DVec3,
+    pub dndv: DVec3,
+    /// change of position and uv towards the neighbouring pixels, zero without
+    /// ray differentials
+    pub dpdx: DVec3,
+    pub dpdy: DVec3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
     pub t: f64,
-    pub shape: Option<Rc<Box<dyn Hittable>>>,
+    /// time of the ray that found the hit, which rays leaving it inherit
+    pub time: f64,
+    /// true if the ray arrived from the side `normal` points to
+    pub front_face: bool,
+    /// index of the primitive inside the shape, e.g. a triangle of a mesh
+    pub primitive_id: usize,
+    /// index of the object inside the scene
+    pub instance_id: usize,
+    pub shape: Option<&'object dyn Hittable>,
     pub object: Option<&'object Object>,
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord>;
+impl<'object> HitRecord<'object> {
+    pub fn new(ray: &Ray, p: DVec3, normal: DVec3, t: f64, uv: DVec2, dpdu: DVec3, dpdv: DVec3) -> HitRecord<'object> {
+        HitRecord {
+            p,
+            normal,
+            shading_normal: normal,
+            uv,
+            dpdu,
+            dpdv,
+            dndu: DVec3::ZERO,
+            dndv: DVec3::ZERO,
+            dpdx: DVec3::ZERO,
+            dpdy: DVec3::ZERO,
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
+            t,
+            time: ray.time,
+            front_face: ray.direction.dot(normal) < 0.0,
+            primitive_id: 0,
+            instance_id: 0,
+            shape: None,
+            object: None,
+        }
+    }
+
+    /// bare record of a point sampled on a surface, facing along `normal`
+    pub fn on_surface(p: DVec3, normal: DVec3, uv: DVec2) -> HitRecord<'object> {
+        let ray = Ray::new(p + normal, -normal);
+        HitRecord::new(&ray, p, normal, 0.0, uv, DVec3::ZERO, DVec3::ZERO)
+    }
+
+    /// copy of the record that outlives the scene, without the shape and object
+    /// it was found on
+    pub fn detached(&self) -> HitRecord<'static> {
+        HitRecord {
+            p: self.p,
+            normal: self.normal,
+            shading_normal: self.shading_normal,
+            uv: self.uv,
+            dpdu: self.dpdu,
+            dpdv: self.dpdv,
+            dndu: self.dndu,
+            dndv: self.dndv,
+            dpdx: self.dpdx,
+            dpdy: self.dpdy,
+            dudx: self.dudx,
+            dvdx: self.dvdx,
+            dudy: self.dudy,
+            dvdy: self.dvdy,
+            t: self.t,
+            time: self.time,
+            front_face: self.front_face,
+            primitive_id: self.primitive_id,
+            instance_id: self.instance_id,
+            shape: None,
+            object: None,
+        }
+    }
+
+    /// orthonormal (tangent, bitangent, normal) frame around the shading normal,
+    /// with the tangent following dpdu where it is not degenerate
+    pub fn tangent_frame(&self) -> (DVec3, DVec3, DVec3) {
+        let n = self.shading_normal;
+        let mut tangent = self.dpdu - n * n.dot(self.dpdu);
+        if tangent.length_squared() < 1e-16 {
+            tangent = if n.x.abs() > 0.9 { DVec3::Y.cross(n) } else { DVec3::X.cross(n) };
+        }
+        let tangent = tangent.normalize();
+        let bitangent = n.cross(tangent);
+        (tangent, bitangent, n)
+    }
+
+    /// replaces the shading normal with `n`, flipped to the side of the geometric normal
+    pub fn set_shading_normal(&mut self, n: DVec3) {
+        let n = n.normalize();
+        self.shading_normal = if n.dot(self.normal) < 0.0 { -n } else { n };
+    }
+
+    /// tilts the shading normal towards the geometric one until the mirror
+    /// direction of `wo` stays above the surface, so that perturbed normals
+    /// facing away from the viewer don't leave black patches
+    pub fn bend_shading_normal(&mut self, wo: DVec3) {
+        const MIN_COSINE: f64 = 1e-2;
+        let wo = wo.normalize();
+        let flip = if wo.dot(self.normal) < 0.0 { -1.0 } else { 1.0 };
+        let ng = flip * self.normal;
+        let ns = flip * self.shading_normal;
+        let r = reflect(wo, ns);
+        let cosine = r.dot(ng);
+        if cosine >= MIN_COSINE {
+            return;
+        }
+        let r = (r + (MIN_COSINE - cosine) * ng).normalize();
+        let bent = (wo + r).normalize();
+        if bent.is_finite() {
+            self.shading_normal = flip * bent;
+        }
+    }
+
+    /// whether the geometric normal agrees with the shading normal on `wo` and
+    /// `wi` being reflection or transmission, light leaks through the surface
+    /// otherwise
+    pub fn shading_consistent(&self, wo: DVec3, wi: DVec3) -> bool {
+        let geometric = wo.dot(self.normal) * wi.dot(self.normal) > 0.0;
+        let shading = wo.dot(self.shading_normal) * wi.dot(self.shading_normal) > 0.0;
+        geometric == shading
+    }
+
+    /// completes the closest hit of `ray` on an object with its footprint and
+    /// the shading of its material. Left out of `Object::hit` so that it only
+    /// runs once per ray and not for every candidate found along the way.
+    pub fn finalize(&mut self, ray: &Ray) {
+        self.compute_differentials(ray);
+        if let Some(object) = self.object {
+            object.material.perturb_shading(-ray.direction, self);
+        }
+    }
+
+    /// estimates the screen space footprint of the hit from the differentials of
+    /// the ray that found it
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        let Some(d) = &ray.differential else {
+            return;
+        };
+        let n = self.normal;
+        let plane = n.dot(self.p);
+        let tx = (plane - n.dot(d.rx_origin)) / n.dot(d.rx_direction);
+        let ty = (plane - n.dot(d.ry_origin)) / n.dot(d.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return;
+        }
+        self.dpdx = d.rx_origin + tx * d.rx_direction - self.p;
+        self.dpdy = d.ry_origin + ty * d.ry_direction - self.p;
+
+        // least squares fit of dp = dpdu * du + dpdv * dv
+        let ata00 = self.dpdu.dot(self.dpdu);
+        let ata01 = self.dpdu.dot(self.dpdv);
+        let ata11 = self.dpdv.dot(self.dpdv);
+        let inv_det = 1.0 / (ata00 * ata11 - ata01 * ata01);
+        let inv_det = if inv_det.is_finite() { inv_det } else { 0.0 };
+        let solve = |dp: DVec3| {
+            let (b0, b1) = (self.dpdu.dot(dp), self.dpdv.dot(dp));
+            let du = ((ata11 * b0 - ata01 * b1) * inv_det).clamp(-1e8, 1e8);
+            let dv = ((ata00 * b1 - ata01 * b0) * inv_det).clamp(-1e8, 1e8);
+            (du, dv)
+        };
+        (self.dudx, self.dvdx) = solve(self.dpdx);
+        (self.dudy, self.dvdy) = solve(self.dpdy);
+    }
+
+    /// ray leaving the surface in `direction`, with its origin pushed off the
+    /// surface on the side it travels to
+    pub fn spawn_ray(&self, direction: DVec3) -> Ray {
+        let offset = self.normal * 1e-7 * (1.0 + self.p.abs().max_element());
+        let origin = if direction.dot(self.normal) > 0.0 { self.p + offset } else { self.p - offset };
+        Ray::new(origin, direction).with_time(self.time)
+    }
+
+    /// shadow ray towards a point `distance` away in direction `wi`, stopping
+    /// just short of it
+    pub fn spawn_shadow_ray(&self, wi: DVec3, distance: f64) -> Ray {
+        let mut ray = self.spawn_ray(wi);
+        ray.max_t = distance * (1.0 - 1e-4);
+        ray
+    }
+
+    /// ray continuing a path along a BSDF sample, tracking differentials through
+    /// specular reflection and refraction
+    pub fn spawn_ray_with_differentials(&self, ray: &Ray, sample: &BsdfSample) -> Ray {
+        let wi = sample.wi;
+        let mut spawned = self.spawn_ray(wi);
+        let Some(d) = &ray.differential else {
+            return spawned;
+        };
+        if !sample.flags.is_specular() || self.dpdx == DVec3::ZERO {
+            return spawned;
+        }
+        let wo = -ray.direction.normalize();
+        let mut n = self.shading_normal;
+        let mut dndx = self.dndu * self.dudx + self.dndv * self.dvdx;
+        let mut dndy = self.dndu * self.dudy + self.dndv * self.dvdy;
+        let dwodx = -d.rx_direction - wo;
+        let dwody = -d.ry_direction - wo;
+        let (rx_direction, ry_direction) = if sample.flags.contains(BsdfFlags::TRANSMISSION) {
+            if wo.dot(n) < 0.0 {
+                n = -n;
+                dndx = -dndx;
+                dndy = -dndy;
+            }
+            // relative index of refraction n_i / n_t along the path
+            let eta = 1.0 / sample.eta;
+            let dwo_dot_n_dx = dwodx.dot(n) + wo.dot(dndx);
+            let dwo_dot_n_dy = dwody.dot(n) + wo.dot(dndy);
+            let mu = eta * wo.dot(n) - wi.dot(n).abs();
+            let dmu = |dwo_dot_n: f64| (eta - eta * eta * wo.dot(n) / wi.dot(n).abs()) * dwo_dot_n;
+            (
+                wi - eta * dwodx + (mu * dndx + dmu(dwo_dot_n_dx) * n),
+                wi - eta * dwody + (mu * dndy + dmu(dwo_dot_n_dy) * n),
+            )
+        } else {
+            let dwo_dot_n_dx = dwodx.dot(n) + wo.dot(dndx);
+            let dwo_dot_n_dy = dwody.dot(n) + wo.dot(dndy);
+            (
+                wi - dwodx + 2.0 * (wo.dot(n) * dndx + dwo_dot_n_dx * n),
+                wi - dwody + 2.0 * (wo.dot(n) * dndy + dwo_dot_n_dy * n),
+            )
+        };
+        spawned.differential = Some(RayDifferential {
+            rx_origin: self.p + self.dpdx,
+            rx_direction,
+            ry_origin: self.p + self.dpdy,
+            ry_direction,
+        });
+        spawned
+    }
+}
+
+/// A point sampled on a shape, with its density either per unit area or per
+/// unit solid angle as seen from a reference point.
+pub struct ShapeSample {
+    pub hit: HitRecord<'static>,
+    pub pdf: f64,
+}
+
+pub trait Hittable : Send + Sync {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>>;
     fn bbox(&self) -> BBox {
         BBox::default()
     }
+
+    /// surface area, zero for shapes that can't be sampled
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// uniformly distributed point on the surface, pdf per unit area
+    fn sample_area(&self, _u: (f64, f64)) -> Option<ShapeSample> {
+        None
+    }
+
+    /// point on the surface as seen from `reference`, pdf per unit solid angle
+    fn sample_solid_angle(&self, reference: DVec3, u: (f64, f64)) -> Option<ShapeSample> {
+        let mut sample = self.sample_area(u)?;
+        let wi = sample.hit.p - reference;
+        let distance_squared = wi.length_squared();
+        let cosine = sample.hit.normal.dot(wi).abs() / distance_squared.sqrt();
+        if distance_squared == 0.0 || cosine == 0.0 {
+            return None;
+        }
+        sample.pdf *= distance_squared / cosine;
+        Some(sample)
+    }
+
+    /// density `sample_solid_angle` picks direction `wi` from `reference` with
+    fn pdf_solid_angle(&self, reference: DVec3, wi: DVec3) -> f64 {
+        let area = self.area();
+        if area == 0.0 {
+            return 0.0;
+        }
+        let Some(hit) = self.hit(&Ray::new(reference, wi)) else {
+            return 0.0;
+        };
+        let distance_squared = (hit.p - reference).length_squared();
+        let cosine = hit.normal.dot(wi.normalize()).abs();
+        if cosine == 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cosine * area)
+    }
 }
 
 pub struct Sphere {
@@ -29,10 +329,15 @@ impl Sphere {
         Sphere { center, radius }
     }
 
+    fn uv(&self, normal: DVec3) -> DVec2 {
+        let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+        let phi = (-normal.z).atan2(normal.x) + PI;
+        DVec2::new(phi / (2.0 * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(ray.direction);
@@ -52,7 +357,20 @@ impl Hittable for Sphere {
         let t = root;
         let p = ray.at(t);
         let normal = (p - self.center) / self.radius;
-        Some(HitRecord { p, normal, t, object: None, shape: None})
+        let local = p - self.center;
+        let uv = self.uv(normal);
+        // v is theta / pi
+        let sin_theta = (uv.y * PI).sin().max(1e-8);
+        let dpdu = 2.0 * PI * DVec3::new(local.z, 0.0, -local.x);
+        let dpdv = PI * DVec3::new(
+            -local.x * local.y / (self.radius * sin_theta),
+            self.radius * sin_theta,
+            -local.z * local.y / (self.radius * sin_theta),
+        );
+        let mut record = HitRecord::new(ray, p, normal, t, uv, dpdu, dpdv);
+        record.dndu = dpdu / self.radius;
+        record.dndv = dpdv / self.radius;
+        Some(record)
     }
 
     fn bbox(&self) -> BBox {
@@ -61,10 +379,282 @@ impl Hittable for Sphere {
             self.center + DVec3::splat(self.radius + 0.0001),
         )
     }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> Option<ShapeSample> {
+        let normal = sample_sphere(u.0, u.1);
+        let p = self.center + self.radius * normal;
+        Some(ShapeSample { hit: HitRecord::on_surface(p, normal, self.uv(normal)), pdf: 1.0 / self.area() })
+    }
+
+    /// samples the cone of directions the sphere subtends from outside of it
+    fn sample_solid_angle(&self, reference: DVec3, u: (f64, f64)) -> Option<ShapeSample> {
+        let to_center = self.center - reference;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            let mut sample = self.sample_area(u)?;
+            let wi = sample.hit.p - reference;
+            let cosine = sample.hit.normal.dot(wi).abs() / wi.length();
+            if cosine == 0.0 {
+                return None;
+            }
+            sample.pdf *= wi.length_squared() / cosine;
+            return Some(sample);
+        }
+        let (cos_theta, sin_theta, one_minus_cos_theta_max) = cone_sample(radius_squared / distance_squared, u.0);
+
+        // point on the sphere the sampled direction hits first
+        let distance = distance_squared.sqrt();
+        let ds = distance * cos_theta - (radius_squared - distance_squared * sin_theta * sin_theta).max(0.0).sqrt();
+        let cos_alpha = ((distance_squared + radius_squared - ds * ds) / (2.0 * distance * self.radius)).clamp(-1.0, 1.0);
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let frame = Frame::from_normal(-to_center / distance);
+        let normal = frame.to_world(DVec3::new(sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha));
+        let p = self.center + self.radius * normal;
+        let pdf = 1.0 / (2.0 * PI * one_minus_cos_theta_max);
+        Some(ShapeSample { hit: HitRecord::on_surface(p, normal, self.uv(normal)), pdf })
+    }
+
+    fn pdf_solid_angle(&self, reference: DVec3, wi: DVec3) -> f64 {
+        let distance_squared = (self.center - reference).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            let Some(hit) = self.hit(&Ray::new(reference, wi)) else {
+                return 0.0;
+            };
+            let cosine = hit.normal.dot(wi.normalize()).abs();
+            if cosine == 0.0 {
+                return 0.0;
+            }
+            return (hit.p - reference).length_squared() / (cosine * self.area());
+        }
+        let sin2_theta_max = radius_squared / distance_squared;
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        let cos_theta = (self.center - reference).dot(wi) / (distance_squared * wi.length_squared()).sqrt();
+        if cos_theta < cos_theta_max {
+            return 0.0;
+        }
+        let (_, _, one_minus_cos_theta_max) = cone_sample(sin2_theta_max, 0.0);
+        1.0 / (2.0 * PI * one_minus_cos_theta_max)
+    }
+}
+
+/// samples cos theta uniformly within a cone of squared half angle sine
+/// `sin2_theta_max`, returning (cos theta, sin theta, 1 - cos theta max). Small
+/// cones go through sin^2 to keep their precision.
+fn cone_sample(sin2_theta_max: f64, u: f64) -> (f64, f64, f64) {
+    if sin2_theta_max < 0.00068523 {
+        let sin2_theta = sin2_theta_max * u;
+        let cos_theta = (1.0 - sin2_theta).sqrt();
+        (cos_theta, sin2_theta.sqrt(), sin2_theta_max / 2.0)
+    } else {
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+        let cos_theta = (1.0 - u) + u * cos_theta_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        (cos_theta, sin_theta, 1.0 - cos_theta_max)
+    }
+}
+
+/// Parallelogram spanned by the edges `u` and `v` from corner `q`, facing along u x v.
+pub struct Quad {
+    pub q: DVec3,
+    pub u: DVec3,
+    pub v: DVec3,
+}
+
+impl Quad {
+    pub fn new(q: DVec3, u: DVec3, v: DVec3) -> Quad {
+        Quad { q, u, v }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let n = self.u.cross(self.v);
+        let denominator = n.dot(ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = n.dot(self.q - ray.origin) / denominator;
+        if t <= ray.min_t || t >= ray.max_t {
+            return None;
+        }
+        let p = ray.at(t);
+        // coordinates of p in the (u, v) basis
+        let w = n / n.length_squared();
+        let offset = p - self.q;
+        let alpha = w.dot(offset.cross(self.v));
+        let beta = w.dot(self.u.cross(offset));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(HitRecord::new(ray, p, n.normalize(), t, DVec2::new(alpha, beta), self.u, self.v))
+    }
+
+    fn bbox(&self) -> BBox {
+        let corners = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let bbox = corners.iter().fold(BBox::default(), |b, &c| b.union(&BBox::new(c, c)));
+        BBox::new(bbox.min - DVec3::splat(0.0001), bbox.max + DVec3::splat(0.0001))
+    }
+
+    fn area(&self) -> f64 {
+        self.u.cross(self.v).length()
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> Option<ShapeSample> {
+        let p = self.q + u.0 * self.u + u.1 * self.v;
+        let normal = self.u.cross(self.v).normalize();
+        Some(ShapeSample { hit: HitRecord::on_surface(p, normal, DVec2::new(u.0, u.1)), pdf: 1.0 / self.area() })
+    }
+}
+
+/// Triangle facing along (p1 - p0) x (p2 - p0), with optional per vertex uvs.
+pub struct Triangle {
+    pub p: [DVec3; 3],
+    pub uv: [DVec2; 3],
+}
+
+impl Triangle {
+    pub fn new(p0: DVec3, p1: DVec3, p2: DVec3) -> Triangle {
+        Triangle { p: [p0, p1, p2], uv: [DVec2::ZERO, DVec2::X, DVec2::ONE] }
+    }
+
+    pub fn with_uvs(p: [DVec3; 3], uv: [DVec2; 3]) -> Triangle {
+        Triangle { p, uv }
+    }
+
+    fn normal(&self) -> DVec3 {
+        (self.p[1] - self.p[0]).cross(self.p[2] - self.p[0]).normalize()
+    }
+
+    fn interpolate_uv(&self, b0: f64, b1: f64) -> DVec2 {
+        b0 * self.uv[0] + b1 * self.uv[1] + (1.0 - b0 - b1) * self.uv[2]
+    }
+
+    /// (dpdu, dpdv) from the uv parameterization, any tangent frame if it is degenerate
+    fn partials(&self) -> (DVec3, DVec3) {
+        let duv02 = self.uv[0] - self.uv[2];
+        let duv12 = self.uv[1] - self.uv[2];
+        let dp02 = self.p[0] - self.p[2];
+        let dp12 = self.p[1] - self.p[2];
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        if determinant.abs() > 1e-12 {
+            let inv = 1.0 / determinant;
+            let dpdu = (duv12.y * dp02 - duv02.y * dp12) * inv;
+            let dpdv = (duv02.x * dp12 - duv12.x * dp02) * inv;
+            if dpdu.cross(dpdv).length_squared() > 0.0 {
+                return (dpdu, dpdv);
+            }
+        }
+        let frame = Frame::from_normal(self.normal());
+        (frame.s, frame.t)
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let e1 = self.p[1] - self.p[0];
+        let e2 = self.p[2] - self.p[0];
+        let pvec = ray.direction.cross(e2);
+        let determinant = e1.dot(pvec);
+        if determinant.abs() < 1e-14 {
+            return None;
+        }
+        let inv = 1.0 / determinant;
+        let tvec = ray.origin - self.p[0];
+        let b1 = tvec.dot(pvec) * inv;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = ray.direction.dot(qvec) * inv;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv;
+        if t <= ray.min_t || t >= ray.max_t {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+        let (dpdu, dpdv) = self.partials();
+        let p = b0 * self.p[0] + b1 * self.p[1] + b2 * self.p[2];
+        Some(HitRecord::new(ray, p, self.normal(), t, self.interpolate_uv(b0, b1), dpdu, dpdv))
+    }
+
+    fn bbox(&self) -> BBox {
+        let bbox = self.p.iter().fold(BBox::default(), |b, &c| b.union(&BBox::new(c, c)));
+        BBox::new(bbox.min - DVec3::splat(0.0001), bbox.max + DVec3::splat(0.0001))
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * (self.p[1] - self.p[0]).cross(self.p[2] - self.p[0]).length()
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> Option<ShapeSample> {
+        let (b0, b1) = sample_triangle(u.0, u.1);
+        let p = b0 * self.p[0] + b1 * self.p[1] + (1.0 - b0 - b1) * self.p[2];
+        let hit = HitRecord::on_surface(p, self.normal(), self.interpolate_uv(b0, b1));
+        Some(ShapeSample { hit, pdf: 1.0 / self.area() })
+    }
+}
+
+/// Shape carried from its own space into the scene by an `AnimatedTransform`,
+/// found by each ray where it is at the ray's time. Its bounds cover the whole
+/// motion, so a BVH built over it holds at every time. Moving shapes can't be
+/// sampled, so they can't be area lights either.
+pub struct Moving {
+    pub shape: Arc<dyn Hittable>,
+    pub motion: AnimatedTransform,
+    bounds: BBox,
+}
+
+impl Moving {
+    pub fn new(shape: Arc<dyn Hittable>, motion: AnimatedTransform) -> Moving {
+        let bounds = motion.motion_bounds(&shape.bbox());
+        Moving { shape, motion, bounds }
+    }
+}
+
+impl Hittable for Moving {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let transform = self.motion.at(ray.time);
+        // the direction keeps its scale, so distances along the ray carry over
+        let local = Ray {
+            origin: transform.point_to_local(ray.origin),
+            direction: transform.vector_to_local(ray.direction),
+            differential: None,
+            ..ray.clone()
+        };
+        let mut record = self.shape.hit(&local)?;
+        record.p = transform.point_to_world(record.p);
+        record.normal = transform.normal_to_world(record.normal).normalize();
+        record.shading_normal = transform.normal_to_world(record.shading_normal).normalize();
+        record.dpdu = transform.vector_to_world(record.dpdu);
+        record.dpdv = transform.vector_to_world(record.dpdv);
+        record.dndu = transform.normal_to_world(record.dndu);
+        record.dndv = transform.normal_to_world(record.dndv);
+        record.front_face = ray.direction.dot(record.normal) < 0.0;
+        Some(record)
+    }
+
+    fn bbox(&self) -> BBox {
+        self.bounds
+    }
 }
 
 pub struct HittableList {
-    pub objects: Vec<Rc<Box<dyn Hittable>>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HittableList {
@@ -72,14 +662,14 @@ impl HittableList {
         HittableList { objects: Vec::new() }
     }
 
-    pub fn add(&mut self, object: Rc<Box<dyn Hittable>>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
 }
 
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
         let mut closest_so_far = ray.max_t;
         let mut hit_record = None;
         for object in &self.objects {
@@ -103,4 +693,179 @@ impl Hittable for HittableList {
         }
         bbox
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_hit_record() {
+        let sphere = Sphere::new(DVec3::ZERO, 2.0);
+        let ray = Ray::new(DVec3::new(0.0, 0.0, 5.0), DVec3::new(0.0, 0.0, -1.0));
+        let record = sphere.hit(&ray).unwrap();
+        assert!(record.front_face);
+        assert!((record.t - 3.0).abs() < 1e-9);
+        assert!((record.uv - DVec2::new(0.25, 0.5)).length() < 1e-9);
+        assert!(record.dpdu.cross(record.dpdv).normalize().dot(record.normal) > 0.999);
+
+        let inside = Ray::new(DVec3::ZERO, DVec3::new(0.0, 0.0, -1.0));
+        let record = sphere.hit(&inside).unwrap();
+        assert!(!record.front_face);
+    }
+
+    #[test]
+    fn test_solid_angle_sampling() {
+        // seen from the origin, the second sphere contains it
+        let shapes: [Box<dyn Hittable>; 4] = [
+            Box::new(Sphere::new(DVec3::new(0.0, 0.0, 3.0), 1.5)),
+            Box::new(Sphere::new(DVec3::new(0.2, 0.0, 0.0), 1.0)),
+            Box::new(Quad::new(DVec3::new(-1.0, -0.5, 1.0), DVec3::new(2.0, 0.0, 0.5), DVec3::new(0.0, 1.5, 0.0))),
+            Box::new(Triangle::new(DVec3::new(-1.0, -1.0, 1.0), DVec3::new(1.0, -1.0, 1.0), DVec3::new(0.0, 1.0, 2.0))),
+        ];
+        let reference = DVec3::ZERO;
+        for shape in &shapes {
+            // the pdf integrates to one over the sphere of directions
+            let n = 400;
+            let mut integral = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let wi = sample_sphere((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                    integral += shape.pdf_solid_angle(reference, wi) * 4.0 * PI / (n * n) as f64;
+                }
+            }
+            assert!((integral - 1.0).abs() < 2e-2, "{integral}");
+
+            // and agrees with the density samples are reported with
+            for i in 0..16 {
+                let u = ((i as f64 + 0.5) / 16.0, (i as f64 * 0.618034) % 1.0);
+                let sample = shape.sample_solid_angle(reference, u).unwrap();
+                let wi = sample.hit.p - reference;
+                let pdf = shape.pdf_solid_angle(reference, wi);
+                assert!((pdf - sample.pdf).abs() < 1e-6 * pdf, "{pdf} != {}", sample.pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_moving_shape() {
+        use crate::transform::{AnimatedTransform, Interpolation};
+        use glam::DMat4;
+
+        // a sphere crossing the view from left to right over the unit interval
+        let motion = AnimatedTransform::new(
+            vec![(0.0, DMat4::from_translation(DVec3::new(-2.0, 0.0, 0.0))), (1.0, DMat4::from_translation(DVec3::new(2.0, 0.0, 0.0)))],
+            Interpolation::Linear,
+        );
+        let moving = Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 0.5)), motion);
+        let bounds = moving.bbox();
+        assert!((bounds.min - DVec3::new(-2.5, -0.5, -0.5)).length() < 1e-3, "{bounds:?}");
+        assert!((bounds.max - DVec3::new(2.5, 0.5, 0.5)).length() < 1e-3, "{bounds:?}");
+
+        let ray = |time| Ray::new(DVec3::new(0.25, 0.0, 5.0), -DVec3::Z).with_time(time);
+        assert!(moving.hit(&ray(0.0)).is_none());
+        assert!(moving.hit(&ray(1.0)).is_none());
+        let record = moving.hit(&ray(0.5)).unwrap();
+        assert_eq!(record.time, 0.5);
+        assert!((record.p - ray(0.5).at(record.t)).length() < 1e-9);
+        assert!((record.normal - record.p / 0.5).length() < 1e-9);
+        assert!(record.front_face);
+        // rays leaving the hit stay at its time
+        assert_eq!(record.spawn_ray(DVec3::Z).time, 0.5);
+
+        // scaling keeps distances along the ray and normals perpendicular
+        let squash = AnimatedTransform::fixed(DMat4::from_scale(DVec3::new(2.0, 1.0, 1.0)));
+        let ellipsoid = Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 1.0)), squash);
+        let oblique = Ray::new(DVec3::new(5.0, 5.0, 0.0), DVec3::new(-1.0, -1.2, 0.0));
+        let record = ellipsoid.hit(&oblique).unwrap();
+        assert!((record.p - oblique.at(record.t)).length() < 1e-9);
+        let local = record.p / DVec3::new(2.0, 1.0, 1.0);
+        assert!((local.length() - 1.0).abs() < 1e-9);
+        assert!(record.normal.dot(record.dpdu).abs() < 1e-9 && record.normal.dot(record.dpdv).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "area lights need a shape with an area")]
+    fn test_moving_shapes_cannot_emit() {
+        use crate::{material::Lambertian, object::Object, transform::AnimatedTransform};
+        use glam::DMat4;
+
+        let moving = Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 0.5)), AnimatedTransform::fixed(DMat4::IDENTITY));
+        Object::emissive(Arc::new(moving), Arc::new(Lambertian::new(DVec3::ZERO)), DVec3::ONE);
+    }
+
+    #[test]
+    fn test_bvh_over_moving_objects() {
+        use crate::{accel::BVH, material::Lambertian, scene::Scene, transform::{AnimatedTransform, Interpolation}};
+        use glam::DMat4;
+
+        let mut scene = Scene::new();
+        for i in 0..20 {
+            let start = DVec3::new(i as f64 - 10.0, 0.0, 0.0);
+            let motion = AnimatedTransform::new(
+                vec![
+                    (0.0, DMat4::from_translation(start)),
+                    (1.0, DMat4::from_rotation_z(i as f64 * 0.3) * DMat4::from_translation(start + DVec3::Y * 3.0)),
+                ],
+                Interpolation::Slerp,
+            );
+            let shape = Arc::new(Moving::new(Arc::new(Sphere::new(DVec3::ZERO, 0.4)), motion));
+            scene.add(Object::new(shape, Arc::new(Lambertian::new(DVec3::ONE))));
+        }
+        let bvh = BVH::new(&scene.objects);
+        let mut hits = 0;
+        for i in 0..2000 {
+            let (x, y, time) = ((i % 40) as f64 * 0.6 - 12.0, (i / 40) as f64 * 0.3 - 6.0, (i % 7) as f64 / 6.0);
+            let ray = Ray::new(DVec3::new(x, y, 5.0), -DVec3::Z).with_time(time);
+            let expected = scene.hit(&ray).map(|hit| hit.instance_id);
+            assert_eq!(bvh.hit(&ray).map(|hit| hit.instance_id), expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 50, "{hits}");
+    }
+
+    #[test]
+    fn test_only_the_closest_hit_is_shaded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::{accel::BVH, bsdf::BsdfFlags, material::Material, sampler::Sampler, scene::Scene};
+
+        /// black material counting how often it shades a hit
+        struct Counting(AtomicUsize);
+
+        impl Material for Counting {
+            fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+                BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE
+            }
+
+            fn eval(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> DVec3 {
+                DVec3::ZERO
+            }
+
+            fn sample(&self, _wo: DVec3, _hit: &HitRecord, _sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+                None
+            }
+
+            fn pdf(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> f64 {
+                0.0
+            }
+
+            fn perturb_shading(&self, _wo: DVec3, _hit: &mut HitRecord) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let counting = Arc::new(Counting(AtomicUsize::new(0)));
+        let mut scene = Scene::new();
+        for i in 0..10 {
+            let quad = Quad::new(DVec3::new(-1.0, -1.0, -(i as f64)), DVec3::new(2.0, 0.0, 0.0), DVec3::new(0.0, 2.0, 0.0));
+            scene.add(Object::new(Arc::new(quad), counting.clone()));
+        }
+        let ray = Ray::new(DVec3::new(0.0, 0.0, 5.0), -DVec3::Z);
+        let bvh = BVH::new(&scene.objects);
+        assert_eq!(bvh.hit(&ray).unwrap().instance_id, 0);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+        assert_eq!(scene.hit(&ray).unwrap().instance_id, 0);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 2);
+    }
+}
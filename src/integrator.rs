@@ -1,15 +1,54 @@
 use glam::DVec3;
 
-use crate::{ray::Ray, scene::Scene, sampler::Sampler, accel::Accel};
+use crate::{
+    camera::Camera,
+    film::Film,
+    ray::Ray,
+    scene::Scene,
+    sampler::Sampler,
+    accel::Accel,
+    hittable::HitRecord,
+    material::Material,
+    bsdf::BsdfFlags,
+    sampling::power_heuristic,
+};
+
+/// Everything a camera sample can reach: the scene, the camera it started from
+/// and the film strategies that land on other pixels splat onto.
+pub struct RenderContext<'a> {
+    pub scene: &'a Scene,
+    pub accel: &'a dyn Accel,
+    pub camera: &'a dyn Camera,
+    pub film: &'a Film,
+}
 
 pub trait Integrator : Send + Sync{
-    fn li(&self, ray: &Ray, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3;
+    /// radiance arriving along `ray`. `accel` is built over `scene.objects`, the
+    /// scene itself gives access to its lights.
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3;
+
+    /// radiance along a ray generated by `ctx.camera`. Integrators that also
+    /// carry light to other pixels splat it onto `ctx.film`.
+    fn li_camera(&self, ray: &Ray, ctx: &RenderContext, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        self.li(ray, ctx.scene, ctx.accel, sampler, depth)
+    }
+
+    /// called once before rendering, for integrators that trace something ahead
+    /// of the camera rays like photons. Those paths take their times from the
+    /// shutter of `camera`, like the camera rays do.
+    fn preprocess(&self, _scene: &Scene, _accel: &dyn Accel, _camera: &dyn Camera) {}
 }
 
 pub struct TestIntegrator {
     
 }
 
+impl Default for TestIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TestIntegrator {
     pub fn new() -> TestIntegrator {
         TestIntegrator {}
@@ -17,25 +56,385 @@ impl TestIntegrator {
 }
 
 impl Integrator for TestIntegrator {
-    fn li(&self, ray: &Ray, accel: &dyn Accel,sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
         if depth <= 0 {
             return DVec3::ZERO;
         }
         if let Some(record) = accel.hit(ray) {
             if let Some(object) = record.object {
+                let emitted = object.emitted(&record, -ray.direction);
                 let material = &object.material;
                 if let Some((scattered, attenuation)) = material.scatter(ray, &record, sampler) {
-                    return attenuation * self.li(&scattered, accel, sampler, depth - 1);
+                    return emitted + attenuation * self.li(&scattered, scene, accel, sampler, depth - 1);
                 }
+                return emitted;
             }
             return DVec3::ZERO;
         }
 
-        let unit_direction = ray.direction.normalize();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        (1.0 - t) * DVec3::new(1.0, 1.0, 1.0) + t * DVec3::new(0.5, 0.7, 1.0)
+        scene.le(ray)
     }   
 }
 
+/// Iterative path tracer. Every non-specular vertex samples one light with a
+/// shadow ray, and both that and the BSDF sample are weighted with the power
+/// heuristic. Paths are cut by Russian roulette after `rr_depth` bounces and
+/// never exceed `depth` bounces, so a depth of one gives direct lighting with
+/// both halves of its MIS estimate.
+pub struct PathIntegrator {
+    pub rr_depth: i32,
+}
+
+impl Default for PathIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PathIntegrator {
+    pub fn new() -> PathIntegrator {
+        PathIntegrator { rr_depth: 3 }
+    }
+}
+
+/// light arriving at `hit` from one uniformly picked light and scattered towards
+/// `wo`. With `mis` the sample is weighted against BSDF sampling finding the same
+/// light, otherwise it has to be the only way the light is found.
+pub(crate) fn sample_light(
+    scene: &Scene,
+    accel: &dyn Accel,
+    hit: &HitRecord,
+    wo: DVec3,
+    material: &dyn Material,
+    sampler: &mut dyn Sampler,
+    mis: bool,
+) -> DVec3 {
+    sample_light_at(
+        scene,
+        hit.p,
+        |wi| {
+            if !hit.shading_consistent(wo, wi) {
+                return DVec3::ZERO;
+            }
+            material.eval(wo, wi, hit) * wi.dot(hit.shading_normal).abs()
+        },
+        |wi| material.pdf(wo, wi, hit),
+        |wi, distance, _| {
+            if accel.hit(&hit.spawn_shadow_ray(wi, distance)).is_some() { DVec3::ZERO } else { DVec3::ONE }
+        },
+        sampler,
+        mis,
+    )
+}
+
+/// light arriving at `p` from one uniformly picked light, for any kind of
+/// scattering: `f` is the scattered fraction of light from a direction, cosine
+/// included, `pdf` the density of sampling that direction otherwise, and
+/// `transmittance` the fraction left after a shadow ray of the given direction
+/// and length, zero when it is blocked.
+pub(crate) fn sample_light_at(
+    scene: &Scene,
+    p: DVec3,
+    f: impl Fn(DVec3) -> DVec3,
+    pdf: impl Fn(DVec3) -> f64,
+    transmittance: impl FnOnce(DVec3, f64, &mut dyn Sampler) -> DVec3,
+    sampler: &mut dyn Sampler,
+    mis: bool,
+) -> DVec3 {
+    let Some((light, select_pdf)) = scene.pick_light(sampler.get_1d()) else {
+        return DVec3::ZERO;
+    };
+    let u = sampler.get_2d();
+    let Some(sample) = light.sample_li(p, u) else {
+        return DVec3::ZERO;
+    };
+    if sample.pdf <= 0.0 || sample.li == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+    let f = f(sample.wi);
+    if f == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+    let tr = transmittance(sample.wi, sample.distance, sampler);
+    if tr == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+    let light_pdf = select_pdf * sample.pdf;
+    if light.is_delta() || !mis {
+        return f * tr * sample.li / light_pdf;
+    }
+    let weight = power_heuristic(light_pdf, pdf(sample.wi));
+    f * tr * sample.li * weight / light_pdf
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        let mut radiance = DVec3::ZERO;
+        let mut beta = DVec3::ONE;
+        let mut ray = ray.clone();
+        // state of the previous vertex, to weight light that BSDF sampling finds
+        let mut specular_bounce = false;
+        let mut bsdf_pdf = 0.0;
+        let mut previous = DVec3::ZERO;
+        // radiance scaling by refraction, undone for Russian roulette
+        let mut eta_scale = 1.0;
+        let select_pdf = 1.0 / scene.lights.len().max(1) as f64;
+
+        for bounce in 0..=depth.max(0) {
+            let Some(hit) = accel.hit(&ray) else {
+                for light in &scene.lights {
+                    let le = light.le(&ray);
+                    if le == DVec3::ZERO {
+                        continue;
+                    }
+                    if bounce == 0 || specular_bounce {
+                        radiance += beta * le;
+                    } else {
+                        let weight = power_heuristic(bsdf_pdf, select_pdf * light.pdf_li(previous, ray.direction));
+                        radiance += beta * le * weight;
+                    }
+                }
+                break;
+            };
+            let Some(object) = hit.object else {
+                break;
+            };
+            let wo = -ray.direction.normalize();
+
+            let emitted = object.emitted(&hit, wo);
+            if emitted != DVec3::ZERO {
+                if bounce == 0 || specular_bounce {
+                    radiance += beta * emitted;
+                } else if let Some(light) = &object.area_light {
+                    let weight = power_heuristic(bsdf_pdf, select_pdf * light.pdf(previous, ray.direction));
+                    radiance += beta * emitted * weight;
+                }
+            }
+
+            // light found by the last BSDF sample still counts, but nothing scatters further
+            if bounce == depth {
+                break;
+            }
+
+            let material = object.material.as_ref();
+            if material.flags(&hit).is_non_specular() {
+                radiance += beta * sample_light(scene, accel, &hit, wo, material, sampler, true);
+            }
+
+            let Some(sample) = material.sample(wo, &hit, sampler) else {
+                break;
+            };
+            if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+                break;
+            }
+            beta *= sample.weight(hit.shading_normal);
+            specular_bounce = sample.flags.is_specular();
+            bsdf_pdf = sample.pdf;
+            if sample.flags.contains(BsdfFlags::TRANSMISSION) {
+                eta_scale *= sample.eta * sample.eta;
+            }
+            previous = hit.p;
+            ray = hit.spawn_ray_with_differentials(&ray, &sample);
+
+            let rr_beta = (beta * eta_scale).max_element();
+            if rr_beta < 1.0 && bounce >= self.rr_depth {
+                let q = 1.0 - rr_beta;
+                if sampler.get_1d() < q {
+                    break;
+                }
+                beta /= 1.0 - q;
+            }
+            if beta == DVec3::ZERO {
+                break;
+            }
+        }
+        radiance
+    }
+}
+
+/// Classic Whitted ray tracer for noise free previews. Surfaces are lit by the
+/// delta lights of the scene with hard shadows, perfectly specular materials
+/// spawn a ray for every direction they scatter into, and everything else is
+/// left to emission and the environment. Nothing is sampled, so every camera
+/// ray gives the same result.
+pub struct WhittedIntegrator {
+    /// specular branches that would carry less than this fraction of the light
+    /// back to the camera aren't traced, which keeps nested glass from doubling
+    /// the rays with every bounce
+    pub min_weight: f64,
+}
+
+impl Default for WhittedIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WhittedIntegrator {
+    pub fn new() -> WhittedIntegrator {
+        WhittedIntegrator { min_weight: 1e-3 }
+    }
+}
+
+impl WhittedIntegrator {
+    /// radiance along `ray`, which reaches the camera scaled by `weight`
+    fn trace(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, depth: i32, weight: DVec3) -> DVec3 {
+        let Some(hit) = accel.hit(ray) else {
+            return scene.le(ray);
+        };
+        let Some(object) = hit.object else {
+            return DVec3::ZERO;
+        };
+        let wo = -ray.direction.normalize();
+        let material = object.material.as_ref();
+        let mut radiance = object.emitted(&hit, wo);
+
+        for light in scene.lights.iter().filter(|light| light.is_delta()) {
+            let Some(sample) = light.sample_li(hit.p, (0.5, 0.5)) else {
+                continue;
+            };
+            if sample.li == DVec3::ZERO || !hit.shading_consistent(wo, sample.wi) {
+                continue;
+            }
+            let f = material.eval(wo, sample.wi, &hit) * sample.wi.dot(hit.shading_normal).abs();
+            if f != DVec3::ZERO && accel.hit(&hit.spawn_shadow_ray(sample.wi, sample.distance)).is_none() {
+                radiance += f * sample.li / sample.pdf;
+            }
+        }
 
+        if depth > 0 {
+            for sample in material.specular(wo, &hit) {
+                if !hit.shading_consistent(wo, sample.wi) {
+                    continue;
+                }
+                let f = sample.weight(hit.shading_normal);
+                if (weight * f).max_element() < self.min_weight {
+                    continue;
+                }
+                let scattered = hit.spawn_ray_with_differentials(ray, &sample);
+                radiance += f * self.trace(&scattered, scene, accel, depth - 1, weight * f);
+            }
+        }
+        radiance
+    }
+}
 
+impl Integrator for WhittedIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, _sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        self.trace(ray, scene, accel, depth, DVec3::ONE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        accel::BVH,
+        hittable::{Hittable, Quad, Sphere},
+        light::{GradientSky, PointLight},
+        material::{Dielectric, Lambertian, Metal},
+        object::Object,
+        sampler::RandomSampler,
+        test_scenes::{floor, square},
+    };
+
+    fn estimate(scene: &Scene, ray: &Ray, samples: usize) -> DVec3 {
+        let accel = BVH::new(&scene.objects);
+        let integrator = PathIntegrator::new();
+        let mut sampler = RandomSampler::seeded(7);
+        (0..samples).map(|_| integrator.li(ray, scene, &accel, &mut sampler, 8)).sum::<DVec3>() / samples as f64
+    }
+
+    #[test]
+    fn test_path_integrator_furnace() {
+        // a convex diffuse object under a uniform sky reflects albedo times the sky
+        let mut scene = Scene::new();
+        scene.set_environment(Some(Arc::new(GradientSky::new(DVec3::ONE, DVec3::ONE))));
+        let sphere = Arc::new(Sphere::new(DVec3::ZERO, 1.0)) as Arc<dyn Hittable>;
+        scene.add(Object::new(sphere, Arc::new(Lambertian::new(DVec3::splat(0.5)))));
+        let value = estimate(&scene, &Ray::new(DVec3::new(0.3, 0.2, 5.0), -DVec3::Z), 4000);
+        assert!((value - DVec3::splat(0.5)).abs().max_element() < 1e-2, "{value}");
+    }
+
+    #[test]
+    fn test_whitted_point_light_and_mirror() {
+        // a point light of intensity I at height h lights the diffuse floor right
+        // below it with albedo / pi * I / h^2, seen directly and in a mirror
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        let mirror = Arc::new(Quad::new(DVec3::new(5.0, -1.0, -5.0), DVec3::new(0.0, 0.0, 10.0), DVec3::new(0.0, 10.0, 0.0)));
+        scene.add(Object::new(mirror, Arc::new(Metal::new(DVec3::splat(0.8), 0.0))));
+        scene.add_light(Arc::new(PointLight::new(DVec3::new(0.0, 2.0, 0.0), DVec3::splat(8.0 * PI))));
+        let accel = BVH::new(&scene.objects);
+        let expected = DVec3::splat(0.5 / PI * 8.0 * PI / 4.0);
+
+        let integrator = WhittedIntegrator::new();
+        let direct = Ray::new(DVec3::new(-1.0, 1.0, 0.0), DVec3::new(1.0, -1.0, 0.0));
+        let value = integrator.li(&direct, &scene, &accel, &mut RandomSampler::seeded(1), 4);
+        assert!((value - expected).abs().max_element() < 1e-9, "{value}");
+        let again = integrator.li(&direct, &scene, &accel, &mut RandomSampler::seeded(2), 4);
+        assert_eq!(value, again);
+
+        // bounces off the mirror at (5, 5, 0) down to the same point
+        let ray = Ray::new(DVec3::new(2.0, 8.0, 0.0), DVec3::new(1.0, -1.0, 0.0));
+        let value = integrator.li(&ray, &scene, &accel, &mut RandomSampler::seeded(3), 4);
+        assert!((value - expected * 0.8).abs().max_element() < 1e-9, "{value}");
+    }
+
+    #[test]
+    fn test_whitted_prunes_faint_branches() {
+        // every glass surface splits a ray in two, so a glass ball inside
+        // another one only stays cheap at a high depth when faint internal
+        // reflections are dropped
+        let mut scene = Scene::new();
+        scene.set_environment(Some(Arc::new(GradientSky::new(DVec3::ONE, DVec3::ONE))));
+        for radius in [1.0, 0.5] {
+            let ball = Arc::new(Sphere::new(DVec3::ZERO, radius)) as Arc<dyn Hittable>;
+            scene.add(Object::new(ball, Arc::new(Dielectric::new(1.5))));
+        }
+        let accel = BVH::new(&scene.objects);
+        let ray = Ray::new(DVec3::new(0.2, 0.1, 5.0), -DVec3::Z);
+
+        let exhaustive = WhittedIntegrator { min_weight: 0.0 }.li(&ray, &scene, &accel, &mut RandomSampler::seeded(1), 8);
+        let pruned = WhittedIntegrator::new().li(&ray, &scene, &accel, &mut RandomSampler::seeded(1), 64);
+        assert!((pruned - exhaustive).abs().max_element() < 1e-2, "{pruned} vs {exhaustive}");
+    }
+
+    #[test]
+    fn test_path_integrator_small_area_light() {
+        // a sphere light of radius r at height h above a diffuse floor gives an
+        // irradiance of pi L (r / h)^2
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        let bulb = Arc::new(Sphere::new(DVec3::new(0.0, 2.0, 0.0), 0.5)) as Arc<dyn Hittable>;
+        scene.add(Object::emissive(bulb, Arc::new(Lambertian::new(DVec3::ZERO)), DVec3::splat(10.0)));
+        let ray = Ray::new(DVec3::new(-1.0, 0.5, 0.0), DVec3::new(1.0, -0.5, 0.0));
+        let value = estimate(&scene, &ray, 4000);
+        assert!((value - DVec3::splat(0.3125)).abs().max_element() < 1e-2, "{value}");
+    }
+
+    #[test]
+    fn test_path_integrator_direct_lighting() {
+        // a 20 x 20 ceiling light one unit above a diffuse floor, seen from the
+        // point below its center with a form factor of 4 / (2 pi) (2 a atan a)
+        // for a = 10 / sqrt(101). Direct lighting gets all of it, including the
+        // large share MIS gives to the light found by the last BSDF sample.
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        scene.add(Object::emissive(square(DVec3::Y, 20.0, false), Arc::new(Lambertian::new(DVec3::ZERO)), DVec3::ONE));
+        let accel = BVH::new(&scene.objects);
+        let a: f64 = 10.0 / 101.0f64.sqrt();
+        let expected = 0.5 * 4.0 / (2.0 * PI) * 2.0 * a * a.atan();
+
+        let ray = Ray::new(DVec3::new(-0.5, 0.5, 0.0), DVec3::new(1.0, -1.0, 0.0));
+        let mut sampler = RandomSampler::seeded(9);
+        let direct = (0..4000).map(|_| PathIntegrator::new().li(&ray, &scene, &accel, &mut sampler, 1)).sum::<DVec3>() / 4000.0;
+        assert!((direct - DVec3::splat(expected)).abs().max_element() < 1e-2, "{direct} vs {expected}");
+    }
+}
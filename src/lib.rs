@@ -11,4 +11,20 @@ pub mod sampling;
 pub mod object;
 pub mod accel;
 pub mod bbox;
-pub mod threadpool;
\ No newline at end of file
+pub mod threadpool;
+pub mod bsdf;
+pub mod texture;
+pub mod light;
+pub mod sky;
+pub mod film;
+pub mod bdpt;
+pub mod photon;
+pub mod vcm;
+pub mod mlt;
+pub mod aov;
+pub mod registry;
+pub mod medium;
+pub mod volpath;
+pub mod volume;
+#[cfg(test)]
+pub(crate) mod test_scenes;
@@ -0,0 +1,640 @@
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{DMat4, DVec2, DVec3};
+
+use crate::{
+    bbox::BBox,
+    bsdf::Frame,
+    hittable::{HitRecord, Hittable, ShapeSample},
+    ray::Ray,
+    sampling::{Distribution2D, sample_disk, sample_hemisphere_cosine, sample_sphere, sample_sphere_pdf},
+    texture::{Image, Texture, WrapMode, constant, luminance},
+    transform::Transform,
+};
+
+/// Incident light at a reference point, arriving from direction `wi`.
+pub struct LightSample {
+    pub wi: DVec3,
+    pub li: DVec3,
+    /// density per unit solid angle, 1 for delta lights
+    pub pdf: f64,
+    /// distance to the sampled point, infinite for lights at infinity
+    pub distance: f64,
+    /// surface normal at the sampled point, zero for lights without a surface
+    pub normal: DVec3,
+}
+
+/// Ray leaving a light, the start of a path traced from the light side.
+pub struct LeSample {
+    pub ray: Ray,
+    /// surface normal at the ray origin, zero for lights without a surface
+    pub normal: DVec3,
+    pub le: DVec3,
+    /// density of the origin per unit area, 1 for point lights
+    pub pdf_pos: f64,
+    /// density of the direction per unit solid angle, 1 for delta directions
+    pub pdf_dir: f64,
+}
+
+pub trait Light : Send + Sync {
+    /// lights described by a delta distribution can't be hit by rays and have
+    /// to be sampled explicitly
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// incident light at `reference` from a point sampled on the light
+    fn sample_li(&self, reference: DVec3, u: (f64, f64)) -> Option<LightSample>;
+
+    /// density `sample_li` picks `wi` with, zero for delta lights
+    fn pdf_li(&self, reference: DVec3, wi: DVec3) -> f64;
+
+    /// total emitted power, `bounds` is the extent of the scene lights at infinity cover
+    fn power(&self, bounds: &BBox) -> DVec3;
+
+    /// radiance lights at infinity send along `ray` when it escapes the scene
+    fn le(&self, _ray: &Ray) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    /// lights at infinity are reached by rays leaving the scene, and emit from
+    /// a disk just outside of `bounds` in `sample_le`
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    /// ray leaving the light, `u1` picks the origin and `u2` the direction.
+    /// Lights that can't start paths return `None`.
+    fn sample_le(&self, _bounds: &BBox, _u1: (f64, f64), _u2: (f64, f64)) -> Option<LeSample> {
+        None
+    }
+
+    /// (positional, directional) densities of `sample_le` generating `ray`
+    /// from a point with surface normal `normal`
+    fn pdf_le(&self, _bounds: &BBox, _ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+}
+
+/// emission of a light at infinity towards the scene, starting on a disk
+/// facing the sampled direction just outside of the bounding sphere
+pub fn sample_le_at_infinity(light: &dyn Light, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+    let (center, radius) = bounds.bounding_sphere();
+    let sample = light.sample_li(center, u2)?;
+    let frame = Frame::from_normal(sample.wi);
+    let disk = radius * sample_disk(u1.0, u1.1);
+    let origin = center + radius * sample.wi + frame.s * disk.x + frame.t * disk.y;
+    Some(LeSample {
+        ray: Ray::new(origin, -sample.wi),
+        normal: DVec3::ZERO,
+        le: sample.li,
+        pdf_pos: 1.0 / (PI * radius * radius),
+        pdf_dir: sample.pdf,
+    })
+}
+
+pub fn pdf_le_at_infinity(light: &dyn Light, bounds: &BBox, ray: &Ray) -> (f64, f64) {
+    let (center, radius) = bounds.bounding_sphere();
+    (1.0 / (PI * radius * radius), light.pdf_li(center, -ray.direction))
+}
+
+/// Uniform emitter over the surface of a shape, optionally modulated by a texture.
+pub struct DiffuseAreaLight {
+    pub shape: Arc<dyn Hittable>,
+    pub radiance: Arc<dyn Texture<DVec3>>,
+    pub scale: f64,
+    /// emit from the back of the surface as well
+    pub two_sided: bool,
+}
+
+impl DiffuseAreaLight {
+    pub fn new(shape: Arc<dyn Hittable>, radiance: DVec3) -> DiffuseAreaLight {
+        DiffuseAreaLight::textured(shape, constant(radiance))
+    }
+
+    /// panics for shapes without an area, which lights couldn't sample points on
+    pub fn textured(shape: Arc<dyn Hittable>, radiance: Arc<dyn Texture<DVec3>>) -> DiffuseAreaLight {
+        assert!(shape.area() > 0.0, "area lights need a shape with an area to sample, moving shapes and lists have none");
+        DiffuseAreaLight { shape, radiance, scale: 1.0, two_sided: false }
+    }
+
+    /// radiance leaving the surface point `hit` in direction `w`
+    pub fn l(&self, hit: &HitRecord, w: DVec3) -> DVec3 {
+        if !self.two_sided && hit.normal.dot(w) <= 0.0 {
+            return DVec3::ZERO;
+        }
+        self.scale * self.radiance.value(hit)
+    }
+
+    /// point on the light as seen from `reference`, pdf per unit solid angle
+    pub fn sample(&self, reference: DVec3, u: (f64, f64)) -> Option<ShapeSample> {
+        self.shape.sample_solid_angle(reference, u)
+    }
+
+    pub fn pdf(&self, reference: DVec3, wi: DVec3) -> f64 {
+        self.shape.pdf_solid_angle(reference, wi)
+    }
+}
+
+impl Light for DiffuseAreaLight {
+    fn sample_li(&self, reference: DVec3, u: (f64, f64)) -> Option<LightSample> {
+        let sample = self.sample(reference, u)?;
+        let to_light = sample.hit.p - reference;
+        let distance = to_light.length();
+        if distance == 0.0 || sample.pdf <= 0.0 {
+            return None;
+        }
+        let wi = to_light / distance;
+        let li = self.l(&sample.hit, -wi);
+        if li == DVec3::ZERO {
+            return None;
+        }
+        Some(LightSample { wi, li, pdf: sample.pdf, distance, normal: sample.hit.normal })
+    }
+
+    fn pdf_li(&self, reference: DVec3, wi: DVec3) -> f64 {
+        self.pdf(reference, wi)
+    }
+
+    fn power(&self, _bounds: &BBox) -> DVec3 {
+        // average the radiance texture over a stratified set of surface points
+        const N: usize = 8;
+        let mut radiance = DVec3::ZERO;
+        for i in 0..N {
+            for j in 0..N {
+                let u = ((i as f64 + 0.5) / N as f64, (j as f64 + 0.5) / N as f64);
+                if let Some(sample) = self.shape.sample_area(u) {
+                    radiance += self.radiance.value(&sample.hit);
+                }
+            }
+        }
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        sides * PI * self.scale * self.shape.area() * radiance / (N * N) as f64
+    }
+
+    /// cosine distributed directions around the normal, picking a side first if two sided
+    fn sample_le(&self, _bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        let sample = self.shape.sample_area(u1)?;
+        let normal = sample.hit.normal;
+        let (mut u, mut side) = (u2, normal);
+        if self.two_sided {
+            if u.0 < 0.5 {
+                u.0 *= 2.0;
+            } else {
+                u.0 = 2.0 * (u.0 - 0.5);
+                side = -normal;
+            }
+        }
+        let local = sample_hemisphere_cosine(u.0, u.1);
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        let pdf_dir = local.z / (PI * sides);
+        if pdf_dir <= 0.0 {
+            return None;
+        }
+        let w = Frame::from_normal(side).to_world(local);
+        let le = self.l(&sample.hit, w);
+        Some(LeSample { ray: sample.hit.spawn_ray(w), normal, le, pdf_pos: sample.pdf, pdf_dir })
+    }
+
+    fn pdf_le(&self, _bounds: &BBox, ray: &Ray, normal: DVec3) -> (f64, f64) {
+        let cosine = normal.dot(ray.direction.normalize());
+        let pdf_dir = if self.two_sided {
+            cosine.abs() / (2.0 * PI)
+        } else {
+            cosine.max(0.0) / PI
+        };
+        (1.0 / self.shape.area(), pdf_dir)
+    }
+}
+
+/// Isotropic point emitter.
+pub struct PointLight {
+    pub position: DVec3,
+    pub intensity: DVec3,
+}
+
+impl PointLight {
+    pub fn new(position: DVec3, intensity: DVec3) -> PointLight {
+        PointLight { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, reference: DVec3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - reference;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let li = self.intensity / (distance * distance);
+        Some(LightSample { wi: to_light / distance, li, pdf: 1.0, distance, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, _wi: DVec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, _bounds: &BBox) -> DVec3 {
+        4.0 * PI * self.intensity
+    }
+
+    fn sample_le(&self, _bounds: &BBox, _u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        let ray = Ray::new(self.position, sample_sphere(u2.0, u2.1));
+        Some(LeSample { ray, normal: DVec3::ZERO, le: self.intensity, pdf_pos: 1.0, pdf_dir: sample_sphere_pdf() })
+    }
+
+    fn pdf_le(&self, _bounds: &BBox, _ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        (0.0, sample_sphere_pdf())
+    }
+}
+
+/// Point emitter restricted to a cone around `direction`, fading out smoothly
+/// between the falloff start and the total width.
+pub struct SpotLight {
+    pub position: DVec3,
+    pub direction: DVec3,
+    pub intensity: DVec3,
+    pub cos_falloff_start: f64,
+    pub cos_total_width: f64,
+}
+
+impl SpotLight {
+    /// `total_width` and `falloff_start` are half angles of the cone in degrees
+    pub fn new(position: DVec3, target: DVec3, intensity: DVec3, total_width: f64, falloff_start: f64) -> SpotLight {
+        SpotLight {
+            position,
+            direction: (target - position).normalize(),
+            intensity,
+            cos_falloff_start: falloff_start.min(total_width).to_radians().cos(),
+            cos_total_width: total_width.to_radians().cos(),
+        }
+    }
+
+    /// fraction of the intensity emitted in direction `w`
+    fn falloff(&self, w: DVec3) -> f64 {
+        let cos_theta = w.dot(self.direction);
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let t = ((cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, reference: DVec3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - reference;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = to_light / distance;
+        let li = self.intensity * self.falloff(-wi) / (distance * distance);
+        if li == DVec3::ZERO {
+            return None;
+        }
+        Some(LightSample { wi, li, pdf: 1.0, distance, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, _wi: DVec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, _bounds: &BBox) -> DVec3 {
+        // the smoothstep falloff integrates to half of its angular range in cos theta
+        let solid_angle = 2.0 * PI * ((1.0 - self.cos_falloff_start) + (self.cos_falloff_start - self.cos_total_width) / 2.0);
+        solid_angle * self.intensity
+    }
+
+    /// uniform directions within the cone
+    fn sample_le(&self, _bounds: &BBox, _u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        let cos_theta = 1.0 - u2.0 * (1.0 - self.cos_total_width);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2.1;
+        let w = Frame::from_normal(self.direction).to_world(DVec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+        let le = self.intensity * self.falloff(w);
+        let pdf_dir = 1.0 / (2.0 * PI * (1.0 - self.cos_total_width));
+        Some(LeSample { ray: Ray::new(self.position, w), normal: DVec3::ZERO, le, pdf_pos: 1.0, pdf_dir })
+    }
+
+    fn pdf_le(&self, _bounds: &BBox, ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        if ray.direction.normalize().dot(self.direction) < self.cos_total_width {
+            return (0.0, 0.0);
+        }
+        (0.0, 1.0 / (2.0 * PI * (1.0 - self.cos_total_width)))
+    }
+}
+
+/// Light arriving from a single direction at infinity, like the sun.
+pub struct DirectionalLight {
+    /// direction the light travels in
+    pub direction: DVec3,
+    pub radiance: DVec3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: DVec3, radiance: DVec3) -> DirectionalLight {
+        DirectionalLight { direction: direction.normalize(), radiance }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, _reference: DVec3, _u: (f64, f64)) -> Option<LightSample> {
+        Some(LightSample { wi: -self.direction, li: self.radiance, pdf: 1.0, distance: f64::INFINITY, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, _wi: DVec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, bounds: &BBox) -> DVec3 {
+        let radius = 0.5 * bounds.diagonal().length();
+        PI * radius * radius * self.radiance
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        sample_le_at_infinity(self, bounds, u1, u2)
+    }
+
+    fn pdf_le(&self, bounds: &BBox, _ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        let (_, radius) = bounds.bounding_sphere();
+        (1.0 / (PI * radius * radius), 0.0)
+    }
+}
+
+/// Background blending from `horizon` below to `zenith` straight up.
+pub struct GradientSky {
+    pub horizon: DVec3,
+    pub zenith: DVec3,
+}
+
+impl GradientSky {
+    pub fn new(horizon: DVec3, zenith: DVec3) -> GradientSky {
+        GradientSky { horizon, zenith }
+    }
+
+    fn radiance(&self, direction: DVec3) -> DVec3 {
+        let t = 0.5 * (direction.normalize().y + 1.0);
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+impl Default for GradientSky {
+    fn default() -> Self {
+        GradientSky::new(DVec3::ONE, DVec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Light for GradientSky {
+    fn sample_li(&self, _reference: DVec3, u: (f64, f64)) -> Option<LightSample> {
+        let wi = sample_sphere(u.0, u.1);
+        Some(LightSample { wi, li: self.radiance(wi), pdf: sample_sphere_pdf(), distance: f64::INFINITY, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, _wi: DVec3) -> f64 {
+        sample_sphere_pdf()
+    }
+
+    fn power(&self, bounds: &BBox) -> DVec3 {
+        // y is uniform over the sphere, so the average radiance is the midpoint
+        let radius = 0.5 * bounds.diagonal().length();
+        PI * radius * radius * 4.0 * PI * 0.5 * (self.horizon + self.zenith)
+    }
+
+    fn le(&self, ray: &Ray) -> DVec3 {
+        self.radiance(ray.direction)
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        sample_le_at_infinity(self, bounds, u1, u2)
+    }
+
+    fn pdf_le(&self, bounds: &BBox, ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        pdf_le_at_infinity(self, bounds, ray)
+    }
+}
+
+/// Equirectangular environment map with +y up, importance sampled by
+/// luminance. Row 0 of the image is straight up.
+pub struct EnvironmentMap {
+    pub image: Image,
+    pub scale: f64,
+    /// rotation from the map's frame into the world
+    pub transform: Transform,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image, scale: f64) -> EnvironmentMap {
+        let (width, height) = (image.width, image.height);
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                func.push(luminance(image.pixels[y * width + x]) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&func, width, height);
+        EnvironmentMap { image, scale, transform: Transform::new(DMat4::IDENTITY), distribution }
+    }
+
+    /// loads an HDR, EXR or any other image the `image` crate reads, as linear values
+    pub fn load<P: AsRef<Path>>(path: P, scale: f64) -> image::ImageResult<EnvironmentMap> {
+        Ok(EnvironmentMap::new(Image::load(path, false)?, scale))
+    }
+
+    /// rotates the map further by `degrees` around the world up axis
+    pub fn rotate_y(&mut self, degrees: f64) {
+        self.transform = Transform::new(DMat4::from_rotation_y(degrees.to_radians()) * self.transform.matrix);
+    }
+
+    /// (u, v) of a world direction, v = 0 being straight up
+    pub fn direction_to_uv(&self, direction: DVec3) -> DVec2 {
+        let d = self.transform.vector_to_local(direction).normalize();
+        let phi = d.z.atan2(d.x).rem_euclid(2.0 * PI);
+        DVec2::new(phi / (2.0 * PI), d.y.clamp(-1.0, 1.0).acos() / PI)
+    }
+
+    pub fn uv_to_direction(&self, uv: DVec2) -> DVec3 {
+        let (phi, theta) = (2.0 * PI * uv.x, PI * uv.y);
+        let local = DVec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        self.transform.vector_to_world(local).normalize()
+    }
+
+    fn radiance(&self, uv: DVec2) -> DVec3 {
+        // around the horizon the map wraps, only the poles are edges
+        self.scale * self.image.bilerp_axes(DVec2::new(uv.x, 1.0 - uv.y), WrapMode::Repeat, WrapMode::Clamp)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample_li(&self, _reference: DVec3, u: (f64, f64)) -> Option<LightSample> {
+        let (uv, map_pdf) = self.distribution.sample_continuous(u);
+        let sin_theta = (PI * uv.y).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        let pdf = map_pdf / (2.0 * PI * PI * sin_theta);
+        Some(LightSample { wi: self.uv_to_direction(uv), li: self.radiance(uv), pdf, distance: f64::INFINITY, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, wi: DVec3) -> f64 {
+        let uv = self.direction_to_uv(wi);
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn power(&self, bounds: &BBox) -> DVec3 {
+        let (width, height) = (self.image.width, self.image.height);
+        let mut radiant_intensity = DVec3::ZERO;
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                radiant_intensity += self.image.pixels[y * width + x] * sin_theta;
+            }
+        }
+        let solid_angle = 2.0 * PI * PI / (width * height) as f64;
+        let radius = 0.5 * bounds.diagonal().length();
+        PI * radius * radius * self.scale * solid_angle * radiant_intensity
+    }
+
+    fn le(&self, ray: &Ray) -> DVec3 {
+        self.radiance(self.direction_to_uv(ray.direction))
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        sample_le_at_infinity(self, bounds, u1, u2)
+    }
+
+    fn pdf_le(&self, bounds: &BBox, ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        pdf_le_at_infinity(self, bounds, ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Quad;
+
+    #[test]
+    fn test_punctual_lights() {
+        let point = PointLight::new(DVec3::new(0.0, 2.0, 0.0), DVec3::splat(8.0));
+        let sample = point.sample_li(DVec3::ZERO, (0.5, 0.5)).unwrap();
+        assert_eq!(sample.wi, DVec3::Y);
+        assert_eq!(sample.li, DVec3::splat(2.0));
+        assert_eq!(sample.distance, 2.0);
+
+        let spot = SpotLight::new(DVec3::new(0.0, 2.0, 0.0), DVec3::ZERO, DVec3::splat(8.0), 30.0, 20.0);
+        assert_eq!(spot.sample_li(DVec3::ZERO, (0.5, 0.5)).unwrap().li, DVec3::splat(2.0));
+        let edge = DVec3::new(2.0 * 25f64.to_radians().tan(), 0.0, 0.0);
+        let dimmed = spot.sample_li(edge, (0.5, 0.5)).unwrap().li.x;
+        assert!(dimmed > 0.0 && dimmed < 8.0 / edge.length_squared());
+        assert!(spot.sample_li(DVec3::new(4.0, 0.0, 0.0), (0.5, 0.5)).is_none());
+
+        let sun = DirectionalLight::new(DVec3::new(0.0, -1.0, 0.0), DVec3::ONE);
+        let sample = sun.sample_li(DVec3::new(5.0, 1.0, 3.0), (0.5, 0.5)).unwrap();
+        assert_eq!(sample.wi, DVec3::Y);
+        assert!(sample.distance.is_infinite());
+    }
+
+    #[test]
+    fn test_area_light_power() {
+        let quad = Arc::new(Quad::new(DVec3::ZERO, DVec3::new(2.0, 0.0, 0.0), DVec3::new(0.0, 0.0, -1.5)));
+        let mut light = DiffuseAreaLight::new(quad, DVec3::new(1.0, 2.0, 3.0));
+        let bounds = BBox::default();
+        assert!((light.power(&bounds) - 3.0 * PI * DVec3::new(1.0, 2.0, 3.0)).length() < 1e-9);
+        light.two_sided = true;
+        assert!((light.power(&bounds) - 6.0 * PI * DVec3::new(1.0, 2.0, 3.0)).length() < 1e-9);
+
+        // only the front, facing +y, emits
+        let above = light.sample_li(DVec3::new(1.0, 1.0, -0.5), (0.3, 0.6)).unwrap();
+        assert_eq!(above.li, DVec3::new(1.0, 2.0, 3.0));
+        light.two_sided = false;
+        assert!(light.sample_li(DVec3::new(1.0, -1.0, -0.5), (0.3, 0.6)).is_none());
+    }
+
+    #[test]
+    fn test_environment_map_sampling() {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height).map(|i| DVec3::splat(1.0 + (i % 5) as f64 + if i % width == 3 { 20.0 } else { 0.0 })).collect();
+        let mut map = EnvironmentMap::new(Image::new(width, height, pixels), 2.0);
+        map.rotate_y(40.0);
+        for i in 0..64 {
+            let u = ((i as f64 + 0.5) / 64.0, ((i as f64 + 0.5) * 0.618034) % 1.0);
+            let sample = map.sample_li(DVec3::ZERO, u).unwrap();
+            let pdf = map.pdf_li(DVec3::ZERO, sample.wi);
+            assert!((pdf - sample.pdf).abs() < 1e-6 * pdf, "{pdf} != {}", sample.pdf);
+            let le = map.le(&Ray::new(DVec3::ZERO, sample.wi));
+            assert!((le - sample.li).length() < 1e-6 * le.length());
+        }
+
+        // the pdf integrates to one over the sphere of directions
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let wi = sample_sphere((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                integral += map.pdf_li(DVec3::ZERO, wi) * 4.0 * PI / (n * n) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2, "{integral}");
+    }
+
+    #[test]
+    fn test_environment_map_seam_and_rotation() {
+        // red first and blue last column meet at phi = 0 without a seam
+        let (width, height) = (8, 4);
+        let pixels = (0..width * height)
+            .map(|i| match i % width {
+                0 => DVec3::X,
+                7 => DVec3::Z,
+                _ => DVec3::Y,
+            })
+            .collect();
+        let mut map = EnvironmentMap::new(Image::new(width, height, pixels), 1.0);
+        let seam = map.le(&Ray::new(DVec3::ZERO, DVec3::X));
+        assert!((seam - DVec3::new(0.5, 0.0, 0.5)).length() < 1e-9, "{seam}");
+        let (above, below) = (map.le(&Ray::new(DVec3::ZERO, DVec3::new(1.0, 0.0, 1e-6))), map.le(&Ray::new(DVec3::ZERO, DVec3::new(1.0, 0.0, -1e-6))));
+        assert!((above - below).length() < 1e-3, "{above} {below}");
+
+        // rotations add up
+        let direction = DVec3::new(0.3, 0.5, -0.8).normalize();
+        map.rotate_y(15.0);
+        map.rotate_y(25.0);
+        let once = {
+            let mut other = EnvironmentMap::new(Image::new(1, 1, vec![DVec3::ONE]), 1.0);
+            other.rotate_y(40.0);
+            other.direction_to_uv(direction)
+        };
+        assert!((map.direction_to_uv(direction) - once).length() < 1e-12);
+    }
+}
@@ -1,86 +1,366 @@
-use glam::DVec3;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-use crate::{ray::Ray, hittable::HitRecord, sampler::Sampler, sampling::{sample_hemisphere, sample_sphere}};
+use glam::{DVec2, DVec3};
 
-pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, DVec3)>;
+use crate::{
+    ray::Ray,
+    hittable::HitRecord,
+    sampler::Sampler,
+    sampling::{sample_hemisphere_cosine, sample_hemisphere_cosine_pdf, sample_sphere},
+    bsdf::{
+        BsdfFlags, BsdfSample, Frame, TrowbridgeReitz, abs_cos_theta, cos_theta, face_forward, fresnel_complex,
+        fresnel_dielectric, reflect, refract, roughness_to_alpha, same_hemisphere, schlick,
+    },
+    texture::{Texture, constant},
+};
+
+/// A material is the BSDF of a surface. Directions are in world space and
+/// both `wo` and `wi` point away from the hit point.
+pub trait Material : Send + Sync {
+    /// lobes the BSDF has at `hit`. Materials whose roughness is textured may be
+    /// smooth at some points and rough at others. After sampling, branch on
+    /// `BsdfSample::flags`, the lobe that was actually picked.
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags;
+
+    /// f(wo, wi), zero for specular lobes
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3;
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+    /// density `sample` picks `wi` with, zero for specular lobes
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64;
+
+    /// adjusts the shading normal of a fresh hit before the BSDF sees it, used
+    /// by normal and bump maps. `wo` points back along the incoming ray.
+    fn perturb_shading(&self, _wo: DVec3, _hit: &mut HitRecord) {}
+
+    /// index of refraction of the inside relative to the outside, for materials
+    /// whose transmission scales radiance by 1 / eta^2. Light traced from the
+    /// lights has to undo that scaling.
+    fn eta(&self) -> f64 {
+        1.0
+    }
+
+    /// every direction a perfectly specular material scatters `wo` into, with a
+    /// pdf of one so that the weights are the fraction of light going each way.
+    /// Empty for materials with any non-specular lobe.
+    fn specular(&self, _wo: DVec3, _hit: &HitRecord) -> Vec<BsdfSample> {
+        Vec::new()
+    }
+
+    /// samples the BSDF and returns the scattered ray with its f * cos / pdf weight
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, DVec3)> {
+        let wo = -ray.direction.normalize();
+        let sample = self.sample(wo, hit, sampler)?;
+        if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+            return None;
+        }
+        Some((hit.spawn_ray_with_differentials(ray, &sample), sample.weight(hit.shading_normal)))
+    }
+}
+
+/// Surface that only marks the boundary between two media and lets light pass
+/// straight through. Integrators without media support see it as black.
+pub struct Interface;
+
+impl Material for Interface {
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::NONE
+    }
+
+    fn eval(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    fn sample(&self, _wo: DVec3, _hit: &HitRecord, _sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        None
+    }
+
+    fn pdf(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> f64 {
+        0.0
+    }
 }
 
 pub struct Lambertian {
-    pub albedo: DVec3,
+    pub albedo: Arc<dyn Texture<DVec3>>,
 }
 
 impl Lambertian {
     pub fn new(albedo: DVec3) -> Lambertian {
+        Lambertian { albedo: constant(albedo) }
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture<DVec3>>) -> Lambertian {
         Lambertian { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, DVec3)> {
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        let frame = Frame::from_hit(hit);
+        if !same_hemisphere(frame.to_local(wo), frame.to_local(wi)) {
+            return DVec3::ZERO;
+        }
+        self.albedo.value(hit) / PI
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(wo);
         let (r1, r2) = sampler.get_2d();
-        let target = hit.p + hit.normal + sample_hemisphere(r1, r2);
-        let scattered = Ray::new(hit.p, (target - hit.p).normalize());
-        Some((scattered, self.albedo))
+        let mut wi = sample_hemisphere_cosine(r1, r2);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        let pdf = sample_hemisphere_cosine_pdf(abs_cos_theta(wi));
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi: frame.to_world(wi), f: self.albedo.value(hit) / PI, pdf, flags: BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE, eta: 1.0 })
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        let frame = Frame::from_hit(hit);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        sample_hemisphere_cosine_pdf(abs_cos_theta(wi))
     }
 }
 
-pub struct Metal {
-    pub albedo: DVec3,
-    pub fuzz: f64,
+/// Perturbs the shading normal of `material` with a tangent space normal map,
+/// stored as colors in [0, 1] with +z along the surface normal and +y along dpdv.
+pub struct NormalMap {
+    pub material: Arc<dyn Material>,
+    pub normals: Arc<dyn Texture<DVec3>>,
 }
 
-impl Metal {
-    pub fn new(albedo: DVec3, fuzz: f64) -> Metal {
-        Metal { albedo, fuzz }
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, normals: Arc<dyn Texture<DVec3>>) -> NormalMap {
+        NormalMap { material, normals }
     }
 }
 
-fn reflect(v: DVec3, n: DVec3) -> DVec3 {
-    let v = v.normalize();
-    let n = n.normalize();
-    ( v - 2.0 * v.dot(n) * n).normalize()
+impl Material for NormalMap {
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        self.material.flags(hit)
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        self.material.eval(wo, wi, hit)
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        self.material.sample(wo, hit, sampler)
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        self.material.pdf(wo, wi, hit)
+    }
+
+    fn eta(&self) -> f64 {
+        self.material.eta()
+    }
+
+    fn specular(&self, wo: DVec3, hit: &HitRecord) -> Vec<BsdfSample> {
+        self.material.specular(wo, hit)
+    }
+
+    fn perturb_shading(&self, wo: DVec3, hit: &mut HitRecord) {
+        self.material.perturb_shading(wo, hit);
+        let n = 2.0 * self.normals.value(hit) - DVec3::ONE;
+        let frame = Frame::from_hit(hit);
+        let n = frame.to_world(n);
+        if n.length_squared() == 0.0 || !n.is_finite() {
+            return;
+        }
+        hit.set_shading_normal(n);
+        hit.bend_shading_normal(wo);
+    }
 }
 
+/// Perturbs the shading normal of `material` as if the surface was displaced
+/// along its normal by `scale` times the height texture.
+pub struct BumpMap {
+    pub material: Arc<dyn Material>,
+    pub height: Arc<dyn Texture<f64>>,
+    pub scale: f64,
+}
 
-fn schlick(cosine: f64, eta: f64) -> f64 {
-    let r0 = (1.0 - eta) / (1.0 + eta);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, height: Arc<dyn Texture<f64>>, scale: f64) -> BumpMap {
+        BumpMap { material, height, scale }
+    }
 }
 
-fn refract(v: DVec3, n: DVec3, eta: f64) -> Option<DVec3> {
-    let v = v.normalize();
-    let n = n.normalize();
-    let n_dot_v = n.dot(v);
-    let k = 1.0 - eta * eta * (1.0 - n_dot_v * n_dot_v);
-    if k < 0.0 {
-        None
-    } else {
-        Some((eta * v - (eta * n_dot_v + k.sqrt()) * n).normalize())
+impl Material for BumpMap {
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        self.material.flags(hit)
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        self.material.eval(wo, wi, hit)
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        self.material.sample(wo, hit, sampler)
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        self.material.pdf(wo, wi, hit)
+    }
+
+    fn eta(&self) -> f64 {
+        self.material.eta()
+    }
+
+    fn specular(&self, wo: DVec3, hit: &HitRecord) -> Vec<BsdfSample> {
+        self.material.specular(wo, hit)
+    }
+
+    fn perturb_shading(&self, wo: DVec3, hit: &mut HitRecord) {
+        self.material.perturb_shading(wo, hit);
+        // finite differences over half the pixel footprint, or a small fixed
+        // step without ray differentials
+        let step = |dx: f64, dy: f64| {
+            let d = 0.5 * (dx.abs() + dy.abs());
+            if d == 0.0 { 0.0005 } else { d }
+        };
+        let du = step(hit.dudx, hit.dudy);
+        let dv = step(hit.dvdx, hit.dvdy);
+        let ns = hit.shading_normal;
+        let displaced = |offset: DVec2, dp: DVec3, dn: DVec3| {
+            let mut shifted = hit.clone();
+            shifted.p = hit.p + dp;
+            shifted.uv = hit.uv + offset;
+            shifted.shading_normal = (ns + dn).normalize();
+            self.scale * self.height.value(&shifted)
+        };
+        let displace = self.scale * self.height.value(hit);
+        let u_displace = displaced(DVec2::new(du, 0.0), du * hit.dpdu, du * hit.dndu);
+        let v_displace = displaced(DVec2::new(0.0, dv), dv * hit.dpdv, dv * hit.dndv);
+        let dpdu = hit.dpdu + (u_displace - displace) / du * ns + displace * hit.dndu;
+        let dpdv = hit.dpdv + (v_displace - displace) / dv * ns + displace * hit.dndv;
+        let n = dpdu.cross(dpdv);
+        if n.length_squared() == 0.0 || !n.is_finite() {
+            return;
+        }
+        hit.set_shading_normal(n);
+        hit.bend_shading_normal(wo);
     }
 }
 
+/// Mirror reflection perturbed by a uniformly sampled point on a sphere of
+/// radius `fuzz` around the reflected direction.
+pub struct Metal {
+    pub albedo: Arc<dyn Texture<DVec3>>,
+    pub fuzz: f64,
+}
 
+impl Metal {
+    pub fn new(albedo: DVec3, fuzz: f64) -> Metal {
+        Metal { albedo: constant(albedo), fuzz }
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture<DVec3>>, fuzz: f64) -> Metal {
+        Metal { albedo, fuzz }
+    }
+}
+
+/// density of normalize(r + fuzz * s) in direction `d`, with `s` uniform on the
+/// unit sphere and `r`, `d` unit vectors
+fn fuzz_pdf(r: DVec3, fuzz: f64, d: DVec3) -> f64 {
+    let b = d.dot(r);
+    let discriminant = b * b - 1.0 + fuzz * fuzz;
+    if discriminant < 0.0 {
+        return 0.0;
+    }
+    let sqrtd = discriminant.sqrt();
+    let mut density = 0.0;
+    for t in [b - sqrtd, b + sqrtd] {
+        if t <= 0.0 {
+            continue;
+        }
+        let n = (t * d - r) / fuzz;
+        let cosine = d.dot(n).abs();
+        if cosine > 1e-12 {
+            density += t * t / cosine;
+        }
+    }
+    density / (4.0 * PI * fuzz * fuzz)
+}
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, DVec3)> {
-        if hit.normal.dot(ray.direction) > 0.0 {
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        if self.fuzz == 0.0 {
+            BsdfFlags::REFLECTION | BsdfFlags::SPECULAR
+        } else {
+            BsdfFlags::REFLECTION | BsdfFlags::GLOSSY
+        }
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        let pdf = self.pdf(wo, wi, hit);
+        if pdf == 0.0 {
+            return DVec3::ZERO;
+        }
+        let frame = Frame::from_hit(hit);
+        self.albedo.value(hit) * pdf / abs_cos_theta(frame.to_local(wi))
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(wo);
+        if wo.z <= 0.0 {
             return None;
         }
-        
-        let reflected = reflect(ray.direction, hit.normal);
+        let reflected = reflect(wo, DVec3::Z);
         let (r1, r2) = sampler.get_2d();
-        let scattered = Ray::new(hit.p, (reflected + self.fuzz * sample_sphere(r1, r2)).normalize());
-        if scattered.direction.dot(hit.normal) > 0.0 {
-            Some((scattered, self.albedo))
-        } else {
-            None
+        if self.fuzz == 0.0 {
+            let f = self.albedo.value(hit) / abs_cos_theta(reflected);
+            return Some(BsdfSample { wi: frame.to_world(reflected), f, pdf: 1.0, flags: self.flags(hit), eta: 1.0 });
         }
+        let wi = (reflected + self.fuzz * sample_sphere(r1, r2)).normalize();
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let pdf = fuzz_pdf(reflected, self.fuzz, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = self.albedo.value(hit) * pdf / abs_cos_theta(wi);
+        Some(BsdfSample { wi: frame.to_world(wi), f, pdf, flags: self.flags(hit), eta: 1.0 })
     }
-}
 
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        if self.fuzz == 0.0 {
+            return 0.0;
+        }
+        let frame = Frame::from_hit(hit);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        fuzz_pdf(reflect(wo, DVec3::Z), self.fuzz, wi)
+    }
 
+    fn specular(&self, wo: DVec3, hit: &HitRecord) -> Vec<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(wo);
+        if self.fuzz != 0.0 || wo.z <= 0.0 {
+            return Vec::new();
+        }
+        let wi = reflect(wo, DVec3::Z);
+        let f = self.albedo.value(hit) / abs_cos_theta(wi);
+        vec![BsdfSample { wi: frame.to_world(wi), f, pdf: 1.0, flags: self.flags(hit), eta: 1.0 }]
+    }
+}
 
 pub struct Dielectric {
     pub ref_idx: f64,
@@ -93,41 +373,817 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, DVec3)> {
-        let attenuation = DVec3::new(1.0, 1.0, 1.0);
-        let front_face = ray.direction.dot(hit.normal) < 0.0;
-        let normal = if front_face { hit.normal } else { -hit.normal };
-        let eta = if front_face { 1.0 / self.ref_idx } else { self.ref_idx };
-        if let Some(refract_dir) = refract(ray.direction, normal, eta) {
-            let r = sampler.get_1d();
-            let reflect_prob = schlick(-ray.direction.dot(normal).min(1.0), eta);
-            if r < reflect_prob {
-                let reflected = reflect(ray.direction, normal);
-                let scattered = Ray::new(hit.p, reflected);
-                return Some((scattered, attenuation));
-            } else {
-                let scattered = Ray::new(hit.p, refract_dir);
-                return Some((scattered, attenuation));
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR
+    }
+
+    fn eval(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> DVec3 {
+        DVec3::ZERO
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(wo);
+        let entering = wo.z > 0.0;
+        let normal = if entering { DVec3::Z } else { -DVec3::Z };
+        let eta = if entering { 1.0 / self.ref_idx } else { self.ref_idx };
+        let refracted = refract(wo, normal, eta);
+        let reflect_prob = match refracted {
+            Some(_) => schlick(wo.dot(normal).min(1.0), eta),
+            None => 1.0,
+        };
+        let r = sampler.get_1d();
+        let (wi, pdf, flags) = match refracted {
+            Some(wt) if r >= reflect_prob => (wt, 1.0 - reflect_prob, BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR),
+            _ => (reflect(wo, normal), reflect_prob, BsdfFlags::REFLECTION | BsdfFlags::SPECULAR),
+        };
+        if abs_cos_theta(wi) == 0.0 {
+            return None;
+        }
+        let f = DVec3::splat(pdf / abs_cos_theta(wi));
+        let eta = if flags.contains(BsdfFlags::TRANSMISSION) { 1.0 / eta } else { 1.0 };
+        Some(BsdfSample { wi: frame.to_world(wi), f, pdf, flags, eta })
+    }
+
+    fn pdf(&self, _wo: DVec3, _wi: DVec3, _hit: &HitRecord) -> f64 {
+        0.0
+    }
+
+    fn specular(&self, wo: DVec3, hit: &HitRecord) -> Vec<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(wo);
+        let entering = wo.z > 0.0;
+        let normal = if entering { DVec3::Z } else { -DVec3::Z };
+        let eta = if entering { 1.0 / self.ref_idx } else { self.ref_idx };
+        let refracted = refract(wo, normal, eta);
+        let reflectance = match refracted {
+            Some(_) => schlick(wo.dot(normal).min(1.0), eta),
+            None => 1.0,
+        };
+        let mut samples = Vec::new();
+        let reflected = reflect(wo, normal);
+        if reflectance > 0.0 && abs_cos_theta(reflected) > 0.0 {
+            let f = DVec3::splat(reflectance / abs_cos_theta(reflected));
+            let flags = BsdfFlags::REFLECTION | BsdfFlags::SPECULAR;
+            samples.push(BsdfSample { wi: frame.to_world(reflected), f, pdf: 1.0, flags, eta: 1.0 });
+        }
+        if let Some(wt) = refracted.filter(|wt| reflectance < 1.0 && abs_cos_theta(*wt) > 0.0) {
+            let f = DVec3::splat((1.0 - reflectance) / abs_cos_theta(wt));
+            let flags = BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR;
+            samples.push(BsdfSample { wi: frame.to_world(wt), f, pdf: 1.0, flags, eta: 1.0 / eta });
+        }
+        samples
+    }
+}
+
+/// GGX microfacet conductor with a complex index of refraction eta + i k.
+pub struct Conductor {
+    pub eta: DVec3,
+    pub k: DVec3,
+    /// roughness along dpdu
+    pub roughness_u: Arc<dyn Texture<f64>>,
+    /// roughness along dpdv
+    pub roughness_v: Arc<dyn Texture<f64>>,
+}
+
+impl Conductor {
+    pub fn new(eta: DVec3, k: DVec3, roughness: f64) -> Conductor {
+        let roughness = constant(roughness);
+        Conductor { eta, k, roughness_u: roughness.clone(), roughness_v: roughness }
+    }
+
+    pub fn anisotropic(eta: DVec3, k: DVec3, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor { eta, k, roughness_u: constant(roughness_u), roughness_v: constant(roughness_v) }
+    }
+
+    fn distribution(&self, hit: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(
+            roughness_to_alpha(self.roughness_u.value(hit)),
+            roughness_to_alpha(self.roughness_v.value(hit)),
+        )
+    }
+
+    /// conductor with the measured (eta, k) of a metal at the RGB primaries, one of
+    /// "gold", "silver", "copper" or "aluminium"
+    pub fn named(name: &str, roughness: f64) -> Option<Conductor> {
+        let (eta, k) = match name {
+            "gold" => (DVec3::new(0.143, 0.374, 1.442), DVec3::new(3.983, 2.385, 1.603)),
+            "silver" => (DVec3::new(0.155, 0.116, 0.138), DVec3::new(4.828, 3.122, 2.147)),
+            "copper" => (DVec3::new(0.200, 0.924, 1.102), DVec3::new(3.912, 2.452, 2.142)),
+            "aluminium" => (DVec3::new(1.657, 0.880, 0.521), DVec3::new(9.224, 6.270, 4.837)),
+            _ => return None,
+        };
+        Some(Conductor::new(eta, k, roughness))
+    }
+}
+
+impl Material for Conductor {
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        if self.distribution(hit).effectively_smooth() {
+            BsdfFlags::REFLECTION | BsdfFlags::SPECULAR
+        } else {
+            BsdfFlags::REFLECTION | BsdfFlags::GLOSSY
+        }
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        let frame = Frame::from_hit(hit);
+        let distribution = self.distribution(hit);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if !same_hemisphere(wo, wi) || distribution.effectively_smooth() {
+            return DVec3::ZERO;
+        }
+        let (cos_o, cos_i) = (abs_cos_theta(wo), abs_cos_theta(wi));
+        let wm = wi + wo;
+        if cos_i == 0.0 || cos_o == 0.0 || wm.length_squared() == 0.0 {
+            return DVec3::ZERO;
+        }
+        let wm = wm.normalize();
+        let fresnel = fresnel_complex(wo.dot(wm).abs(), self.eta, self.k);
+        distribution.d(wm) * fresnel * distribution.g(wo, wi) / (4.0 * cos_i * cos_o)
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let distribution = self.distribution(hit);
+        let wo = frame.to_local(wo);
+        let u = sampler.get_2d();
+        if distribution.effectively_smooth() {
+            let wi = DVec3::new(-wo.x, -wo.y, wo.z);
+            let cos_i = abs_cos_theta(wi);
+            if cos_i == 0.0 {
+                return None;
+            }
+            let f = fresnel_complex(cos_i, self.eta, self.k) / cos_i;
+            return Some(BsdfSample { wi: frame.to_world(wi), f, pdf: 1.0, flags: BsdfFlags::REFLECTION | BsdfFlags::SPECULAR, eta: 1.0 });
+        }
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = distribution.sample_wm(wo, u);
+        let wi = reflect(wo, wm);
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        let pdf = distribution.pdf(wo, wm) / (4.0 * wo.dot(wm).abs());
+        let (cos_o, cos_i) = (abs_cos_theta(wo), abs_cos_theta(wi));
+        if cos_i == 0.0 || cos_o == 0.0 || pdf == 0.0 {
+            return None;
+        }
+        let fresnel = fresnel_complex(wo.dot(wm).abs(), self.eta, self.k);
+        let f = distribution.d(wm) * fresnel * distribution.g(wo, wi) / (4.0 * cos_i * cos_o);
+        Some(BsdfSample { wi: frame.to_world(wi), f, pdf, flags: BsdfFlags::REFLECTION | BsdfFlags::GLOSSY, eta: 1.0 })
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        let frame = Frame::from_hit(hit);
+        let distribution = self.distribution(hit);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if !same_hemisphere(wo, wi) || distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let wm = wo + wi;
+        if wm.length_squared() == 0.0 {
+            return 0.0;
+        }
+        let wm = face_forward(wm.normalize(), DVec3::Z);
+        distribution.pdf(wo, wm) / (4.0 * wo.dot(wm).abs())
+    }
+}
+
+/// GGX microfacet dielectric with reflection and transmission, `eta` is the index
+/// of refraction of the inside relative to the outside.
+pub struct RoughDielectric {
+    pub eta: f64,
+    pub roughness: Arc<dyn Texture<f64>>,
+}
+
+impl RoughDielectric {
+    pub fn new(eta: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric { eta, roughness: constant(roughness) }
+    }
+
+    fn lobe(&self, hit: &HitRecord) -> DielectricLobe {
+        let alpha = roughness_to_alpha(self.roughness.value(hit));
+        DielectricLobe { eta: self.eta, distribution: TrowbridgeReitz::new(alpha, alpha) }
+    }
+}
+
+/// the rough dielectric BSDF with its parameters resolved, in the local frame
+struct DielectricLobe {
+    eta: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl DielectricLobe {
+    /// generalized half vector of a reflection or refraction pair, facing +z, and
+    /// the relative index along the path
+    fn half_vector(&self, wo: DVec3, wi: DVec3) -> Option<(DVec3, f64)> {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        let reflect = cos_i * cos_o > 0.0;
+        let etap = if reflect { 1.0 } else if cos_o > 0.0 { self.eta } else { 1.0 / self.eta };
+        let wm = wi * etap + wo;
+        if cos_i == 0.0 || cos_o == 0.0 || wm.length_squared() == 0.0 {
+            return None;
+        }
+        let wm = face_forward(wm.normalize(), DVec3::Z);
+        // discard back facing microfacets
+        if wm.dot(wi) * cos_i < 0.0 || wm.dot(wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+
+    /// f(wo, wi) with both directions in the local shading frame
+    fn eval_local(&self, wo: DVec3, wi: DVec3) -> f64 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let Some((wm, etap)) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let fresnel = fresnel_dielectric(wo.dot(wm), self.eta);
+        let (cos_o, cos_i) = (cos_theta(wo), cos_theta(wi));
+        if etap == 1.0 {
+            return self.distribution.d(wm) * self.distribution.g(wo, wi) * fresnel / (4.0 * cos_i * cos_o).abs();
+        }
+        let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2) * cos_i * cos_o;
+        let f = self.distribution.d(wm) * (1.0 - fresnel) * self.distribution.g(wo, wi)
+            * (wi.dot(wm) * wo.dot(wm) / denom).abs();
+        // radiance is compressed into the smaller solid angle on the denser side
+        f / (etap * etap)
+    }
+
+    /// samples wi in the local shading frame, returning (wi, f, pdf, lobe)
+    fn sample_local(&self, wo: DVec3, uc: f64, u: (f64, f64)) -> Option<(DVec3, f64, f64, BsdfFlags)> {
+        if self.distribution.effectively_smooth() {
+            let reflectance = fresnel_dielectric(cos_theta(wo), self.eta);
+            if uc < reflectance {
+                let wi = DVec3::new(-wo.x, -wo.y, wo.z);
+                return Some((wi, reflectance / abs_cos_theta(wi), reflectance, BsdfFlags::REFLECTION | BsdfFlags::SPECULAR));
+            }
+            let (n, etap) = if wo.z > 0.0 { (DVec3::Z, self.eta) } else { (-DVec3::Z, 1.0 / self.eta) };
+            let wi = refract(wo, n, 1.0 / etap)?;
+            let transmittance = 1.0 - reflectance;
+            let f = transmittance / abs_cos_theta(wi) / (etap * etap);
+            return Some((wi, f, transmittance, BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR));
+        }
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.eta);
+        let transmittance = 1.0 - reflectance;
+        if uc < reflectance {
+            let wi = reflect(wo, wm);
+            if !same_hemisphere(wo, wi) {
+                return None;
             }
+            let pdf = self.distribution.pdf(wo, wm) / (4.0 * wo.dot(wm).abs()) * reflectance;
+            let f = self.distribution.d(wm) * self.distribution.g(wo, wi) * reflectance
+                / (4.0 * cos_theta(wi) * cos_theta(wo)).abs();
+            return Some((wi, f, pdf, BsdfFlags::REFLECTION | BsdfFlags::GLOSSY));
+        }
+        let (n, etap) = if wo.dot(wm) > 0.0 { (wm, self.eta) } else { (-wm, 1.0 / self.eta) };
+        let wi = refract(wo, n, 1.0 / etap)?;
+        if same_hemisphere(wo, wi) || wi.z == 0.0 {
+            return None;
+        }
+        let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+        let dwm_dwi = wi.dot(wm).abs() / denom;
+        let pdf = self.distribution.pdf(wo, wm) * dwm_dwi * transmittance;
+        let f = transmittance * self.distribution.d(wm) * self.distribution.g(wo, wi)
+            * (wi.dot(wm) * wo.dot(wm) / (cos_theta(wi) * cos_theta(wo) * denom)).abs()
+            / (etap * etap);
+        Some((wi, f, pdf, BsdfFlags::TRANSMISSION | BsdfFlags::GLOSSY))
+    }
+
+    /// pdf(wo, wi) with both directions in the local shading frame
+    fn pdf_local(&self, wo: DVec3, wi: DVec3) -> f64 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let Some((wm, etap)) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.eta);
+        if etap == 1.0 {
+            return self.distribution.pdf(wo, wm) / (4.0 * wo.dot(wm).abs()) * reflectance;
+        }
+        let denom = (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+        let dwm_dwi = wi.dot(wm).abs() / denom;
+        self.distribution.pdf(wo, wm) * dwm_dwi * (1.0 - reflectance)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        if self.lobe(hit).distribution.effectively_smooth() {
+            BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR
         } else {
-            let reflected = reflect(ray.direction, normal);
-            let scattered = Ray::new(hit.p, reflected);
-            return Some((scattered, attenuation));
+            BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::GLOSSY
+        }
+    }
+
+    fn eta(&self) -> f64 {
+        self.eta
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        let frame = Frame::from_hit(hit);
+        DVec3::splat(self.lobe(hit).eval_local(frame.to_local(wo), frame.to_local(wi)))
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let wo = frame.to_local(wo);
+        let (wi, f, pdf, flags) = self.lobe(hit).sample_local(wo, uc, u)?;
+        let eta = match (flags.contains(BsdfFlags::TRANSMISSION), wo.z > 0.0) {
+            (false, _) => 1.0,
+            (true, true) => self.eta,
+            (true, false) => 1.0 / self.eta,
+        };
+        Some(BsdfSample { wi: frame.to_world(wi), f: DVec3::splat(f), pdf, flags, eta })
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        let frame = Frame::from_hit(hit);
+        self.lobe(hit).pdf_local(frame.to_local(wo), frame.to_local(wi))
+    }
+}
+
+/// Disney's principled BSDF: a Burley diffuse and sheen base, an anisotropic GGX
+/// specular lobe blending from dielectric to metal, a GTR1 clearcoat and a rough
+/// dielectric transmission lobe. Every parameter but `ior` is a texture.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture<DVec3>>,
+    pub metallic: Arc<dyn Texture<f64>>,
+    pub roughness: Arc<dyn Texture<f64>>,
+    pub specular: Arc<dyn Texture<f64>>,
+    pub specular_tint: Arc<dyn Texture<f64>>,
+    pub anisotropic: Arc<dyn Texture<f64>>,
+    pub sheen: Arc<dyn Texture<f64>>,
+    pub sheen_tint: Arc<dyn Texture<f64>>,
+    pub clearcoat: Arc<dyn Texture<f64>>,
+    pub clearcoat_gloss: Arc<dyn Texture<f64>>,
+    pub transmission: Arc<dyn Texture<f64>>,
+    pub ior: f64,
+}
+
+impl Principled {
+    /// a plastic-like material with the usual defaults for everything but the color
+    pub fn new(base_color: DVec3) -> Principled {
+        Principled {
+            base_color: constant(base_color),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            anisotropic: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    fn lobes(&self, hit: &HitRecord) -> PrincipledLobes {
+        let base_color = self.base_color.value(hit);
+        let metallic = self.metallic.value(hit).clamp(0.0, 1.0);
+        let roughness = self.roughness.value(hit).clamp(0.0, 1.0);
+        let transmission = self.transmission.value(hit).clamp(0.0, 1.0);
+        let luminance = base_color.dot(DVec3::new(0.2126, 0.7152, 0.0722));
+        let tint = if luminance > 0.0 { base_color / luminance } else { DVec3::ONE };
+
+        let aspect = (1.0 - 0.9 * self.anisotropic.value(hit).clamp(0.0, 1.0)).sqrt();
+        let alpha = roughness_to_alpha(roughness);
+        let specular = TrowbridgeReitz::new((alpha / aspect).max(1e-3), (alpha * aspect).max(1e-3));
+        let specular_color = self.specular.value(hit) * 0.08 * DVec3::ONE.lerp(tint, self.specular_tint.value(hit));
+        let gloss = self.clearcoat_gloss.value(hit).clamp(0.0, 1.0);
+
+        PrincipledLobes {
+            base_color,
+            roughness,
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            sheen: (1.0 - metallic) * self.sheen.value(hit) * DVec3::ONE.lerp(tint, self.sheen_tint.value(hit)),
+            specular,
+            specular_weight: 1.0 - transmission * (1.0 - metallic),
+            specular_f0: specular_color.lerp(base_color, metallic),
+            clearcoat_weight: 0.25 * self.clearcoat.value(hit).max(0.0),
+            clearcoat_alpha: 0.1 * (1.0 - gloss) + 0.001 * gloss,
+            transmission_weight: (1.0 - metallic) * transmission,
+            transmission: DielectricLobe { eta: self.ior, distribution: TrowbridgeReitz::new(alpha.max(1e-3), alpha.max(1e-3)) },
         }
     }
 }
 
+/// principled parameters resolved at a hit point
+struct PrincipledLobes {
+    base_color: DVec3,
+    roughness: f64,
+    diffuse_weight: f64,
+    sheen: DVec3,
+    specular: TrowbridgeReitz,
+    specular_weight: f64,
+    specular_f0: DVec3,
+    clearcoat_weight: f64,
+    clearcoat_alpha: f64,
+    transmission_weight: f64,
+    transmission: DielectricLobe,
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+/// Berry's GTR1 distribution used by the clearcoat
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+impl PrincipledLobes {
+    /// the surface is two sided unless light can enter it
+    fn opaque(&self) -> bool {
+        self.transmission_weight == 0.0
+    }
+
+    /// probabilities of sampling the diffuse, specular, clearcoat and transmission lobes
+    fn lobe_probabilities(&self, wo: DVec3) -> [f64; 4] {
+        if wo.z < 0.0 {
+            return [0.0, 0.0, 0.0, 1.0];
+        }
+        let weights = [self.diffuse_weight, self.specular_weight, self.clearcoat_weight, self.transmission_weight];
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3) -> DVec3 {
+        let (wo, wi) = if wo.z < 0.0 && self.opaque() { (-wo, -wi) } else { (wo, wi) };
+        if wo.z < 0.0 || !same_hemisphere(wo, wi) {
+            return self.transmission_weight * self.base_color * self.transmission.eval_local(wo, wi);
+        }
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        let wh = wo + wi;
+        if wh.length_squared() == 0.0 {
+            return DVec3::ZERO;
+        }
+        let wh = wh.normalize();
+        let cos_d = wi.dot(wh);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse = self.base_color / PI
+            * (1.0 + (fd90 - 1.0) * schlick_weight(cos_i))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+        let sheen = self.sheen * schlick_weight(cos_d);
+        let mut f = self.diffuse_weight * diffuse + sheen;
+
+        let fresnel = self.specular_f0 + (DVec3::ONE - self.specular_f0) * schlick_weight(cos_d);
+        f += self.specular_weight * self.specular.d(wh) * self.specular.g(wo, wi) * fresnel / (4.0 * cos_i * cos_o);
+
+        let clearcoat = TrowbridgeReitz::new(0.25, 0.25);
+        let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+        f += DVec3::splat(self.clearcoat_weight * gtr1(wh.z, self.clearcoat_alpha) * fresnel * clearcoat.g(wo, wi) / (4.0 * cos_i * cos_o));
+
+        f + DVec3::splat(self.transmission_weight * self.transmission.eval_local(wo, wi))
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3) -> f64 {
+        let (wo, wi) = if wo.z < 0.0 && self.opaque() { (-wo, -wi) } else { (wo, wi) };
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_probabilities(wo);
+        let mut pdf = transmission * self.transmission.pdf_local(wo, wi);
+        if wo.z < 0.0 || !same_hemisphere(wo, wi) {
+            return pdf;
+        }
+        pdf += diffuse * sample_hemisphere_cosine_pdf(wi.z);
+        let wh = wo + wi;
+        if wh.length_squared() == 0.0 {
+            return pdf;
+        }
+        let wh = wh.normalize();
+        pdf += specular * self.specular.pdf(wo, wh) / (4.0 * wo.dot(wh).abs());
+        pdf += clearcoat * gtr1(wh.z, self.clearcoat_alpha) * wh.z / (4.0 * wo.dot(wh).abs());
+        pdf
+    }
+
+    /// samples wi in the local frame, the value and density come from `eval` and `pdf`
+    fn sample(&self, wo: DVec3, uc: f64, u: (f64, f64)) -> Option<(DVec3, BsdfFlags)> {
+        let flip = wo.z < 0.0 && self.opaque();
+        let wo = if flip { -wo } else { wo };
+        let probabilities = self.lobe_probabilities(wo);
+        let mut uc = uc;
+        let mut lobe = 0;
+        while lobe < 3 && uc >= probabilities[lobe] {
+            uc -= probabilities[lobe];
+            lobe += 1;
+        }
+        let uc = (uc / probabilities[lobe]).min(1.0 - f64::EPSILON);
+        let (wi, flags) = match lobe {
+            0 => (sample_hemisphere_cosine(u.0, u.1), BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE),
+            1 => (reflect(wo, self.specular.sample_wm(wo, u)), BsdfFlags::REFLECTION | BsdfFlags::GLOSSY),
+            2 => {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos_h = ((1.0 - a2.powf(1.0 - u.0)) / (1.0 - a2)).max(0.0).sqrt();
+                let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.1;
+                let wh = DVec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h);
+                (reflect(wo, wh), BsdfFlags::REFLECTION | BsdfFlags::GLOSSY)
+            }
+            _ => {
+                let (wi, _, _, flags) = self.transmission.sample_local(wo, uc, u)?;
+                (wi, flags)
+            }
+        };
+        if wi.z == 0.0 || (lobe < 3 && !same_hemisphere(wo, wi)) {
+            return None;
+        }
+        Some((if flip { -wi } else { wi }, flags))
+    }
+}
+
+impl Material for Principled {
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY
+    }
+
+    fn eta(&self) -> f64 {
+        self.ior
+    }
+
+    fn eval(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> DVec3 {
+        let frame = Frame::from_hit(hit);
+        self.lobes(hit).eval(frame.to_local(wo), frame.to_local(wi))
+    }
+
+    fn sample(&self, wo: DVec3, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::from_hit(hit);
+        let lobes = self.lobes(hit);
+        let wo = frame.to_local(wo);
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let (wi, flags) = lobes.sample(wo, uc, u)?;
+        let pdf = lobes.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let eta = match (flags.contains(BsdfFlags::TRANSMISSION), wo.z > 0.0) {
+            (false, _) => 1.0,
+            (true, true) => self.ior,
+            (true, false) => 1.0 / self.ior,
+        };
+        Some(BsdfSample { wi: frame.to_world(wi), f: lobes.eval(wo, wi), pdf, flags, eta })
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3, hit: &HitRecord) -> f64 {
+        let frame = Frame::from_hit(hit);
+        self.lobes(hit).pdf(frame.to_local(wo), frame.to_local(wi))
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sampler::RandomSampler;
+
+    fn hit_at_origin(ray: &Ray) -> HitRecord<'static> {
+        HitRecord::new(ray, DVec3::ZERO, DVec3::Y, 1.0, DVec2::ZERO, DVec3::X, DVec3::Z)
+    }
 
     #[test]
     fn test_schlick() {
         let cosine = 0.5;
         let eta = 1.5;
         let r = schlick(cosine, eta);
-        assert_eq!(r, 0.776393202250021);
+        assert!((r - 0.07).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_scatter_weights_match_albedo() {
+        let ray = Ray::new(DVec3::new(-1.0, 1.0, 0.0), DVec3::new(1.0, -1.0, 0.0).normalize());
+        let hit = hit_at_origin(&ray);
+        let mut sampler = RandomSampler::new();
+        let albedo = DVec3::new(0.8, 0.6, 0.2);
+        let materials: [Box<dyn Material>; 3] = [
+            Box::new(Lambertian::new(albedo)),
+            Box::new(Metal::new(albedo, 0.0)),
+            Box::new(Metal::new(albedo, 0.3)),
+        ];
+        for material in &materials {
+            for _ in 0..1000 {
+                if let Some((scattered, attenuation)) = material.scatter(&ray, &hit, &mut sampler) {
+                    assert!(scattered.direction.dot(hit.normal) > 0.0);
+                    assert!((attenuation - albedo).length() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_metal_pdf_matches_eval() {
+        let ray = Ray::new(DVec3::new(-1.0, 1.0, 0.0), DVec3::new(1.0, -1.0, 0.0).normalize());
+        let hit = hit_at_origin(&ray);
+        let wo = -ray.direction;
+        let metal = Metal::new(DVec3::ONE, 0.4);
+        let mut sampler = RandomSampler::new();
+        // the fuzz distribution integrates to one over the sphere
+        let reflected = reflect(wo, hit.normal);
+        let (n_theta, n_phi) = (400, 800);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * PI;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * 2.0 * PI;
+                let wi = DVec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                let area = theta.sin() * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+                total += fuzz_pdf(reflected, 0.4, wi) * area;
+            }
+        }
+        // the density has an integrable singularity at the rim of the cone, which
+        // the midpoint rule slightly underestimates
+        assert!((total - 1.0).abs() < 3e-2);
+        let sample = metal.sample(wo, &hit, &mut sampler).unwrap();
+        assert!((sample.pdf - metal.pdf(wo, sample.wi, &hit)).abs() < 1e-9);
+        assert!((sample.f - metal.eval(wo, sample.wi, &hit)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_dielectric_matches_schlick() {
+        let direction = DVec3::new(0.5, -1.0, 0.0).normalize();
+        let ray = Ray::new(-direction, direction);
+        let hit = hit_at_origin(&ray);
+        let dielectric = Dielectric::new(1.5);
+        let mut sampler = RandomSampler::new();
+        let n = 20000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let (scattered, attenuation) = dielectric.scatter(&ray, &hit, &mut sampler).unwrap();
+            assert!((attenuation - DVec3::ONE).length() < 1e-9);
+            if scattered.direction.dot(hit.normal) > 0.0 {
+                reflected += 1;
+            }
+        }
+        let expected = schlick(-direction.dot(hit.normal), 1.0 / 1.5);
+        assert!((reflected as f64 / n as f64 - expected).abs() < 0.01);
+    }
+
+    /// Pearson's chi-square test of the directions `sample` draws against `pdf`,
+    /// binned on a (cos theta, phi) grid around a +z normal
+    fn chi2_test(material: &dyn Material, wo: DVec3, seed: u64) {
+        const THETA_BINS: usize = 16;
+        const PHI_BINS: usize = 32;
+        const SAMPLES: usize = 200_000;
+        const SUBDIVISIONS: usize = 8;
+        let wo = wo.normalize();
+        let ray = Ray::new(wo, -wo);
+        let hit = HitRecord::new(&ray, DVec3::ZERO, DVec3::Z, 1.0, DVec2::ZERO, DVec3::X, DVec3::Y);
+        let bin = |w: DVec3| {
+            let theta = (((w.z + 1.0) / 2.0 * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+            let phi = w.y.atan2(w.x).rem_euclid(2.0 * PI);
+            let phi = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+            theta * PHI_BINS + phi
+        };
+
+        let mut sampler = RandomSampler::seeded(seed);
+        let mut observed = vec![0.0; THETA_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            if let Some(sample) = material.sample(wo, &hit, &mut sampler) {
+                let pdf = material.pdf(wo, sample.wi, &hit);
+                assert!((sample.pdf - pdf).abs() <= 1e-6 * pdf.max(1.0));
+                assert!((sample.f - material.eval(wo, sample.wi, &hit)).length() <= 1e-6 * sample.f.length().max(1.0));
+                observed[bin(sample.wi)] += 1.0;
+            }
+        }
+
+        let mut expected = vec![0.0; THETA_BINS * PHI_BINS];
+        let (d_cos, d_phi) = (2.0 / THETA_BINS as f64, 2.0 * PI / PHI_BINS as f64);
+        for i in 0..THETA_BINS * SUBDIVISIONS {
+            let cos = -1.0 + (i as f64 + 0.5) * d_cos / SUBDIVISIONS as f64;
+            let sin = (1.0 - cos * cos).sqrt();
+            for j in 0..PHI_BINS * SUBDIVISIONS {
+                let phi = (j as f64 + 0.5) * d_phi / SUBDIVISIONS as f64;
+                let wi = DVec3::new(sin * phi.cos(), sin * phi.sin(), cos);
+                let area = d_cos * d_phi / (SUBDIVISIONS * SUBDIVISIONS) as f64;
+                expected[bin(wi)] += material.pdf(wo, wi, &hit) * area * SAMPLES as f64;
+            }
+        }
+
+        // pool the bins too sparse for the chi-square approximation
+        let mut chi2 = 0.0;
+        let mut dof = 0;
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (o, e) in observed.iter().zip(&expected) {
+            if *e < 5.0 {
+                pooled_observed += o;
+                pooled_expected += e;
+            } else {
+                chi2 += (o - e) * (o - e) / e;
+                dof += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            chi2 += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected) / pooled_expected;
+            dof += 1;
+        }
+        let dof = (dof - 1) as f64;
+        // Wilson-Hilferty transform of the statistic to a standard normal variable
+        let z = ((chi2 / dof).cbrt() - (1.0 - 2.0 / (9.0 * dof))) / (2.0 / (9.0 * dof)).sqrt();
+        assert!(z < 4.0, "chi-square test failed: chi2 = {chi2}, dof = {dof}, z = {z}");
+    }
+
+    #[test]
+    fn test_lambertian_chi2() {
+        chi2_test(&Lambertian::new(DVec3::ONE), DVec3::new(0.5, 0.0, 0.866), 1);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_conductor_chi2() {
+        let gold = Conductor::named("gold", 0.5).unwrap();
+        chi2_test(&gold, DVec3::new(0.5, 0.0, 0.866), 2);
+        chi2_test(&gold, DVec3::new(0.9, 0.3, 0.3).normalize(), 3);
+        let brushed = Conductor::anisotropic(DVec3::splat(0.2), DVec3::splat(3.0), 0.4, 0.8);
+        chi2_test(&brushed, DVec3::new(0.3, 0.4, 0.866).normalize(), 4);
+    }
+
+    #[test]
+    fn test_rough_dielectric_chi2() {
+        let glass = RoughDielectric::new(1.5, 0.5);
+        chi2_test(&glass, DVec3::new(0.5, 0.0, 0.866), 5);
+        chi2_test(&glass, DVec3::new(0.3, 0.2, -0.9).normalize(), 6);
+        chi2_test(&RoughDielectric::new(1.33, 0.7), DVec3::new(0.8, 0.0, 0.6), 7);
+    }
+
+    #[test]
+    fn test_principled_chi2() {
+        let plastic = Principled::new(DVec3::new(0.8, 0.2, 0.1));
+        chi2_test(&plastic, DVec3::new(0.5, 0.0, 0.866), 8);
+        chi2_test(&plastic, DVec3::new(0.2, 0.3, -0.9), 9);
+
+        let mut metal = Principled::new(DVec3::new(0.9, 0.6, 0.3));
+        metal.metallic = constant(1.0);
+        metal.anisotropic = constant(0.8);
+        metal.clearcoat = constant(1.0);
+        metal.clearcoat_gloss = constant(0.3);
+        chi2_test(&metal, DVec3::new(0.6, 0.3, 0.7), 10);
+
+        let mut glass = Principled::new(DVec3::ONE);
+        glass.transmission = constant(0.9);
+        glass.roughness = constant(0.6);
+        chi2_test(&glass, DVec3::new(0.5, 0.0, 0.866), 11);
+        chi2_test(&glass, DVec3::new(0.3, 0.0, -0.9), 12);
+    }
+
+    struct RampTexture(f64);
+
+    impl Texture<f64> for RampTexture {
+        fn value(&self, hit: &HitRecord) -> f64 {
+            self.0 * hit.uv.x
+        }
+    }
+
+    #[test]
+    fn test_flags_follow_textured_roughness() {
+        // roughness ramping up along u leaves the left edge a perfect mirror
+        let ramp = Arc::new(RampTexture(0.5));
+        let conductor = Conductor { eta: DVec3::splat(0.2), k: DVec3::splat(3.0), roughness_u: ramp.clone(), roughness_v: ramp.clone() };
+        let glass = RoughDielectric { eta: 1.5, roughness: ramp };
+        let mut hit = HitRecord::on_surface(DVec3::ZERO, DVec3::Z, DVec2::ZERO);
+        hit.dpdu = DVec3::X;
+        assert_eq!(conductor.flags(&hit), BsdfFlags::REFLECTION | BsdfFlags::SPECULAR);
+        assert_eq!(glass.flags(&hit), BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR);
+        hit.uv.x = 1.0;
+        assert_eq!(conductor.flags(&hit), BsdfFlags::REFLECTION | BsdfFlags::GLOSSY);
+        assert!(glass.flags(&hit).is_non_specular());
+    }
+
+    #[test]
+    fn test_normal_and_bump_maps() {
+        let lambertian: Arc<dyn Material> = Arc::new(Lambertian::new(DVec3::ONE));
+        let ray = Ray::new(DVec3::Y, -DVec3::Y);
+
+        let flat = NormalMap::new(lambertian.clone(), constant(DVec3::new(0.5, 0.5, 1.0)));
+        let mut hit = hit_at_origin(&ray);
+        flat.perturb_shading(-ray.direction, &mut hit);
+        assert!((hit.shading_normal - DVec3::Y).length() < 1e-12);
+
+        // a height growing along u tilts the normal against dpdu
+        let bump = BumpMap::new(lambertian.clone(), Arc::new(RampTexture(0.5)), 1.0);
+        let mut hit = hit_at_origin(&ray);
+        bump.perturb_shading(-ray.direction, &mut hit);
+        assert!((hit.shading_normal - DVec3::new(-0.5, 1.0, 0.0).normalize()).length() < 1e-9);
+
+        // a normal tilted far away from a grazing viewer is bent back towards it
+        let tilted = NormalMap::new(lambertian, constant(DVec3::new(0.95, 0.5, 0.6)));
+        let grazing = Ray::new(DVec3::new(-1.0, 0.05, 0.0), DVec3::new(1.0, -0.05, 0.0));
+        let mut hit = hit_at_origin(&grazing);
+        tilted.perturb_shading(-grazing.direction, &mut hit);
+        let wo = -grazing.direction.normalize();
+        assert!(wo.dot(hit.shading_normal) > 0.0);
+        assert!(reflect(wo, hit.shading_normal).dot(hit.normal) > 0.0);
+        assert!(hit.shading_normal.dot(hit.normal) > 0.0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{bsdf::Frame, ray::Ray, sampler::Sampler};
+
+/// Henyey-Greenstein phase function. `g` is the mean cosine of the scattering
+/// angle, positive for forward scattering. Like BSDFs, both `wo` and `wi` point
+/// away from the scattering point.
+#[derive(Debug, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein { g: g.clamp(-0.99, 0.99) }
+    }
+
+    /// density over the sphere of scattering by the angle with cosine `cos_theta`
+    fn density(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    pub fn p(&self, wo: DVec3, wi: DVec3) -> f64 {
+        self.density(-wo.dot(wi))
+    }
+
+    /// samples `wi` with a density of exactly `p(wo, wi)`, which is returned with it
+    pub fn sample_p(&self, wo: DVec3, u: (f64, f64)) -> (DVec3, f64) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.0
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.0);
+            ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let local = DVec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let wi = Frame::from_normal(-wo.normalize()).to_world(local);
+        (wi, self.density(cos_theta))
+    }
+}
+
+/// Point inside a medium where a ray scatters.
+pub struct MediumInteraction {
+    pub p: DVec3,
+    pub phase: HenyeyGreenstein,
+}
+
+/// Outcome of sampling a free flight through a medium.
+pub struct MediumSample {
+    /// weight the path throughput is multiplied by
+    pub weight: DVec3,
+    /// radiance emitted along the flight towards the ray origin, relative to
+    /// the throughput before it
+    pub emission: DVec3,
+    /// scattering point, `None` if the ray made it through
+    pub interaction: Option<MediumInteraction>,
+}
+
+/// Participating medium filling some region of space. Distances are measured
+/// in ray parameters, so rays don't need normalized directions.
+pub trait Medium : Send + Sync {
+    /// fraction of light that gets through along `ray` from its origin to `t_max`
+    fn tr(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> DVec3;
+
+    /// samples where `ray` first scatters before `t_max`
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> MediumSample;
+}
+
+/// Medium with the same coefficients everywhere. Delta tracking through it
+/// never meets a null collision, so free flight distances are sampled straight
+/// from the extinction of one randomly picked channel.
+pub struct HomogeneousMedium {
+    pub sigma_a: DVec3,
+    pub sigma_s: DVec3,
+    pub phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    /// medium absorbing `sigma_a` and scattering `sigma_s` per unit length,
+    /// with Henyey-Greenstein asymmetry `g`
+    pub fn new(sigma_a: DVec3, sigma_s: DVec3, g: f64) -> HomogeneousMedium {
+        HomogeneousMedium { sigma_a, sigma_s, phase: HenyeyGreenstein::new(g) }
+    }
+
+    fn sigma_t(&self) -> DVec3 {
+        self.sigma_a + self.sigma_s
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn tr(&self, ray: &Ray, t_max: f64, _sampler: &mut dyn Sampler) -> DVec3 {
+        // clamped so that channels without extinction stay at one over infinite distances
+        let distance = (t_max * ray.direction.length()).min(f64::MAX);
+        (-self.sigma_t() * distance).exp()
+    }
+
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> MediumSample {
+        let sigma_t = self.sigma_t();
+        let length = ray.direction.length();
+        let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+        let distance = -(1.0 - sampler.get_1d()).ln() / sigma_t[channel];
+        let t = (distance / length).min(t_max);
+        let scattered = t < t_max;
+        let tr = (-sigma_t * (t * length).min(f64::MAX)).exp();
+        // the density of the distance averaged over the channels it could be picked by
+        let density = if scattered { sigma_t * tr } else { tr };
+        let pdf = (density.x + density.y + density.z) / 3.0;
+        if pdf <= 0.0 {
+            return MediumSample { weight: DVec3::ZERO, emission: DVec3::ZERO, interaction: None };
+        }
+        if scattered {
+            let interaction = MediumInteraction { p: ray.at(t), phase: self.phase };
+            MediumSample { weight: tr * self.sigma_s / pdf, emission: DVec3::ZERO, interaction: Some(interaction) }
+        } else {
+            MediumSample { weight: tr / pdf, emission: DVec3::ZERO, interaction: None }
+        }
+    }
+}
+
+/// Media on both sides of a closed surface. Crossing a surface without an
+/// interface leaves the ray in the medium it was in.
+#[derive(Clone, Default)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<dyn Medium>>,
+    pub outside: Option<Arc<dyn Medium>>,
+}
+
+impl MediumInterface {
+    pub fn new(inside: Option<Arc<dyn Medium>>, outside: Option<Arc<dyn Medium>>) -> MediumInterface {
+        MediumInterface { inside, outside }
+    }
+
+    /// medium a ray leaving the surface in `direction` travels through, with
+    /// `normal` the outward geometric normal
+    pub fn next(&self, direction: DVec3, normal: DVec3) -> Option<Arc<dyn Medium>> {
+        if direction.dot(normal) < 0.0 { self.inside.clone() } else { self.outside.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    #[test]
+    fn test_henyey_greenstein_sampling() {
+        // the mean cosine between the incoming and scattered direction is g
+        let phase = HenyeyGreenstein::new(0.6);
+        let wo = DVec3::new(0.0, 0.6, 0.8);
+        let mut sampler = RandomSampler::seeded(11);
+        let n = 200_000;
+        let mut mean = 0.0;
+        for _ in 0..n {
+            let (wi, p) = phase.sample_p(wo, sampler.get_2d());
+            assert!((phase.p(wo, wi) - p).abs() < 1e-9 * p.max(1.0));
+            mean += -wo.dot(wi) / n as f64;
+        }
+        assert!((mean - 0.6).abs() < 5e-3, "{mean}");
+    }
+
+    #[test]
+    fn test_homogeneous_medium_sampling() {
+        // passing through weighs the transmittance on average and scattering the
+        // integral of sigma_s times the transmittance up to there
+        let medium = HomogeneousMedium::new(DVec3::new(0.1, 0.2, 0.3), DVec3::new(0.5, 0.4, 0.3), 0.0);
+        let ray = Ray::new(DVec3::ZERO, DVec3::new(0.0, 0.0, 2.0));
+        let mut sampler = RandomSampler::seeded(5);
+        let n = 200_000;
+        let (mut through, mut scattered) = (DVec3::ZERO, DVec3::ZERO);
+        for _ in 0..n {
+            let sample = medium.sample(&ray, 1.0, &mut sampler);
+            match sample.interaction {
+                Some(_) => scattered += sample.weight / n as f64,
+                None => through += sample.weight / n as f64,
+            }
+        }
+        let tr = medium.tr(&ray, 1.0, &mut sampler);
+        assert!((through - tr).abs().max_element() < 1e-2, "{through} vs {tr}");
+        let sigma_t = medium.sigma_t();
+        let expected = medium.sigma_s / sigma_t * (DVec3::ONE - tr);
+        assert!((scattered - expected).abs().max_element() < 1e-2, "{scattered} vs {expected}");
+    }
+}
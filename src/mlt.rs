@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use glam::{DVec2, DVec3};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+
+use crate::{
+    accel::{Accel, BVH},
+    bdpt::{Context, sample_strategy},
+    camera::Camera,
+    film::Film,
+    integrator::Integrator,
+    renderer::Renderer,
+    sampler::{MltSampler, Sampler},
+    sampling::Distribution1D,
+    scene::Scene,
+    texture::luminance,
+};
+
+/// What a Metropolis chain mutates the primary samples of.
+pub enum MltMode {
+    /// whole paths sampled by an integrator, Kelemen style primary sample space MLT
+    PrimarySample(Arc<dyn Integrator>),
+    /// single bidirectional strategies, with separate chains for every path
+    /// length and the strategy picked by the first sample (multiplexed MLT).
+    /// Needs a camera with `Camera::has_importance`.
+    Multiplexed,
+}
+
+/// Metropolis light transport. A bootstrap phase estimates the brightness of
+/// the image and seeds the chains, which then mutate primary sample vectors
+/// and splat every state they visit onto a film.
+pub struct Mlt {
+    pub mode: MltMode,
+    /// independent samples used to estimate the normalization and pick the
+    /// chain starting points, per path length in multiplexed mode
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub mutations_per_pixel: usize,
+    /// standard deviation of small step mutations
+    pub sigma: f64,
+    pub large_step_probability: f64,
+}
+
+/// What every chain needs to evaluate a sample.
+struct MltContext<'a> {
+    scene: &'a Scene,
+    accel: &'a dyn Accel,
+    camera: &'a dyn Camera,
+    depth: i32,
+}
+
+impl Mlt {
+    pub fn new(mode: MltMode, mutations_per_pixel: usize) -> Mlt {
+        Mlt {
+            mode,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            mutations_per_pixel,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    /// number of separately bootstrapped path lengths
+    fn depth_classes(&self, depth: i32) -> usize {
+        match self.mode {
+            MltMode::PrimarySample(_) => 1,
+            MltMode::Multiplexed => depth.max(0) as usize + 1,
+        }
+    }
+
+    fn streams(&self) -> usize {
+        match self.mode {
+            MltMode::PrimarySample(_) => 1,
+            MltMode::Multiplexed => 3,
+        }
+    }
+
+    fn sampler(&self, seed: u64) -> MltSampler {
+        MltSampler::new(seed, self.sigma, self.large_step_probability, self.streams())
+    }
+
+    /// radiance of the path the sampler's current primary samples map to, and
+    /// the image position it lands on
+    fn l(&self, ctx: &MltContext, sampler: &mut MltSampler, depth: usize) -> (DVec3, DVec2) {
+        sampler.start_stream(0);
+        match &self.mode {
+            MltMode::PrimarySample(integrator) => {
+                let (a, b) = sampler.get_2d();
+                let uv = DVec2::new(2.0 * a - 1.0, 1.0 - 2.0 * b);
+                let time = ctx.camera.shutter().sample(sampler.get_1d());
+                let ray = ctx.camera.get_ray(uv.x, uv.y, sampler.get_2d()).with_time(time);
+                (integrator.li(&ray, ctx.scene, ctx.accel, sampler, ctx.depth), uv)
+            }
+            MltMode::Multiplexed => {
+                // paths of `depth` bounces have depth + 2 vertices and as many strategies
+                let (s, t, strategies) = if depth == 0 {
+                    (0, 2, 1)
+                } else {
+                    let strategies = depth + 2;
+                    let s = ((sampler.get_1d() * strategies as f64) as usize).min(strategies - 1);
+                    (s, strategies - s, strategies)
+                };
+                let (a, b) = sampler.get_2d();
+                let uv = DVec2::new(2.0 * a - 1.0, 1.0 - 2.0 * b);
+                let time = ctx.camera.shutter().sample(sampler.get_1d());
+                let ray = ctx.camera.get_ray(uv.x, uv.y, sampler.get_2d()).with_time(time);
+                let bdpt = Context::new(ctx.scene, ctx.accel, Some(ctx.camera), time);
+                let (value, splat) = sample_strategy(&bdpt, &ray, s, t, sampler);
+                (value * strategies as f64, splat.unwrap_or(uv))
+            }
+        }
+    }
+
+    /// renders into `renderer.buffer`, using its size and depth. Its sample
+    /// count is ignored in favour of `mutations_per_pixel`. Panics in
+    /// multiplexed mode if `camera` has no importance to connect light paths to.
+    pub fn render(&self, renderer: &mut Renderer, camera: &dyn Camera, scene: &Scene) {
+        assert!(
+            camera.has_importance() || matches!(self.mode, MltMode::PrimarySample(_)),
+            "multiplexed MLT needs a camera with importance, use primary sample MLT for this one"
+        );
+        let bvh = BVH::new(&scene.objects);
+        if let MltMode::PrimarySample(integrator) = &self.mode {
+            integrator.preprocess(scene, &bvh, camera);
+        }
+        let ctx = MltContext { scene, accel: &bvh, camera, depth: renderer.depth };
+        let classes = self.depth_classes(renderer.depth);
+
+        let weights: Vec<f64> = (0..self.bootstrap_samples * classes)
+            .into_par_iter()
+            .map(|index| {
+                let mut sampler = self.sampler(index as u64);
+                luminance(self.l(&ctx, &mut sampler, index % classes).0)
+            })
+            .collect();
+        let bootstrap = Distribution1D::new(weights);
+        // average brightness of the image, summed over the path lengths
+        let b = bootstrap.func_int * classes as f64;
+
+        let film = Film::new(renderer.width, renderer.height);
+        let mutations = self.mutations_per_pixel * renderer.width * renderer.height;
+        let chains = self.chains.max(1);
+        if b > 0.0 {
+            (0..chains).into_par_iter().for_each(|chain| {
+                let start = chain * mutations / chains;
+                let end = (chain + 1) * mutations / chains;
+                self.run_chain(&ctx, &bootstrap, &film, chain as u64, end - start, classes);
+            });
+        }
+
+        let scale = b / self.mutations_per_pixel.max(1) as f64;
+        for (i, pixel) in renderer.buffer.iter_mut().enumerate() {
+            let color = film.splat(i % renderer.width, i / renderer.width) * scale;
+            *pixel = color.clamp(DVec3::ZERO, DVec3::ONE);
+        }
+    }
+
+    /// runs one chain from a bootstrap sample, splatting every state it visits
+    /// weighted by how long the chain stays there
+    fn run_chain(&self, ctx: &MltContext, bootstrap: &Distribution1D, film: &Film, seed: u64, mutations: usize, classes: usize) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (index, _) = bootstrap.sample_discrete(rng.gen());
+        let depth = index % classes;
+        // replaying the bootstrap sample's seed restores its primary samples
+        let mut sampler = self.sampler(index as u64);
+        let (mut current, mut current_uv) = self.l(ctx, &mut sampler, depth);
+        sampler.accept();
+
+        for _ in 0..mutations {
+            sampler.start_iteration();
+            let (proposed, proposed_uv) = self.l(ctx, &mut sampler, depth);
+            let (y_current, y_proposed) = (luminance(current), luminance(proposed));
+            let accept = if y_current > 0.0 { (y_proposed / y_current).min(1.0) } else { 1.0 };
+            if accept > 0.0 && y_proposed > 0.0 {
+                film.add_splat(proposed_uv.x, proposed_uv.y, proposed * accept / y_proposed);
+            }
+            if y_current > 0.0 {
+                film.add_splat(current_uv.x, current_uv.y, current * (1.0 - accept) / y_current);
+            }
+            if rng.gen::<f64>() < accept {
+                current = proposed;
+                current_uv = proposed_uv;
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::OrthographicCamera,
+        integrator::PathIntegrator,
+        photon::PhotonMapIntegrator,
+        test_scenes::{average, bounce_scene, floor_and_lamp},
+    };
+
+    /// averages of the four quadrants of the image
+    fn quadrants(renderer: &Renderer) -> [DVec3; 4] {
+        let mut sums = [DVec3::ZERO; 4];
+        for (i, pixel) in renderer.buffer.iter().enumerate() {
+            let (x, y) = (i % renderer.width, i / renderer.width);
+            sums[2 * (2 * y / renderer.height) + 2 * x / renderer.width] += *pixel;
+        }
+        sums.map(|sum| sum * 4.0 / renderer.buffer.len() as f64)
+    }
+
+    #[test]
+    fn test_mlt_matches_path_tracing() {
+        let (scene, camera) = floor_and_lamp();
+
+        let mut reference = Renderer::new(16, 12, 256, 4);
+        reference.render(&camera, &scene, &PathIntegrator::new());
+        let expected = quadrants(&reference);
+
+        for mode in [MltMode::PrimarySample(Arc::new(PathIntegrator::new())), MltMode::Multiplexed] {
+            let mut mlt = Mlt::new(mode, 256);
+            mlt.bootstrap_samples = 20_000;
+            mlt.chains = 64;
+            let mut renderer = Renderer::new(16, 12, 1, 4);
+            mlt.render(&mut renderer, &camera, &scene);
+            // the bootstrap alone gets the overall brightness right, the
+            // quadrants check that the chains distribute it over the image
+            let value = quadrants(&renderer);
+            let (total, expected_total) = (value.iter().sum::<DVec3>(), expected.iter().sum::<DVec3>());
+            assert!(((total - expected_total) / expected_total).abs().max_element() < 0.05, "{total} vs {expected_total}");
+            for (value, expected) in value.iter().zip(&expected) {
+                assert!(((*value - *expected) / *expected).abs().max_element() < 0.1, "{value} vs {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_primary_sample_mlt_preprocesses_the_integrator() {
+        // all the light on the floor comes from the photon map, which MLT has
+        // to build before the reference render does
+        let (scene, camera) = bounce_scene();
+        let integrator = Arc::new(PhotonMapIntegrator::new(200_000, 0.1));
+        let mut mlt = Mlt::new(MltMode::PrimarySample(integrator.clone()), 64);
+        mlt.bootstrap_samples = 20_000;
+        mlt.chains = 64;
+        let mut renderer = Renderer::new(16, 12, 1, 4);
+        mlt.render(&mut renderer, &camera, &scene);
+        let value = average(&renderer);
+
+        let mut reference = Renderer::new(16, 12, 16, 4);
+        reference.render(&camera, &scene, integrator.as_ref());
+        let expected = average(&reference);
+        assert!(value.x > 0.0, "{value}");
+        assert!(((value - expected) / expected).abs().max_element() < 0.08, "{value} vs {expected}");
+    }
+
+    #[test]
+    #[should_panic(expected = "needs a camera with importance")]
+    fn test_multiplexed_mlt_rejects_cameras_without_importance() {
+        let camera = OrthographicCamera::new(DVec3::new(0.0, 1.0, 4.0), DVec3::ZERO, DVec3::Y, 2.0, 4.0 / 3.0);
+        let mut renderer = Renderer::new(4, 3, 1, 2);
+        Mlt::new(MltMode::Multiplexed, 1).render(&mut renderer, &camera, &Scene::new());
+    }
+}
@@ -1,25 +1,70 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{material::Material, hittable::{Hittable, HitRecord}, ray::Ray, bbox::BBox};
+use glam::DVec3;
+
+use crate::{material::Material, hittable::{Hittable, HitRecord}, ray::Ray, bbox::BBox, light::DiffuseAreaLight, texture::Texture, medium::{Medium, MediumInterface}};
 
 pub struct Object {
-    pub material: Rc<Box<dyn Material>>,
-    pub hittable: Rc<Box<dyn Hittable>>,
+    pub material: Arc<dyn Material>,
+    pub hittable: Arc<dyn Hittable>,
+    /// index of the object in its scene, assigned by `Scene::add`
+    pub id: usize,
+    /// index of the object's material among the distinct materials of its
+    /// scene, assigned by `Scene::add`
+    pub material_id: usize,
+    /// set for objects that emit light from their surface
+    pub area_light: Option<Arc<DiffuseAreaLight>>,
+    /// media inside and outside a closed object, `None` for surfaces that
+    /// don't change the medium rays travel through
+    pub media: Option<MediumInterface>,
 }
 
 impl Object {
-    pub fn new(hittable: Rc<Box<dyn Hittable>>, material: Rc<Box<dyn Material>>) -> Object {
-        Object { material, hittable }
+    pub fn new(hittable: Arc<dyn Hittable>, material: Arc<dyn Material>) -> Object {
+        Object { material, hittable, id: 0, material_id: 0, area_light: None, media: None }
+    }
+
+    /// object emitting `radiance` from the front of its surface
+    pub fn emissive(hittable: Arc<dyn Hittable>, material: Arc<dyn Material>, radiance: DVec3) -> Object {
+        let light = DiffuseAreaLight::new(hittable.clone(), radiance);
+        Object { area_light: Some(Arc::new(light)), ..Object::new(hittable, material) }
+    }
+
+    /// object emitting a textured radiance, from both sides if `two_sided`
+    pub fn emissive_textured(
+        hittable: Arc<dyn Hittable>,
+        material: Arc<dyn Material>,
+        radiance: Arc<dyn Texture<DVec3>>,
+        two_sided: bool,
+    ) -> Object {
+        let mut light = DiffuseAreaLight::textured(hittable.clone(), radiance);
+        light.two_sided = two_sided;
+        Object { area_light: Some(Arc::new(light)), ..Object::new(hittable, material) }
+    }
+
+    /// fills the inside of the object with `inside` and the space around it with
+    /// `outside`. Use a `material::Interface` for a boundary that is only there
+    /// to hold a volume.
+    pub fn with_media(self, inside: Option<Arc<dyn Medium>>, outside: Option<Arc<dyn Medium>>) -> Object {
+        Object { media: Some(MediumInterface::new(inside, outside)), ..self }
+    }
+
+    /// radiance the object emits from `hit` in direction `w`
+    pub fn emitted(&self, hit: &HitRecord, w: DVec3) -> DVec3 {
+        match &self.area_light {
+            Some(light) => light.l(hit, w),
+            None => DVec3::ZERO,
+        }
     }
 }
 
 impl Hittable for Object {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
-        if let Some(record) = self.hittable.hit(ray) {
-            Some(HitRecord { p: record.p, normal: record.normal, t: record.t, object: Some(self), shape: Some(self.hittable.clone()) })
-        } else {
-            None
-        }
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let mut record = self.hittable.hit(ray)?;
+        record.instance_id = self.id;
+        record.object = Some(self);
+        record.shape = Some(self.hittable.as_ref());
+        Some(record)
     }
 
     fn bbox(&self) -> BBox {
@@ -0,0 +1,466 @@
+use std::f64::consts::PI;
+use std::sync::RwLock;
+
+use glam::DVec3;
+use rayon::prelude::*;
+
+use crate::{
+    accel::{Accel, BVH},
+    bbox::BBox,
+    bdpt::{importance_correction, sample_emission},
+    bsdf::BsdfSample,
+    camera::{Camera, Shutter},
+    hittable::HitRecord,
+    integrator::{Integrator, sample_light},
+    material::Material,
+    ray::Ray,
+    renderer::Renderer,
+    sampler::{RandomSampler, Sampler},
+    scene::Scene,
+};
+
+/// Light arriving at a surface, left behind by a path traced from the lights.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub p: DVec3,
+    /// direction the photon came from, pointing away from the surface
+    pub wi: DVec3,
+    /// flux carried by the photon, for a single path traced from the lights
+    pub power: DVec3,
+}
+
+/// Anything a `PhotonMap` can look up by position.
+pub trait Located {
+    fn position(&self) -> DVec3;
+}
+
+impl Located for Photon {
+    fn position(&self) -> DVec3 {
+        self.p
+    }
+}
+
+/// Photons, or other points, in a balanced kd-tree. The tree is implicit: the
+/// node of a range of photons is the median at its middle, split along the
+/// axis stored next to it.
+pub struct PhotonMap<T = Photon> {
+    photons: Vec<T>,
+    axes: Vec<u8>,
+    /// number of paths traced from the lights to collect the photons
+    pub paths: usize,
+}
+
+impl<T: Located> PhotonMap<T> {
+    pub fn new(mut photons: Vec<T>, paths: usize) -> PhotonMap<T> {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+        PhotonMap { photons, axes, paths }
+    }
+
+    fn build(photons: &mut [T], axes: &mut [u8]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let (min, max) = photons.iter().fold((DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)), |(min, max), photon| {
+            (min.min(photon.position()), max.max(photon.position()))
+        });
+        let axis = BBox::new(min, max).max_extent();
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.position()[axis].total_cmp(&b.position()[axis]));
+        axes[mid] = axis as u8;
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// calls `f` for every photon closer to `p` than `radius`
+    pub fn within(&self, p: DVec3, radius: f64, mut f: impl FnMut(&T)) {
+        self.visit(0, self.photons.len(), p, radius * radius, &mut f);
+    }
+
+    fn visit(&self, start: usize, end: usize, p: DVec3, radius_squared: f64, f: &mut impl FnMut(&T)) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let photon = &self.photons[mid];
+        let position = photon.position();
+        if (position - p).length_squared() <= radius_squared {
+            f(photon);
+        }
+        let d = p[self.axes[mid] as usize] - position[self.axes[mid] as usize];
+        let (near, far) = if d <= 0.0 { ((start, mid), (mid + 1, end)) } else { ((mid + 1, end), (start, mid)) };
+        self.visit(near.0, near.1, p, radius_squared, f);
+        if d * d <= radius_squared {
+            self.visit(far.0, far.1, p, radius_squared, f);
+        }
+    }
+}
+
+/// Traces `paths` paths of at most `depth` bounces from the lights and stores
+/// a photon at every non-specular hit. Light arriving straight from a light is
+/// left to shadow rays and never stored.
+pub fn trace_photons(scene: &Scene, accel: &dyn Accel, shutter: &Shutter, paths: usize, depth: usize) -> PhotonMap {
+    let photons = (0..paths)
+        .into_par_iter()
+        .map_init(RandomSampler::new, |sampler, _| {
+            let mut photons = Vec::new();
+            trace_photon(scene, accel, shutter, sampler, depth, &mut photons);
+            photons
+        })
+        .flatten_iter()
+        .collect();
+    PhotonMap::new(photons, paths)
+}
+
+fn trace_photon(scene: &Scene, accel: &dyn Accel, shutter: &Shutter, sampler: &mut dyn Sampler, depth: usize, photons: &mut Vec<Photon>) {
+    let Some(emission) = sample_emission(scene, &accel.bbox(), sampler) else {
+        return;
+    };
+    let mut ray = emission.sample.ray.with_time(shutter.sample(sampler.get_1d()));
+    let mut beta = emission.beta;
+
+    for bounce in 0..depth {
+        let Some(hit) = accel.hit(&ray) else {
+            break;
+        };
+        let Some(object) = hit.object else {
+            break;
+        };
+        let wo = -ray.direction.normalize();
+        let material = object.material.as_ref();
+        let bsdf = material.sample(wo, &hit, sampler);
+        if bounce > 0 && gathers(material, &hit, bsdf.as_ref()) {
+            photons.push(Photon { p: hit.p, wi: wo, power: beta });
+        }
+
+        let Some(bsdf) = bsdf else {
+            break;
+        };
+        if bsdf.pdf <= 0.0 || !hit.shading_consistent(wo, bsdf.wi) {
+            break;
+        }
+        let scattered = beta * bsdf.weight(hit.shading_normal) * importance_correction(&hit, material, wo, bsdf.wi);
+        if scattered == DVec3::ZERO {
+            break;
+        }
+        // keep the photon powers even, paths die as often as they lose power
+        let q = (1.0 - scattered.max_element() / beta.max_element()).max(0.0);
+        if sampler.get_1d() < q {
+            break;
+        }
+        beta = scattered / (1.0 - q);
+        ray = hit.spawn_ray(bsdf.wi);
+    }
+}
+
+/// Whether a path reaching `hit` ends there in a density estimate, decided by
+/// the lobe it sampled so that mirrors and glass are followed and never store
+/// or gather photons. Without a sample the material's flags at `hit` decide.
+fn gathers(material: &dyn Material, hit: &HitRecord, sample: Option<&BsdfSample>) -> bool {
+    match sample {
+        Some(sample) => sample.flags.is_non_specular(),
+        None => material.flags(hit).is_non_specular(),
+    }
+}
+
+/// Radiance the photons within `radius` of `hit` scatter towards `wo`.
+fn estimate_radiance(map: &PhotonMap, hit: &HitRecord, wo: DVec3, material: &dyn Material, radius: f64) -> DVec3 {
+    let mut flux = DVec3::ZERO;
+    map.within(hit.p, radius, |photon| flux += material.eval(wo, photon.wi, hit) * photon.power);
+    flux / (map.paths.max(1) as f64 * PI * radius * radius)
+}
+
+/// Photon mapping. Camera rays follow specular bounces to the first other
+/// surface, which gets direct light from shadow rays and everything else,
+/// caustics in particular, from a density estimate over a photon map traced in
+/// `preprocess`.
+pub struct PhotonMapIntegrator {
+    /// paths traced from the lights
+    pub photons: usize,
+    /// radius of the density estimate
+    pub radius: f64,
+    /// bounces of the paths traced from the lights
+    pub depth: usize,
+    map: RwLock<Option<PhotonMap>>,
+}
+
+impl PhotonMapIntegrator {
+    pub fn new(photons: usize, radius: f64) -> PhotonMapIntegrator {
+        PhotonMapIntegrator { photons, radius, depth: 8, map: RwLock::new(None) }
+    }
+}
+
+impl Integrator for PhotonMapIntegrator {
+    fn preprocess(&self, scene: &Scene, accel: &dyn Accel, camera: &dyn Camera) {
+        let map = trace_photons(scene, accel, camera.shutter(), self.photons, self.depth);
+        *self.map.write().unwrap() = Some(map);
+    }
+
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        let map = self.map.read().unwrap();
+        let mut radiance = DVec3::ZERO;
+        let mut beta = DVec3::ONE;
+        let mut ray = ray.clone();
+        for bounce in 0..=depth.max(0) {
+            // everything before the first non-specular surface is a chain of
+            // specular bounces, so emission along it counts in full
+            let Some(hit) = accel.hit(&ray) else {
+                radiance += beta * scene.le(&ray);
+                break;
+            };
+            let Some(object) = hit.object else {
+                break;
+            };
+            let wo = -ray.direction.normalize();
+            radiance += beta * object.emitted(&hit, wo);
+            if bounce == depth {
+                break;
+            }
+
+            let material = object.material.as_ref();
+            let sample = material.sample(wo, &hit, sampler);
+            if gathers(material, &hit, sample.as_ref()) {
+                radiance += beta * sample_light(scene, accel, &hit, wo, material, sampler, false);
+                if let Some(map) = map.as_ref() {
+                    radiance += beta * estimate_radiance(map, &hit, wo, material, self.radius);
+                }
+                break;
+            }
+
+            let Some(sample) = sample else {
+                break;
+            };
+            if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+                break;
+            }
+            beta *= sample.weight(hit.shading_normal);
+            ray = hit.spawn_ray_with_differentials(&ray, &sample);
+        }
+        radiance
+    }
+}
+
+/// Stochastic progressive photon mapping. Every iteration traces one camera
+/// sample per pixel and a fresh set of photons, and shrinks the radius of each
+/// pixel as photons accumulate, so unlike plain photon mapping the image
+/// converges to the right answer.
+pub struct Sppm {
+    pub iterations: usize,
+    /// paths traced from the lights per iteration
+    pub photons: usize,
+    /// radius every pixel starts with
+    pub radius: f64,
+    /// fraction of the new photons kept per iteration, smaller values shrink
+    /// the radius faster
+    pub alpha: f64,
+}
+
+#[derive(Clone, Copy)]
+struct SppmPixel {
+    radius: f64,
+    /// sum of the light that doesn't come from photons
+    direct: DVec3,
+    /// accumulated photon flux within the current radius
+    tau: DVec3,
+    /// effective number of photons gathered
+    n: f64,
+}
+
+impl Sppm {
+    pub fn new(iterations: usize, photons: usize, radius: f64) -> Sppm {
+        Sppm { iterations, photons, radius, alpha: 0.7 }
+    }
+
+    /// renders into `renderer.buffer`, using its size and depth. Its sample
+    /// count is ignored, every iteration takes one sample per pixel.
+    pub fn render(&self, renderer: &mut Renderer, camera: &dyn Camera, scene: &Scene) {
+        let bvh = BVH::new(&scene.objects);
+        let (width, height) = (renderer.width, renderer.height);
+        let depth = renderer.depth.max(0);
+        let start = SppmPixel { radius: self.radius, direct: DVec3::ZERO, tau: DVec3::ZERO, n: 0.0 };
+        let mut pixels = vec![start; width * height];
+
+        for _ in 0..self.iterations {
+            let map = trace_photons(scene, &bvh, camera.shutter(), self.photons, depth as usize);
+            pixels.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                let mut sampler = RandomSampler::new();
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let u = (x as f64 + sampler.get_1d()) / width as f64 * 2.0 - 1.0;
+                    let v = 1.0 - (y as f64 + sampler.get_1d()) / height as f64 * 2.0;
+                    let lens = sampler.get_2d();
+                    let ray = camera
+                        .get_ray_differential(u, v, 2.0 / width as f64, -2.0 / height as f64, lens)
+                        .with_time(camera.shutter().sample(sampler.get_1d()));
+                    self.update(pixel, &ray, scene, &bvh, &map, &mut sampler, depth);
+                }
+            });
+        }
+
+        let iterations = self.iterations.max(1) as f64;
+        let paths = iterations * self.photons.max(1) as f64;
+        for (color, pixel) in renderer.buffer.iter_mut().zip(&pixels) {
+            let photons = pixel.tau / (paths * PI * pixel.radius * pixel.radius);
+            *color = (pixel.direct / iterations + photons).clamp(DVec3::ZERO, DVec3::ONE);
+        }
+    }
+
+    /// follows a camera ray to its first non-specular surface and gathers the
+    /// photons around it
+    #[allow(clippy::too_many_arguments)]
+    fn update(&self, pixel: &mut SppmPixel, ray: &Ray, scene: &Scene, accel: &dyn Accel, map: &PhotonMap, sampler: &mut dyn Sampler, depth: i32) {
+        let mut beta = DVec3::ONE;
+        let mut ray = ray.clone();
+        for bounce in 0..=depth {
+            let Some(hit) = accel.hit(&ray) else {
+                pixel.direct += beta * scene.le(&ray);
+                return;
+            };
+            let Some(object) = hit.object else {
+                return;
+            };
+            let wo = -ray.direction.normalize();
+            pixel.direct += beta * object.emitted(&hit, wo);
+            if bounce == depth {
+                return;
+            }
+
+            let material = object.material.as_ref();
+            let sample = material.sample(wo, &hit, sampler);
+            if gathers(material, &hit, sample.as_ref()) {
+                pixel.direct += beta * sample_light(scene, accel, &hit, wo, material, sampler, false);
+                let mut phi = DVec3::ZERO;
+                let mut m = 0.0;
+                map.within(hit.p, pixel.radius, |photon| {
+                    phi += material.eval(wo, photon.wi, &hit) * photon.power;
+                    m += 1.0;
+                });
+                if m > 0.0 {
+                    let n = pixel.n + self.alpha * m;
+                    let radius = pixel.radius * (n / (pixel.n + m)).sqrt();
+                    pixel.tau = (pixel.tau + beta * phi) * (radius * radius) / (pixel.radius * pixel.radius);
+                    pixel.n = n;
+                    pixel.radius = radius;
+                }
+                return;
+            }
+
+            let Some(sample) = sample else {
+                return;
+            };
+            if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+                return;
+            }
+            beta *= sample.weight(hit.shading_normal);
+            ray = hit.spawn_ray_with_differentials(&ray, &sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        hittable::Sphere,
+        integrator::PathIntegrator,
+        material::{Conductor, Dielectric},
+        object::Object,
+        test_scenes::{average, bounce_scene, floor, lamp, square},
+    };
+
+    #[test]
+    fn test_photon_map_within() {
+        let mut sampler = RandomSampler::seeded(5);
+        let photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon { p: DVec3::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d()), wi: DVec3::Y, power: DVec3::ONE })
+            .collect();
+        let map = PhotonMap::new(photons.clone(), 2000);
+        for _ in 0..20 {
+            let p = DVec3::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d());
+            let mut found = 0;
+            map.within(p, 0.15, |_| found += 1);
+            let expected = photons.iter().filter(|photon| (photon.p - p).length() <= 0.15).count();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_photon_mapping_matches_path_tracing() {
+        let (scene, camera) = bounce_scene();
+        let mut reference = Renderer::new(16, 12, 256, 6);
+        reference.render(&camera, &scene, &PathIntegrator::new());
+        let expected = average(&reference);
+        assert!(expected.x > 0.03, "{expected}");
+
+        let mut photon_map = Renderer::new(16, 12, 4, 6);
+        let mut integrator = PhotonMapIntegrator::new(200_000, 0.05);
+        integrator.depth = 6;
+        photon_map.render(&camera, &scene, &integrator);
+        let value = average(&photon_map);
+        assert!(((value - expected) / expected).abs().max_element() < 0.05, "{value} vs {expected}");
+
+        let mut sppm = Renderer::new(16, 12, 1, 6);
+        Sppm::new(8, 40_000, 0.1).render(&mut sppm, &camera, &scene);
+        let value = average(&sppm);
+        assert!(((value - expected) / expected).abs().max_element() < 0.05, "{value} vs {expected}");
+    }
+
+    /// renders `scene` with photon mapping and SPPM and compares both to path
+    /// tracing
+    fn assert_matches_path_tracing(scene: &Scene, camera: &PerspectiveCamera, radius: f64) {
+        let mut reference = Renderer::new(16, 12, 512, 6);
+        reference.render(camera, scene, &PathIntegrator::new());
+        let expected = average(&reference);
+        assert!(expected.x > 0.03, "{expected}");
+
+        let mut photon_map = Renderer::new(16, 12, 4, 6);
+        photon_map.render(camera, scene, &PhotonMapIntegrator::new(200_000, radius));
+        let value = average(&photon_map);
+        assert!(((value - expected) / expected).abs().max_element() < 0.1, "{value} vs {expected}");
+
+        let mut sppm = Renderer::new(16, 12, 1, 6);
+        Sppm::new(8, 40_000, radius).render(&mut sppm, camera, scene);
+        let value = average(&sppm);
+        assert!(((value - expected) / expected).abs().max_element() < 0.1, "{value} vs {expected}");
+    }
+
+    #[test]
+    fn test_glass_sphere_caustic() {
+        // the floor under a glass sphere only sees the lamp through it
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        let sphere = Arc::new(Sphere::new(DVec3::new(0.0, 1.0, 0.0), 0.5));
+        scene.add(Object::new(sphere, Arc::new(Dielectric::new(1.5))));
+        scene.add(lamp(DVec3::new(0.0, 2.5, 0.0), false, 8.0));
+        let camera = PerspectiveCamera::new(DVec3::new(0.0, 0.45, 0.0), DVec3::ZERO, DVec3::Z, 60.0, 4.0 / 3.0);
+        assert_matches_path_tracing(&scene, &camera, 0.05);
+    }
+
+    #[test]
+    fn test_mirror_caustic() {
+        // the floor only sees the lamp facing up in a smooth metal ceiling,
+        // which must neither gather nor store photons
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        let mirror = square(DVec3::new(0.0, 2.0, 0.0), 20.0, false);
+        scene.add(Object::new(mirror, Arc::new(Conductor::new(DVec3::splat(0.2), DVec3::splat(3.9), 0.0))));
+        // off to the side, where it doesn't shadow the floor the camera sees
+        scene.add(lamp(DVec3::new(1.5, 1.0, 0.0), true, 6.0));
+        let camera = PerspectiveCamera::new(DVec3::new(0.0, 0.9, 0.0), DVec3::ZERO, DVec3::Z, 60.0, 4.0 / 3.0);
+        assert_matches_path_tracing(&scene, &camera, 0.05);
+    }
+}
@@ -1,22 +1,45 @@
-use std::f64::{EPSILON, MAX};
-
 use glam::DVec3;
 
+/// Offset rays one pixel over in x and y, used to estimate texture footprints.
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: DVec3,
+    pub rx_direction: DVec3,
+    pub ry_origin: DVec3,
+    pub ry_direction: DVec3,
+}
+
 #[derive(Debug, Clone)]
 pub struct Ray {
     pub origin: DVec3,
     pub direction: DVec3,
     pub min_t: f64,
     pub max_t: f64,
+    /// moment within the shutter interval the ray travels at, for motion blur
+    pub time: f64,
+    pub differential: Option<RayDifferential>,
 }
 
 impl Ray {
     pub fn new(origin: DVec3, direction: DVec3) -> Ray {
-        Ray { origin, direction, min_t : EPSILON, max_t: MAX }
+        Ray { origin, direction, min_t : f64::EPSILON, max_t: f64::MAX, time: 0.0, differential: None }
+    }
+
+    pub fn with_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
     }
 
     pub fn at(&self, t: f64) -> DVec3 {
         self.origin + self.direction * t
     }
-}
 
+    /// shrinks the differentials to the spacing between `samples` samples per pixel
+    pub fn scale_differentials(&mut self, s: f64) {
+        if let Some(d) = &mut self.differential {
+            d.rx_origin = self.origin + (d.rx_origin - self.origin) * s;
+            d.ry_origin = self.origin + (d.ry_origin - self.origin) * s;
+            d.rx_direction = self.direction + (d.rx_direction - self.direction) * s;
+            d.ry_direction = self.direction + (d.ry_direction - self.direction) * s;
+        }
+    }
+}
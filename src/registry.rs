@@ -0,0 +1,81 @@
+use crate::{
+    aov::{Aov, AovIntegrator, AmbientOcclusionIntegrator},
+    bdpt::BdptIntegrator,
+    integrator::{Integrator, PathIntegrator, TestIntegrator, WhittedIntegrator},
+    volpath::VolPathIntegrator,
+};
+
+/// integrator for a name as it would appear on a command line: `path`, `test`,
+/// `whitted`, `volpath`, `bdpt`, `ao` or the name of an `Aov`. Settings follow
+/// the name separated by colons, `ao:<distance>:<samples>`, and the ones left
+/// out keep their defaults.
+pub fn integrator_from_name(name: &str) -> Result<Box<dyn Integrator>, String> {
+    let mut parts = name.split(':');
+    let base = parts.next().unwrap_or_default();
+    let params: Vec<&str> = parts.collect();
+    let integrator: Box<dyn Integrator> = match base {
+        "ao" => return Ok(Box::new(ambient_occlusion(name, &params)?)),
+        "path" => Box::new(PathIntegrator::new()),
+        "test" => Box::new(TestIntegrator::new()),
+        "whitted" => Box::new(WhittedIntegrator::new()),
+        "volpath" => Box::new(VolPathIntegrator::new()),
+        "bdpt" => Box::new(BdptIntegrator::new()),
+        _ => match base.parse::<Aov>() {
+            Ok(aov) => Box::new(AovIntegrator::new(aov)),
+            Err(_) => return Err(format!("unknown integrator `{base}`")),
+        },
+    };
+    if !params.is_empty() {
+        return Err(format!("`{base}` takes no settings, got `{name}`"));
+    }
+    Ok(integrator)
+}
+
+/// `AmbientOcclusionIntegrator` from the settings after `ao` in `name`
+fn ambient_occlusion(name: &str, params: &[&str]) -> Result<AmbientOcclusionIntegrator, String> {
+    let mut ao = AmbientOcclusionIntegrator::default();
+    if params.len() > 2 {
+        return Err(format!("too many settings in `{name}`, expected `ao:<distance>:<samples>`"));
+    }
+    if let Some(distance) = params.first() {
+        ao.distance = parse(name, "distance", distance)?;
+    }
+    if let Some(samples) = params.get(1) {
+        ao.samples = parse(name, "samples", samples)?;
+    }
+    Ok(ao)
+}
+
+fn parse<T: std::str::FromStr>(name: &str, setting: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid {setting} `{value}` in `{name}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrator_from_name() {
+        assert!(integrator_from_name("path").is_ok());
+        assert!(integrator_from_name("normal").is_ok());
+        assert!(integrator_from_name("ao").is_ok());
+        assert!(integrator_from_name("ao:2.5").is_ok());
+        assert!(integrator_from_name("ao:2.5:64").is_ok());
+        assert!(integrator_from_name("beauty").is_err());
+        assert!(integrator_from_name("path:4").is_err());
+        assert!(integrator_from_name("ao:far").is_err());
+        assert!(integrator_from_name("ao:1:-3").is_err());
+        assert!(integrator_from_name("ao:1:16:2").is_err());
+    }
+
+    #[test]
+    fn test_ambient_occlusion_settings() {
+        let ao = ambient_occlusion("ao", &[]).unwrap();
+        assert_eq!((ao.distance, ao.samples), (1.0, 16));
+        let ao = ambient_occlusion("ao:2.5", &["2.5"]).unwrap();
+        assert_eq!((ao.distance, ao.samples), (2.5, 16));
+        let ao = ambient_occlusion("ao:0.5:64", &["0.5", "64"]).unwrap();
+        assert_eq!((ao.distance, ao.samples), (0.5, 64));
+        assert_eq!(ambient_occlusion("ao:far", &["far"]).err().unwrap(), "invalid distance `far` in `ao:far`");
+    }
+}
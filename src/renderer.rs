@@ -3,7 +3,8 @@ use rayon::prelude::*;
 
 use crate::accel::{BVH};
 use crate::camera::Camera;
-use crate::integrator::{Integrator};
+use crate::film::Film;
+use crate::integrator::{Integrator, RenderContext};
 use crate::sampler::{Sampler, RandomSampler};
 use crate::scene::Scene;
 pub struct Renderer {
@@ -21,29 +22,39 @@ impl Renderer {
 
     pub fn render(&mut self, camera: &dyn Camera, scene: &Scene, integrator: &dyn Integrator) {
         let bvh = BVH::new(&scene.objects);
+        integrator.preprocess(scene, &bvh, camera);
+        let film = Film::new(self.width, self.height);
+        let ctx = RenderContext { scene, accel: &bvh, camera, film: &film };
         let bands: Vec<(usize, &mut [DVec3])> = self.buffer.chunks_mut(self.width).enumerate().collect();
         bands.into_par_iter().for_each(|(i, band)| {
             let mut sampler = RandomSampler::new();
             let y = i;
-            for x in 0..self.width {
+            for (x, pixel) in band.iter_mut().enumerate() {
                 let mut color = DVec3::ZERO;
                 for _ in 0..self.samples {
                     let u = (x as f64 + sampler.get_1d()) / self.width as f64 * 2.0 - 1.0;
                     let v = 1.0 - (y as f64 + sampler.get_1d()) / self.height as f64 * 2.0;
-                    let ray = camera.get_ray(u, v);
+                    let lens = sampler.get_2d();
+                    let mut ray = camera.get_ray_differential(u, v, 2.0 / self.width as f64, -2.0 / self.height as f64, lens);
+                    ray.time = camera.shutter().sample(sampler.get_1d());
+                    ray.scale_differentials((1.0 / (self.samples as f64).sqrt()).max(0.125));
                     // println!("ray: {:?}", ray);
-                    color  += integrator.li(&ray, &bvh, &mut sampler, self.depth);
+                    color  += integrator.li_camera(&ray, &ctx, &mut sampler, self.depth);
                     // color += (ray.direction + DVec3::ONE) * 0.5;
                 }
 
-                color /= self.samples as f64;
-                color.x = color.x.clamp(0.0, 1.0);
-                color.y = color.y.clamp(0.0, 1.0);
-                color.z = color.z.clamp(0.0, 1.0);
-                
-                band[x] = color;
+                *pixel = color / self.samples as f64;
             }
-        })
+        });
+
+        // light splatted onto the film came from one light path per camera sample
+        for (i, pixel) in self.buffer.iter_mut().enumerate() {
+            let mut color = *pixel + film.splat(i % self.width, i / self.width) / self.samples as f64;
+            color.x = color.x.clamp(0.0, 1.0);
+            color.y = color.y.clamp(0.0, 1.0);
+            color.z = color.z.clamp(0.0, 1.0);
+            *pixel = color;
+        }
 
         
         // for y in 0..self.height {
@@ -1,17 +1,35 @@
-use rand::Rng;
+use std::f64::consts::PI;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 pub trait Sampler {
     fn get_1d(&mut self) -> f64;
     fn get_2d(&mut self) -> (f64, f64);
+
+    /// switches to an independent stream of samples, so that the parts of a
+    /// path drawing from different streams don't shift each other's samples
+    fn start_stream(&mut self, _index: usize) {}
 }
 
 pub struct RandomSampler {
-    rng: rand::rngs::ThreadRng,
+    rng: StdRng,
+}
+
+impl Default for RandomSampler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RandomSampler {
     pub fn new() -> RandomSampler {
-        RandomSampler { rng: rand::thread_rng() }
+        RandomSampler { rng: StdRng::from_entropy() }
+    }
+
+    /// reproducible sequence, mostly useful for tests
+    pub fn seeded(seed: u64) -> RandomSampler {
+        RandomSampler { rng: StdRng::seed_from_u64(seed) }
     }
 }
 
@@ -23,4 +41,155 @@ impl Sampler for RandomSampler {
     fn get_2d(&mut self) -> (f64, f64) {
         (self.rng.gen(), self.rng.gen())
     }
-}
\ No newline at end of file
+}
+
+/// One coordinate of a primary sample vector, with the state to undo a
+/// rejected mutation.
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    /// iteration the value was last mutated in
+    last_modification: i64,
+    value_backup: f64,
+    modification_backup: i64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.value_backup = self.value;
+        self.modification_backup = self.last_modification;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.value_backup;
+        self.last_modification = self.modification_backup;
+    }
+}
+
+/// Sampler over a primary sample vector that Metropolis light transport
+/// mutates. Every iteration either draws all coordinates afresh (a large
+/// step) or perturbs them slightly (a small step), and a rejected iteration
+/// restores the previous values. Coordinates are mutated lazily when they are
+/// first asked for in an iteration, so paths of any length can be sampled.
+/// The same seed always replays the same sequence.
+pub struct MltSampler {
+    rng: StdRng,
+    /// standard deviation of the small step perturbations
+    pub sigma: f64,
+    pub large_step_probability: f64,
+    /// number of independent streams the coordinates are interleaved into
+    stream_count: usize,
+    x: Vec<PrimarySample>,
+    current_iteration: i64,
+    large_step: bool,
+    last_large_step: i64,
+    stream_index: usize,
+    sample_index: usize,
+}
+
+impl MltSampler {
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64, stream_count: usize) -> MltSampler {
+        MltSampler {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            stream_count: stream_count.max(1),
+            x: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            stream_index: 0,
+            sample_index: 0,
+        }
+    }
+
+    /// begins a mutation, deciding whether it is a large step
+    pub fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.current_iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for xi in &mut self.x {
+            if xi.last_modification == self.current_iteration {
+                xi.restore();
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.x.len() {
+            self.x.resize(index + 1, PrimarySample::default());
+        }
+        let xi = &mut self.x[index];
+        // catch up on a large step that happened while the coordinate was unused
+        if xi.last_modification < self.last_large_step {
+            xi.value = self.rng.gen();
+            xi.last_modification = self.last_large_step;
+        }
+        xi.backup();
+        if self.large_step {
+            xi.value = self.rng.gen();
+        } else {
+            // the small steps missed while unused add up to one wider step
+            let steps = (self.current_iteration - xi.last_modification) as f64;
+            let (u1, u2): (f64, f64) = (self.rng.gen(), self.rng.gen());
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+            xi.value += normal * self.sigma * steps.sqrt();
+            xi.value -= xi.value.floor();
+        }
+        xi.last_modification = self.current_iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn get_1d(&mut self) -> f64 {
+        let index = self.stream_index + self.stream_count * self.sample_index;
+        self.sample_index += 1;
+        self.ensure_ready(index);
+        self.x[index].value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+
+    fn start_stream(&mut self, index: usize) {
+        self.stream_index = index.min(self.stream_count - 1);
+        self.sample_index = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mlt_sampler_replay_and_reject() {
+        let draw = |sampler: &mut MltSampler| (0..8).map(|_| sampler.get_1d()).collect::<Vec<_>>();
+        let first = draw(&mut MltSampler::new(11, 0.01, 0.3, 1));
+        let mut sampler = MltSampler::new(11, 0.01, 0.3, 1);
+        assert_eq!(draw(&mut sampler), first);
+        sampler.accept();
+
+        for _ in 0..20 {
+            sampler.start_iteration();
+            sampler.start_stream(0);
+            let mutated = draw(&mut sampler);
+            assert!(mutated.iter().all(|x| (0.0..1.0).contains(x)));
+            assert_ne!(mutated, first);
+            sampler.reject();
+        }
+        // every rejected mutation leaves the original values in place
+        sampler.start_iteration();
+        sampler.reject();
+        assert!(sampler.x.iter().zip(&first).all(|(xi, x)| xi.value == *x));
+    }
+}
@@ -1,6 +1,6 @@
 use std::f64::consts::PI;
 
-use glam::{DVec3};
+use glam::{DVec2, DVec3};
 
 pub fn sample_sphere(x: f64, y: f64) -> DVec3 {
     let phi = 2.0 * PI * x;
@@ -34,3 +34,163 @@ pub fn sample_hemisphere_cosine(x: f64, y: f64) -> DVec3 {
 pub fn sample_hemisphere_cosine_pdf(cos_theta: f64) -> f64 {
     cos_theta / PI
 }
+
+pub fn sample_disk(x: f64, y: f64) -> DVec2 {
+    let r = x.sqrt();
+    let theta = 2.0 * PI * y;
+    DVec2::new(r * theta.cos(), r * theta.sin())
+}
+
+pub fn sample_disk_pdf() -> f64 {
+    1.0 / PI
+}
+
+/// uniform barycentric coordinates (b0, b1) on a triangle
+pub fn sample_triangle(x: f64, y: f64) -> (f64, f64) {
+    let su0 = x.sqrt();
+    (1.0 - su0, y * su0)
+}
+
+/// MIS weight of a sample drawn with density `f_pdf` against a second
+/// strategy with density `g_pdf`, one sample each
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let (f, g) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f.is_infinite() {
+        return 1.0;
+    }
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
+
+/// Piecewise constant distribution over [0, 1) with one segment per function value.
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    pub cdf: Vec<f64>,
+    /// integral of the step function over [0, 1)
+    pub func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if func_int == 0.0 { i as f64 / n as f64 } else { *c / func_int };
+        }
+        Distribution1D { func, cdf, func_int }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// segment `u` falls into, the last one whose cdf is at most `u`
+    fn offset(&self, u: f64) -> usize {
+        (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.count() - 1)
+    }
+
+    /// returns (x, pdf, segment) for a point sampled proportional to the function
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.offset(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = if self.func_int > 0.0 { self.func[offset].abs() / self.func_int } else { 0.0 };
+        ((offset as f64 + du) / self.count() as f64, pdf, offset)
+    }
+
+    /// returns (index, probability) of a segment picked proportional to the function
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let offset = self.offset(u);
+        (offset, self.discrete_pdf(offset))
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> f64 {
+        if self.func_int == 0.0 {
+            return 0.0;
+        }
+        self.func[index].abs() / (self.func_int * self.count() as f64)
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)^2, sampled as a marginal
+/// distribution over rows and a conditional one along each row.
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `nv` rows of `nu` values each
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func.chunks(nu).take(nv).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.func_int).collect());
+        Distribution2D { conditional, marginal }
+    }
+
+    /// returns a point in [0, 1)^2 sampled proportional to the function, with its pdf
+    pub fn sample_continuous(&self, u: (f64, f64)) -> (DVec2, f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u.1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u.0);
+        (DVec2::new(u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, p: DVec2) -> f64 {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.x * nu as f64) as usize).min(nu - 1);
+        let iv = ((p.y * nv as f64) as usize).min(nv - 1);
+        if self.marginal.func_int == 0.0 {
+            return 0.0;
+        }
+        self.conditional[iv].func[iu].abs() / self.marginal.func_int
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert!((distribution.func_int - 4.0 / 3.0).abs() < 1e-12);
+        let (x, pdf, offset) = distribution.sample_continuous(0.125);
+        assert_eq!(offset, 0);
+        assert!((x - 1.0 / 6.0).abs() < 1e-12);
+        assert!((pdf - 0.75).abs() < 1e-12);
+        let (x, pdf, offset) = distribution.sample_continuous(0.625);
+        assert_eq!(offset, 2);
+        assert!((x - 5.0 / 6.0).abs() < 1e-12);
+        assert!((pdf - 2.25).abs() < 1e-12);
+        assert_eq!(distribution.sample_discrete(0.3), (2, 0.75));
+        assert_eq!(distribution.discrete_pdf(1), 0.0);
+    }
+
+    #[test]
+    fn test_distribution_2d_pdf_matches_samples() {
+        let (nu, nv) = (5, 3);
+        let func: Vec<f64> = (0..nu * nv).map(|i| ((i * 7) % 4) as f64).collect();
+        let distribution = Distribution2D::new(&func, nu, nv);
+        let mut integral = 0.0;
+        for i in 0..nu * nv {
+            let p = DVec2::new(((i % nu) as f64 + 0.5) / nu as f64, ((i / nu) as f64 + 0.5) / nv as f64);
+            integral += distribution.pdf(p) / (nu * nv) as f64;
+        }
+        assert!((integral - 1.0).abs() < 1e-12);
+        for i in 0..32 {
+            let u = ((i as f64 + 0.5) / 32.0, (i as f64 * 0.618034) % 1.0);
+            let (p, pdf) = distribution.sample_continuous(u);
+            assert!(pdf > 0.0);
+            assert!((distribution.pdf(p) - pdf).abs() < 1e-12);
+        }
+    }
+}
@@ -1,24 +1,53 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::accel::{BVHNode, Accel, BVH};
+use glam::DVec3;
+
+use crate::bbox::BBox;
 use crate::hittable::{Hittable, HitRecord};
+use crate::light::{GradientSky, Light};
+use crate::medium::Medium;
 use crate::object::Object;
 use crate::ray::Ray;
 
 pub struct Scene {
     pub objects: Vec<Object>,
+    /// every light in the scene, including the area lights of emissive objects
+    /// and the environment
+    pub lights: Vec<Arc<dyn Light>>,
+    /// light at infinity seen by rays that escape the scene
+    pub environment: Option<Arc<dyn Light>>,
+    /// medium around the camera, which camera rays start in
+    pub medium: Option<Arc<dyn Medium>>,
+    /// ids handed out by `add`, keyed by the address of the material. The
+    /// address is stored as a `usize` to keep the scene `Send` and `Sync`.
+    material_ids: HashMap<usize, usize>,
+    next_material_id: usize,
 }
 
-unsafe impl Send for Scene {}
-unsafe impl Sync for Scene {}
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene { objects: Vec::new()}
+        let mut scene = Scene {
+            objects: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+            medium: None,
+            material_ids: HashMap::new(),
+            next_material_id: 0,
+        };
+        scene.set_environment(Some(Arc::new(GradientSky::default())));
+        scene
     }
 
-    pub fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    pub fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
         
-        let mut closest = std::f64::MAX;
+        let mut closest = f64::MAX;
         let mut hit = None;
         for object in &self.objects {
             if let Some(record) = object.hit(ray) {
@@ -28,10 +57,71 @@ impl Scene {
                 }
             }
         }
+        if let Some(record) = &mut hit {
+            record.finalize(ray);
+        }
         hit
     }
 
-    pub fn add(&mut self, object: Object) {
+    pub fn add(&mut self, mut object: Object) {
+        object.id = self.objects.len();
+        let key = Arc::as_ptr(&object.material) as *const () as usize;
+        object.material_id = *self.material_ids.entry(key).or_insert_with(|| {
+            self.next_material_id += 1;
+            self.next_material_id - 1
+        });
+        if let Some(light) = &object.area_light {
+            self.lights.push(light.clone());
+        }
         self.objects.push(object);
     }
-}
\ No newline at end of file
+
+    pub fn add_light(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    /// light picked uniformly by `u` in [0, 1), with the probability of picking it
+    pub fn pick_light(&self, u: f64) -> Option<(&Arc<dyn Light>, f64)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let count = self.lights.len();
+        Some((&self.lights[((u * count as f64) as usize).min(count - 1)], 1.0 / count as f64))
+    }
+
+    /// replaces the environment, `None` leaves escaping rays black
+    pub fn set_environment(&mut self, environment: Option<Arc<dyn Light>>) {
+        if let Some(old) = self.environment.take() {
+            self.lights.retain(|light| !Arc::ptr_eq(light, &old));
+        }
+        if let Some(light) = &environment {
+            self.lights.push(light.clone());
+        }
+        self.environment = environment;
+    }
+
+    /// radiance of the environment and any other lights at infinity, like the
+    /// sun, along a ray that left the scene
+    pub fn le(&self, ray: &Ray) -> DVec3 {
+        self.lights.iter().map(|light| light.le(ray)).sum()
+    }
+
+    pub fn bounds(&self) -> BBox {
+        self.objects.iter().fold(BBox::default(), |bounds, object| bounds.union(&object.bbox()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accel::BVH;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_scene_is_send_sync() {
+        assert_send_sync::<Scene>();
+        assert_send_sync::<Object>();
+        assert_send_sync::<BVH>();
+    }
+}
@@ -0,0 +1,332 @@
+use std::f64::consts::PI;
+
+use glam::{DVec2, DVec3};
+
+use crate::{
+    bbox::BBox,
+    bsdf::Frame,
+    light::{EnvironmentMap, LeSample, Light, LightSample, pdf_le_at_infinity, sample_le_at_infinity},
+    ray::Ray,
+    texture::Image,
+};
+
+/// Converts kcd/m^2, the unit of the sky model, into scene radiance. A white
+/// diffuse surface under the midday sun and sky comes out near 1.
+pub const DEFAULT_SKY_SCALE: f64 = 0.02;
+
+/// resolution of the table the sky is importance sampled from
+const SKY_TABLE_WIDTH: usize = 64;
+const SKY_TABLE_HEIGHT: usize = 32;
+
+/// Perez luminance distribution function
+fn perez(theta: f64, gamma: f64, coefficients: [f64; 5]) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    (1.0 + a * (b / theta.cos().max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> DVec3 {
+    if y <= 0.0 {
+        return DVec3::ZERO;
+    }
+    let xyz = DVec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    DVec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+    .max(DVec3::ZERO)
+}
+
+/// world direction for an elevation above the horizon and an azimuth clockwise
+/// from north, in degrees. North is -z and east is +x.
+pub fn direction_from_angles(elevation: f64, azimuth: f64) -> DVec3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    DVec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos())
+}
+
+fn day_of_year(year: i32, month: u32, day: u32) -> u32 {
+    const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month = month.clamp(1, 12);
+    DAYS_BEFORE_MONTH[month as usize - 1] + day + if leap && month > 2 { 1 } else { 0 }
+}
+
+/// (elevation, azimuth) of the sun in degrees, following the NOAA low accuracy
+/// equations. Latitude and longitude are in degrees, north and east positive,
+/// and the time of day is in hours UTC.
+pub fn solar_position(latitude: f64, longitude: f64, year: i32, month: u32, day: u32, utc_hours: f64) -> (f64, f64) {
+    let gamma = 2.0 * PI / 365.0 * (day_of_year(year, month, day) as f64 - 1.0 + (utc_hours - 12.0) / 24.0);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let true_solar_minutes = utc_hours * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = (true_solar_minutes / 4.0 - 180.0).to_radians();
+    let latitude = latitude.to_radians();
+
+    let cos_zenith = (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos()).clamp(-1.0, 1.0);
+    let elevation = 90.0 - cos_zenith.acos().to_degrees();
+    let azimuth = hour_angle.sin().atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos());
+    (elevation, (azimuth.to_degrees() + 180.0).rem_euclid(360.0))
+}
+
+/// Preetham et al.'s analytic daylight sky for a given sun position and
+/// turbidity, from about 2 (very clear) to 10 (hazy). Directions below the
+/// horizon see the horizon's color.
+pub struct PreethamSky {
+    /// unit direction towards the sun
+    pub sun_direction: DVec3,
+    pub turbidity: f64,
+    pub scale: f64,
+    zenith: [f64; 3],
+    coefficients: [[f64; 5]; 3],
+    /// tabulated copy of the sky to importance sample from
+    table: EnvironmentMap,
+}
+
+impl PreethamSky {
+    /// sun elevation and azimuth in degrees, see `direction_from_angles`
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> PreethamSky {
+        PreethamSky::from_direction(direction_from_angles(elevation, azimuth), turbidity)
+    }
+
+    /// sky as seen from a location on earth at a date and UTC time
+    pub fn from_location(latitude: f64, longitude: f64, year: i32, month: u32, day: u32, utc_hours: f64, turbidity: f64) -> PreethamSky {
+        let (elevation, azimuth) = solar_position(latitude, longitude, year, month, day, utc_hours);
+        PreethamSky::new(elevation, azimuth, turbidity)
+    }
+
+    pub fn from_direction(sun_direction: DVec3, turbidity: f64) -> PreethamSky {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity;
+        let theta_s = sun_direction.y.clamp(-1.0, 1.0).acos().min(PI / 2.0);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s * theta_s, theta_s, 1.0];
+            let turbidities = [t * t, t, 1.0];
+            (0..3).map(|i| turbidities[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f64>()).sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        // normalize so the zenith gets exactly its modelled value
+        let zenith = [
+            zenith_luminance / perez(0.0, theta_s, coefficients[0]),
+            zenith_x / perez(0.0, theta_s, coefficients[1]),
+            zenith_y / perez(0.0, theta_s, coefficients[2]),
+        ];
+
+        let mut sky = PreethamSky {
+            sun_direction,
+            turbidity,
+            scale: DEFAULT_SKY_SCALE,
+            zenith,
+            coefficients,
+            table: EnvironmentMap::new(Image::new(1, 1, vec![DVec3::ONE]), 1.0),
+        };
+        let mut pixels = Vec::with_capacity(SKY_TABLE_WIDTH * SKY_TABLE_HEIGHT);
+        for y in 0..SKY_TABLE_HEIGHT {
+            for x in 0..SKY_TABLE_WIDTH {
+                let uv = DVec2::new((x as f64 + 0.5) / SKY_TABLE_WIDTH as f64, (y as f64 + 0.5) / SKY_TABLE_HEIGHT as f64);
+                pixels.push(sky.radiance(sky.table.uv_to_direction(uv)));
+            }
+        }
+        sky.table = EnvironmentMap::new(Image::new(SKY_TABLE_WIDTH, SKY_TABLE_HEIGHT, pixels), 1.0);
+        sky
+    }
+
+    /// sky radiance in kcd/m^2, before `scale`
+    fn radiance(&self, direction: DVec3) -> DVec3 {
+        let direction = direction.normalize();
+        let theta = direction.y.clamp(1e-3, 1.0).acos();
+        let horizon = DVec3::new(direction.x, direction.y.max(1e-3), direction.z).normalize();
+        let gamma = horizon.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| self.zenith[i] * perez(theta, gamma, self.coefficients[i]);
+        xyy_to_rgb(value(1), value(2), value(0))
+    }
+
+    /// sun disk matching the sky's sun position and turbidity
+    pub fn sun(&self) -> SunLight {
+        SunLight::new(self.sun_direction, self.turbidity, self.scale)
+    }
+}
+
+impl Light for PreethamSky {
+    fn sample_li(&self, reference: DVec3, u: (f64, f64)) -> Option<LightSample> {
+        let mut sample = self.table.sample_li(reference, u)?;
+        sample.li = self.scale * self.radiance(sample.wi);
+        Some(sample)
+    }
+
+    fn pdf_li(&self, reference: DVec3, wi: DVec3) -> f64 {
+        self.table.pdf_li(reference, wi)
+    }
+
+    fn power(&self, bounds: &BBox) -> DVec3 {
+        self.scale * self.table.power(bounds)
+    }
+
+    fn le(&self, ray: &Ray) -> DVec3 {
+        self.scale * self.radiance(ray.direction)
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        sample_le_at_infinity(self, bounds, u1, u2)
+    }
+
+    fn pdf_le(&self, bounds: &BBox, ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        pdf_le_at_infinity(self, bounds, ray)
+    }
+}
+
+/// apparent angular radius of the sun, in degrees
+pub const SUN_ANGULAR_RADIUS: f64 = 0.2667;
+
+/// The sun as a small disk at infinity, its color attenuated by Rayleigh and
+/// aerosol scattering along the way through the atmosphere.
+pub struct SunLight {
+    /// unit direction towards the sun
+    pub direction: DVec3,
+    pub radiance: DVec3,
+    pub cos_theta_max: f64,
+}
+
+impl SunLight {
+    /// sun in `direction` seen through an atmosphere of `turbidity`, with radiance
+    /// in kcd/m^2 times `scale` like `PreethamSky`
+    pub fn new(direction: DVec3, turbidity: f64, scale: f64) -> SunLight {
+        // luminance of the sun disk outside of the atmosphere
+        const EXTRATERRESTRIAL: f64 = 1.6e6;
+        let direction = direction.normalize();
+        let radiance = if direction.y <= 0.0 {
+            DVec3::ZERO
+        } else {
+            let zenith_degrees = direction.y.acos().to_degrees();
+            let air_mass = 1.0 / (direction.y + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+            let beta = 0.04608 * turbidity - 0.04586;
+            // red, green and blue wavelengths in micrometers
+            let transmittance = |lambda: f64| {
+                let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+                let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+                rayleigh * aerosol
+            };
+            scale * EXTRATERRESTRIAL * DVec3::new(transmittance(0.68), transmittance(0.55), transmittance(0.44))
+        };
+        SunLight { direction, radiance, cos_theta_max: SUN_ANGULAR_RADIUS.to_radians().cos() }
+    }
+
+    fn cone_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_theta_max))
+    }
+}
+
+impl Light for SunLight {
+    fn sample_li(&self, _reference: DVec3, u: (f64, f64)) -> Option<LightSample> {
+        if self.radiance == DVec3::ZERO {
+            return None;
+        }
+        let cos_theta = 1.0 - u.0 * (1.0 - self.cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let wi = Frame::from_normal(self.direction).to_world(DVec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+        Some(LightSample { wi, li: self.radiance, pdf: self.cone_pdf(), distance: f64::INFINITY, normal: DVec3::ZERO })
+    }
+
+    fn pdf_li(&self, _reference: DVec3, wi: DVec3) -> f64 {
+        if wi.normalize().dot(self.direction) < self.cos_theta_max {
+            return 0.0;
+        }
+        self.cone_pdf()
+    }
+
+    fn power(&self, bounds: &BBox) -> DVec3 {
+        let radius = 0.5 * bounds.diagonal().length();
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_theta_max);
+        PI * radius * radius * solid_angle * self.radiance
+    }
+
+    fn le(&self, ray: &Ray) -> DVec3 {
+        if ray.direction.normalize().dot(self.direction) < self.cos_theta_max {
+            return DVec3::ZERO;
+        }
+        self.radiance
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+
+    fn sample_le(&self, bounds: &BBox, u1: (f64, f64), u2: (f64, f64)) -> Option<LeSample> {
+        sample_le_at_infinity(self, bounds, u1, u2)
+    }
+
+    fn pdf_le(&self, bounds: &BBox, ray: &Ray, _normal: DVec3) -> (f64, f64) {
+        pdf_le_at_infinity(self, bounds, ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solar_position() {
+        // around noon on the june solstice in london
+        let (elevation, azimuth) = solar_position(51.5, 0.0, 2024, 6, 21, 12.0);
+        assert!((elevation - 62.0).abs() < 0.5, "{elevation}");
+        assert!((azimuth - 180.0).abs() < 2.0, "{azimuth}");
+        // sunrise in the east at the equator around the equinox
+        let (elevation, azimuth) = solar_position(0.0, 0.0, 2024, 3, 20, 6.1);
+        assert!(elevation.abs() < 1.5, "{elevation}");
+        assert!((azimuth - 90.0).abs() < 1.0, "{azimuth}");
+        let east = direction_from_angles(0.0, 90.0);
+        assert!((east - DVec3::X).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_preetham_sky() {
+        let sky = PreethamSky::new(40.0, 135.0, 3.0);
+        // the sky gets brighter towards the sun and is blue overhead
+        let zenith = sky.le(&Ray::new(DVec3::ZERO, DVec3::Y));
+        let near_sun = sky.le(&Ray::new(DVec3::ZERO, direction_from_angles(35.0, 135.0)));
+        let away = sky.le(&Ray::new(DVec3::ZERO, direction_from_angles(35.0, 315.0)));
+        assert!(near_sun.y > zenith.y && near_sun.y > away.y);
+        assert!(zenith.z > zenith.x);
+
+        for i in 0..32 {
+            let u = ((i as f64 + 0.5) / 32.0, ((i as f64 + 0.5) * 0.618034) % 1.0);
+            let sample = sky.sample_li(DVec3::ZERO, u).unwrap();
+            assert!((sky.pdf_li(DVec3::ZERO, sample.wi) - sample.pdf).abs() < 1e-6 * sample.pdf);
+            assert!((sky.le(&Ray::new(DVec3::ZERO, sample.wi)) - sample.li).length() < 1e-9);
+        }
+
+        let sun = sky.sun();
+        let sample = sun.sample_li(DVec3::ZERO, (0.3, 0.7)).unwrap();
+        assert!(sample.wi.dot(sky.sun_direction) >= sun.cos_theta_max);
+        assert_eq!(sun.pdf_li(DVec3::ZERO, sample.wi), sample.pdf);
+        assert_eq!(sun.le(&Ray::new(DVec3::ZERO, sample.wi)), sample.li);
+        // sunlight is reddened by the atmosphere
+        assert!(sun.radiance.x > sun.radiance.z);
+    }
+}
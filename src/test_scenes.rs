@@ -0,0 +1,62 @@
+//! Scenes and helpers shared by the tests of the integrators.
+
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    camera::PerspectiveCamera,
+    hittable::Quad,
+    material::Lambertian,
+    object::Object,
+    renderer::Renderer,
+    scene::Scene,
+};
+
+/// square of side `size` centered on `center`, parallel to the floor and facing
+/// up or down
+pub(crate) fn square(center: DVec3, size: f64, up: bool) -> Arc<Quad> {
+    let half = size / 2.0;
+    if up {
+        Arc::new(Quad::new(center + DVec3::new(-half, 0.0, half), DVec3::new(size, 0.0, 0.0), DVec3::new(0.0, 0.0, -size)))
+    } else {
+        Arc::new(Quad::new(center + DVec3::new(-half, 0.0, -half), DVec3::new(size, 0.0, 0.0), DVec3::new(0.0, 0.0, size)))
+    }
+}
+
+/// diffuse 20 x 20 floor at y = 0
+pub(crate) fn floor(albedo: f64) -> Object {
+    Object::new(square(DVec3::ZERO, 20.0, true), Arc::new(Lambertian::new(DVec3::splat(albedo))))
+}
+
+/// black unit square emitting `radiance` up or down
+pub(crate) fn lamp(center: DVec3, up: bool, radiance: f64) -> Object {
+    Object::emissive(square(center, 1.0, up), Arc::new(Lambertian::new(DVec3::ZERO)), DVec3::splat(radiance))
+}
+
+/// a floor lit by a lamp facing down 3 units above it, which the camera
+/// doesn't see
+pub(crate) fn floor_and_lamp() -> (Scene, PerspectiveCamera) {
+    let mut scene = Scene::new();
+    scene.set_environment(None);
+    scene.add(floor(0.8));
+    scene.add(lamp(DVec3::new(0.0, 3.0, 0.0), false, 10.0));
+    let camera = PerspectiveCamera::new(DVec3::new(0.0, 1.5, 4.0), DVec3::ZERO, DVec3::Y, 40.0, 4.0 / 3.0);
+    (scene, camera)
+}
+
+/// a floor only lit by a ceiling, which a lamp facing up illuminates
+pub(crate) fn bounce_scene() -> (Scene, PerspectiveCamera) {
+    let mut scene = Scene::new();
+    scene.set_environment(None);
+    scene.add(floor(0.5));
+    scene.add(Object::new(square(DVec3::new(0.0, 2.0, 0.0), 20.0, false), Arc::new(Lambertian::new(DVec3::splat(0.8)))));
+    scene.add(lamp(DVec3::new(0.0, 1.0, 0.0), true, 6.0));
+    let camera = PerspectiveCamera::new(DVec3::new(0.0, 0.9, 0.0), DVec3::ZERO, DVec3::Z, 60.0, 4.0 / 3.0);
+    (scene, camera)
+}
+
+/// mean of all pixels of the image
+pub(crate) fn average(renderer: &Renderer) -> DVec3 {
+    renderer.buffer.iter().sum::<DVec3>() / renderer.buffer.len() as f64
+}
@@ -0,0 +1,702 @@
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{DVec2, DVec3};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use crate::hittable::HitRecord;
+
+/// A value varying over a surface, evaluated at a hit point.
+pub trait Texture<T> : Send + Sync {
+    fn value(&self, hit: &HitRecord) -> T;
+}
+
+pub struct ConstantTexture<T> {
+    pub value: T,
+}
+
+impl<T> ConstantTexture<T> {
+    pub fn new(value: T) -> ConstantTexture<T> {
+        ConstantTexture { value }
+    }
+}
+
+impl<T: Copy + Send + Sync> Texture<T> for ConstantTexture<T> {
+    fn value(&self, _hit: &HitRecord) -> T {
+        self.value
+    }
+}
+
+/// shorthand for a shared constant texture
+pub fn constant<T: Copy + Send + Sync + 'static>(value: T) -> Arc<dyn Texture<T>> {
+    Arc::new(ConstantTexture::new(value))
+}
+
+/// How a hit point is turned into 2D texture coordinates.
+#[derive(Debug, Clone, Copy)]
+pub enum TextureMapping {
+    /// the surface parameterization, scaled then offset
+    Uv { scale: DVec2, offset: DVec2 },
+    /// longitude and latitude around `center`
+    Spherical { center: DVec3 },
+    /// projection of the position on the `s` and `t` axes
+    Planar { s: DVec3, t: DVec3, offset: DVec2 },
+}
+
+impl Default for TextureMapping {
+    fn default() -> Self {
+        TextureMapping::Uv { scale: DVec2::ONE, offset: DVec2::ZERO }
+    }
+}
+
+/// longitude and colatitude of the direction `p`, both scaled to [0, 1]
+fn spherical_uv(p: DVec3) -> DVec2 {
+    let d = p.normalize();
+    let phi = d.z.atan2(d.x).rem_euclid(2.0 * PI);
+    DVec2::new(phi / (2.0 * PI), d.y.clamp(-1.0, 1.0).acos() / PI)
+}
+
+impl TextureMapping {
+    pub fn map(&self, hit: &HitRecord) -> DVec2 {
+        match *self {
+            TextureMapping::Uv { scale, offset } => hit.uv * scale + offset,
+            TextureMapping::Spherical { center } => spherical_uv(hit.p - center),
+            TextureMapping::Planar { s, t, offset } => DVec2::new(hit.p.dot(s), hit.p.dot(t)) + offset,
+        }
+    }
+
+    /// texture coordinates with their change towards the neighbouring pixels in x and y
+    pub fn map_with_footprint(&self, hit: &HitRecord) -> (DVec2, DVec2, DVec2) {
+        let st = self.map(hit);
+        match *self {
+            TextureMapping::Uv { scale, .. } => (
+                st,
+                DVec2::new(hit.dudx, hit.dvdx) * scale,
+                DVec2::new(hit.dudy, hit.dvdy) * scale,
+            ),
+            TextureMapping::Planar { s, t, .. } => (
+                st,
+                DVec2::new(hit.dpdx.dot(s), hit.dpdx.dot(t)),
+                DVec2::new(hit.dpdy.dot(s), hit.dpdy.dot(t)),
+            ),
+            TextureMapping::Spherical { center } => {
+                let delta = |dp: DVec3| {
+                    let mut d = spherical_uv(hit.p + dp - center) - st;
+                    // don't let the seam at phi = 0 turn into a huge footprint
+                    d.x -= d.x.round();
+                    d
+                };
+                (st, delta(hit.dpdx), delta(hit.dpdy))
+            }
+        }
+    }
+}
+
+/// What happens to texel lookups outside of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        match self {
+            WrapMode::Repeat => i.rem_euclid(n) as usize,
+            WrapMode::Clamp => i.clamp(0, n - 1) as usize,
+            WrapMode::Mirror => {
+                let period = i.rem_euclid(2 * n);
+                (if period < n { period } else { 2 * n - 1 - period }) as usize
+            }
+        }
+    }
+}
+
+/// relative luminance of a linear Rec. 709 color
+pub fn luminance(c: DVec3) -> f64 {
+    c.dot(DVec3::new(0.2126, 0.7152, 0.0722))
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear RGB pixels, row 0 at the top.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<DVec3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<DVec3>) -> Image {
+        assert_eq!(pixels.len(), width * height);
+        Image { width, height, pixels }
+    }
+
+    /// loads any format the `image` crate reads, decoding sRGB to linear when
+    /// `srgb` is set; HDR formats are already linear
+    pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> image::ImageResult<Image> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|p| {
+            let c = DVec3::new(p[0] as f64, p[1] as f64, p[2] as f64);
+            if srgb {
+                DVec3::new(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z))
+            } else {
+                c
+            }
+        }).collect();
+        Ok(Image::new(width, height, pixels))
+    }
+
+    pub fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> DVec3 {
+        let x = wrap.apply(x, self.width);
+        let y = wrap.apply(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+
+    /// bilinearly filtered lookup, `uv` = (0, 0) is the bottom left corner
+    pub fn bilerp(&self, uv: DVec2, wrap: WrapMode) -> DVec3 {
+        self.bilerp_axes(uv, wrap, wrap)
+    }
+
+    /// `bilerp` wrapping columns with `wrap_u` and rows with `wrap_v`
+    pub fn bilerp_axes(&self, uv: DVec2, wrap_u: WrapMode, wrap_v: WrapMode) -> DVec3 {
+        let x = uv.x * self.width as f64 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |x: i64, y: i64| self.pixels[wrap_v.apply(y, self.height) * self.width + wrap_u.apply(x, self.width)];
+        (1.0 - dx) * (1.0 - dy) * texel(x0, y0)
+            + dx * (1.0 - dy) * texel(x0 + 1, y0)
+            + (1.0 - dx) * dy * texel(x0, y0 + 1)
+            + dx * dy * texel(x0 + 1, y0 + 1)
+    }
+}
+
+impl Image {
+    /// half resolution image, each pixel the average of the 2x2 block it covers
+    fn downsample(&self, wrap: WrapMode) -> Image {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let sum = self.texel(2 * x, 2 * y, wrap)
+                    + self.texel(2 * x + 1, 2 * y, wrap)
+                    + self.texel(2 * x, 2 * y + 1, wrap)
+                    + self.texel(2 * x + 1, 2 * y + 1, wrap);
+                pixels.push(sum / 4.0);
+            }
+        }
+        Image::new(width, height, pixels)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// finest level only, ignores the footprint
+    Bilinear,
+    /// isotropic blend between the two levels matching the footprint width
+    Trilinear,
+    /// elliptically weighted average over the anisotropic footprint
+    Ewa,
+}
+
+/// Image pyramid down to a single pixel, level 0 being the original image.
+pub struct MipMap {
+    pub levels: Vec<Image>,
+    pub wrap: WrapMode,
+}
+
+/// footprints are clamped to this ratio between their major and minor axes
+const MAX_ANISOTROPY: f64 = 8.0;
+
+impl MipMap {
+    pub fn new(image: Image, wrap: WrapMode) -> MipMap {
+        let mut levels = vec![image];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = last.downsample(wrap);
+            levels.push(next);
+        }
+        MipMap { levels, wrap }
+    }
+
+    fn bilerp_level(&self, level: usize, st: DVec2) -> DVec3 {
+        self.levels[level.min(self.levels.len() - 1)].bilerp(st, self.wrap)
+    }
+
+    /// filtered lookup over the parallelogram spanned by `dst0` and `dst1` around `st`
+    pub fn lookup(&self, st: DVec2, dst0: DVec2, dst1: DVec2, filter: FilterMode) -> DVec3 {
+        match filter {
+            FilterMode::Bilinear => self.bilerp_level(0, st),
+            FilterMode::Trilinear => {
+                let width = 2.0 * dst0.x.abs().max(dst0.y.abs()).max(dst1.x.abs()).max(dst1.y.abs());
+                self.trilinear(st, width)
+            }
+            FilterMode::Ewa => {
+                let (mut major, mut minor) = if dst0.length_squared() < dst1.length_squared() { (dst1, dst0) } else { (dst0, dst1) };
+                if minor.length() == 0.0 {
+                    return self.bilerp_level(0, st);
+                }
+                if minor.length() * MAX_ANISOTROPY < major.length() {
+                    minor *= major.length() / (minor.length() * MAX_ANISOTROPY);
+                }
+                if major.length() == 0.0 {
+                    major = minor;
+                }
+                let lod = (self.levels.len() as f64 - 1.0 + minor.length().log2()).max(0.0);
+                let level = lod.floor() as usize;
+                if level >= self.levels.len() - 1 {
+                    return self.levels[self.levels.len() - 1].texel(0, 0, self.wrap);
+                }
+                let t = lod - level as f64;
+                (1.0 - t) * self.ewa(level, st, major, minor) + t * self.ewa(level + 1, st, major, minor)
+            }
+        }
+    }
+
+    fn trilinear(&self, st: DVec2, width: f64) -> DVec3 {
+        let level = self.levels.len() as f64 - 1.0 + width.max(1e-8).log2();
+        if level <= 0.0 {
+            return self.bilerp_level(0, st);
+        }
+        if level >= (self.levels.len() - 1) as f64 {
+            return self.levels[self.levels.len() - 1].texel(0, 0, self.wrap);
+        }
+        let base = level.floor() as usize;
+        let t = level - base as f64;
+        (1.0 - t) * self.bilerp_level(base, st) + t * self.bilerp_level(base + 1, st)
+    }
+
+    fn ewa(&self, level: usize, st: DVec2, dst0: DVec2, dst1: DVec2) -> DVec3 {
+        let image = &self.levels[level];
+        let resolution = DVec2::new(image.width as f64, image.height as f64);
+        // texel space, with rows going down like the image, so t and its
+        // derivatives flip sign
+        let s = st.x * resolution.x - 0.5;
+        let t = (1.0 - st.y) * resolution.y - 0.5;
+        let dst0 = DVec2::new(dst0.x, -dst0.y) * resolution;
+        let dst1 = DVec2::new(dst1.x, -dst1.y) * resolution;
+
+        // implicit ellipse A s^2 + B s t + C t^2 < 1 around the lookup point
+        let mut a = dst0.y * dst0.y + dst1.y * dst1.y + 1.0;
+        let mut b = -2.0 * (dst0.x * dst0.y + dst1.x * dst1.y);
+        let mut c = dst0.x * dst0.x + dst1.x * dst1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let u_sqrt = (det * c).sqrt();
+        let v_sqrt = (a * det).sqrt();
+        let s0 = (s - 2.0 * inv_det * u_sqrt).ceil() as i64;
+        let s1 = (s + 2.0 * inv_det * u_sqrt).floor() as i64;
+        let t0 = (t - 2.0 * inv_det * v_sqrt).ceil() as i64;
+        let t1 = (t + 2.0 * inv_det * v_sqrt).floor() as i64;
+
+        const ALPHA: f64 = 2.0;
+        let mut sum = DVec3::ZERO;
+        let mut weight_sum = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += weight * image.texel(is, it, self.wrap);
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum <= 0.0 {
+            return image.bilerp(st, self.wrap);
+        }
+        sum / weight_sum
+    }
+}
+
+/// An image looked up through a mapping. Scalar lookups read the first channel.
+pub struct ImageTexture {
+    pub mipmap: Arc<MipMap>,
+    pub mapping: TextureMapping,
+    pub filter: FilterMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> ImageTexture {
+        ImageTexture::with_mipmap(Arc::new(MipMap::new(image, WrapMode::Repeat)))
+    }
+
+    /// shares an existing pyramid, e.g. between a color and a roughness lookup
+    pub fn with_mipmap(mipmap: Arc<MipMap>) -> ImageTexture {
+        ImageTexture { mipmap, mapping: TextureMapping::default(), filter: FilterMode::Ewa }
+    }
+
+    fn lookup(&self, hit: &HitRecord) -> DVec3 {
+        let (st, dst0, dst1) = self.mapping.map_with_footprint(hit);
+        self.mipmap.lookup(st, dst0, dst1, self.filter)
+    }
+}
+
+impl Texture<DVec3> for ImageTexture {
+    fn value(&self, hit: &HitRecord) -> DVec3 {
+        self.lookup(hit)
+    }
+}
+
+impl Texture<f64> for ImageTexture {
+    fn value(&self, hit: &HitRecord) -> f64 {
+        self.lookup(hit).x
+    }
+}
+
+/// Projects an image along the three axes and blends the projections by how much
+/// the normal faces each of them, for surfaces without a usable parameterization.
+pub struct TriplanarTexture {
+    pub image: Arc<Image>,
+    /// world units covered by one repetition of the image
+    pub scale: f64,
+    /// higher values give narrower transitions between projections
+    pub sharpness: f64,
+    pub wrap: WrapMode,
+}
+
+impl TriplanarTexture {
+    pub fn new(image: Arc<Image>, scale: f64) -> TriplanarTexture {
+        TriplanarTexture { image, scale, sharpness: 4.0, wrap: WrapMode::Repeat }
+    }
+}
+
+impl Texture<DVec3> for TriplanarTexture {
+    fn value(&self, hit: &HitRecord) -> DVec3 {
+        let n = hit.normal.abs();
+        let weights = DVec3::new(n.x.powf(self.sharpness), n.y.powf(self.sharpness), n.z.powf(self.sharpness));
+        let weights = weights / (weights.x + weights.y + weights.z);
+        let p = hit.p / self.scale;
+        weights.x * self.image.bilerp(DVec2::new(p.z, p.y), self.wrap)
+            + weights.y * self.image.bilerp(DVec2::new(p.x, p.z), self.wrap)
+            + weights.z * self.image.bilerp(DVec2::new(p.x, p.y), self.wrap)
+    }
+}
+
+/// Alternates between two textures on a grid in texture space.
+pub struct CheckerTexture<T> {
+    pub even: Arc<dyn Texture<T>>,
+    pub odd: Arc<dyn Texture<T>>,
+    pub mapping: TextureMapping,
+    /// checks per unit of texture space
+    pub frequency: f64,
+}
+
+impl<T> CheckerTexture<T> {
+    pub fn new(even: Arc<dyn Texture<T>>, odd: Arc<dyn Texture<T>>, frequency: f64) -> CheckerTexture<T> {
+        CheckerTexture { even, odd, mapping: TextureMapping::default(), frequency }
+    }
+}
+
+impl<T> Texture<T> for CheckerTexture<T> {
+    fn value(&self, hit: &HitRecord) -> T {
+        let uv = self.mapping.map(hit) * self.frequency;
+        if (uv.x.floor() + uv.y.floor()) as i64 % 2 == 0 {
+            self.even.value(hit)
+        } else {
+            self.odd.value(hit)
+        }
+    }
+}
+
+/// Classic gradient noise on a permuted lattice.
+pub struct Perlin {
+    gradients: Vec<DVec3>,
+    permutation: [Vec<usize>; 3],
+}
+
+const PERLIN_POINTS: usize = 256;
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINTS).map(|_| {
+            let v = DVec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            v.try_normalize().unwrap_or(DVec3::X)
+        }).collect();
+        let mut permute = || {
+            let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let permutation = [permute(), permute(), permute()];
+        Perlin { gradients, permutation }
+    }
+
+    fn gradient(&self, i: i64, j: i64, k: i64) -> DVec3 {
+        let mask = PERLIN_POINTS as i64 - 1;
+        let index = self.permutation[0][(i & mask) as usize]
+            ^ self.permutation[1][(j & mask) as usize]
+            ^ self.permutation[2][(k & mask) as usize];
+        self.gradients[index]
+    }
+
+    /// noise in roughly [-1, 1]
+    pub fn noise(&self, p: DVec3) -> f64 {
+        let base = p.floor();
+        let f = p - base;
+        let (i, j, k) = (base.x as i64, base.y as i64, base.z as i64);
+        let smooth = f * f * (DVec3::splat(3.0) - 2.0 * f);
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let corner = DVec3::new(di as f64, dj as f64, dk as f64);
+                    let w = corner * smooth + (DVec3::ONE - corner) * (DVec3::ONE - smooth);
+                    let weight = w.x * w.y * w.z;
+                    accum += weight * self.gradient(i + di, j + dj, k + dk).dot(f - corner);
+                }
+            }
+        }
+        accum
+    }
+
+    /// sum of `octaves` absolute noise values at doubling frequencies
+    pub fn turbulence(&self, p: DVec3, octaves: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(p).abs();
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accum
+    }
+}
+
+/// Cellular noise: distance to the closest of one random feature point per lattice cell.
+pub struct Worley {
+    seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Worley {
+        Worley { seed }
+    }
+
+    fn feature_point(&self, cell: (i64, i64, i64)) -> DVec3 {
+        let mut h = self.seed
+            ^ (cell.0 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (cell.1 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ (cell.2 as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+        let mut next = || {
+            // splitmix64
+            h = h.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = h;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            (z ^ (z >> 31)) as f64 / u64::MAX as f64
+        };
+        DVec3::new(cell.0 as f64 + next(), cell.1 as f64 + next(), cell.2 as f64 + next())
+    }
+
+    /// distance to the closest feature point, in [0, sqrt(3)]
+    pub fn noise(&self, p: DVec3) -> f64 {
+        let base = p.floor();
+        let mut closest = f64::MAX;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let cell = (base.x as i64 + di, base.y as i64 + dj, base.z as i64 + dk);
+                    closest = closest.min((self.feature_point(cell) - p).length());
+                }
+            }
+        }
+        closest
+    }
+}
+
+pub enum Noise {
+    Perlin(Perlin),
+    Turbulence(Perlin, usize),
+    Worley(Worley),
+}
+
+/// Solid noise in [0, 1] evaluated at the scaled hit position.
+pub struct NoiseTexture {
+    pub noise: Noise,
+    pub scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(noise: Noise, scale: f64) -> NoiseTexture {
+        NoiseTexture { noise, scale }
+    }
+}
+
+impl Texture<f64> for NoiseTexture {
+    fn value(&self, hit: &HitRecord) -> f64 {
+        let p = hit.p * self.scale;
+        match &self.noise {
+            Noise::Perlin(perlin) => 0.5 * (perlin.noise(p) + 1.0),
+            Noise::Turbulence(perlin, octaves) => perlin.turbulence(p, *octaves).min(1.0),
+            Noise::Worley(worley) => worley.noise(p).min(1.0),
+        }
+    }
+}
+
+/// Veined marble: a sine along x phase shifted by turbulence.
+pub struct MarbleTexture {
+    pub perlin: Perlin,
+    pub scale: f64,
+    pub turbulence: f64,
+    pub base: DVec3,
+    pub vein: DVec3,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: f64, base: DVec3, vein: DVec3) -> MarbleTexture {
+        MarbleTexture { perlin: Perlin::new(0), scale, turbulence: 10.0, base, vein }
+    }
+}
+
+impl Texture<DVec3> for MarbleTexture {
+    fn value(&self, hit: &HitRecord) -> DVec3 {
+        let p = hit.p * self.scale;
+        let t = 0.5 * (1.0 + (p.x + self.turbulence * self.perlin.turbulence(p, 7)).sin());
+        self.vein.lerp(self.base, t)
+    }
+}
+
+/// Growth rings around the y axis, distorted by noise.
+pub struct WoodTexture {
+    pub perlin: Perlin,
+    pub scale: f64,
+    /// rings per unit of distance from the axis
+    pub rings: f64,
+    pub light: DVec3,
+    pub dark: DVec3,
+}
+
+impl WoodTexture {
+    pub fn new(scale: f64, light: DVec3, dark: DVec3) -> WoodTexture {
+        WoodTexture { perlin: Perlin::new(1), scale, rings: 8.0, light, dark }
+    }
+}
+
+impl Texture<DVec3> for WoodTexture {
+    fn value(&self, hit: &HitRecord) -> DVec3 {
+        let p = hit.p * self.scale;
+        let r = (p.x * p.x + p.z * p.z).sqrt() + 0.3 * self.perlin.noise(p * DVec3::new(1.0, 0.1, 1.0));
+        let ring = (r * self.rings).fract();
+        self.light.lerp(self.dark, ring * ring)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn hit_at(p: DVec3, uv: DVec2) -> HitRecord<'static> {
+        let ray = Ray::new(p + DVec3::Z, -DVec3::Z);
+        HitRecord::new(&ray, p, DVec3::Z, 1.0, uv, DVec3::X, DVec3::Y)
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Clamp.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(5, 4), 2);
+    }
+
+    #[test]
+    fn test_bilerp_and_checker() {
+        let image = Image::new(2, 1, vec![DVec3::ZERO, DVec3::ONE]);
+        let center = image.bilerp(DVec2::new(0.5, 0.5), WrapMode::Clamp);
+        assert!((center - DVec3::splat(0.5)).length() < 1e-12);
+        assert_eq!(image.bilerp(DVec2::new(0.75, 0.5), WrapMode::Clamp), DVec3::ONE);
+
+        let checker = CheckerTexture::new(constant(0.0), constant(1.0), 2.0);
+        assert_eq!(checker.value(&hit_at(DVec3::ZERO, DVec2::new(0.25, 0.25))), 0.0);
+        assert_eq!(checker.value(&hit_at(DVec3::ZERO, DVec2::new(0.75, 0.25))), 1.0);
+    }
+
+    #[test]
+    fn test_mipmap_filters_wide_footprints_to_the_average() {
+        let (width, height) = (64, 32);
+        let pixels = (0..width * height).map(|i| if (i % width + i / width) % 2 == 0 { DVec3::ZERO } else { DVec3::ONE }).collect();
+        let mipmap = MipMap::new(Image::new(width, height, pixels), WrapMode::Repeat);
+        assert_eq!(mipmap.levels.len(), 7);
+        assert_eq!(mipmap.levels[6].texel(0, 0, WrapMode::Repeat), DVec3::splat(0.5));
+
+        let st = DVec2::new(0.3, 0.6);
+        let footprint = DVec2::new(0.1, 0.0);
+        for filter in [FilterMode::Trilinear, FilterMode::Ewa] {
+            let value = mipmap.lookup(st, footprint, footprint.perp(), filter);
+            assert!((value - DVec3::splat(0.5)).length() < 0.05, "{filter:?}: {value}");
+        }
+        // a footprint below a texel reads the base level
+        let texel = mipmap.lookup(DVec2::new(0.5 / 64.0, 1.0 - 0.5 / 32.0), DVec2::splat(1e-5), DVec2::splat(-1e-5), FilterMode::Trilinear);
+        assert_eq!(texel, DVec3::ZERO);
+    }
+
+    #[test]
+    fn test_ewa_follows_diagonal_footprints() {
+        // a bright band along s = t, which a footprint stretched along the band
+        // stays on and one stretched across it mostly misses
+        let size = 64;
+        let pixels = (0..size * size)
+            .map(|i| {
+                let s = ((i % size) as f64 + 0.5) / size as f64;
+                let t = 1.0 - ((i / size) as f64 + 0.5) / size as f64;
+                if (s - t).abs() < 0.1 { DVec3::ONE } else { DVec3::ZERO }
+            })
+            .collect();
+        let mipmap = MipMap::new(Image::new(size, size, pixels), WrapMode::Clamp);
+        let st = DVec2::splat(0.5);
+        let along = mipmap.lookup(st, DVec2::new(0.15, 0.15), DVec2::new(0.02, -0.02), FilterMode::Ewa);
+        let across = mipmap.lookup(st, DVec2::new(0.15, -0.15), DVec2::new(0.02, 0.02), FilterMode::Ewa);
+        assert!(along.x > 0.9, "{along}");
+        assert!(across.x < 0.7, "{across}");
+    }
+
+    #[test]
+    fn test_noise_ranges() {
+        let perlin = Perlin::new(3);
+        let worley = Worley::new(3);
+        for i in 0..1000 {
+            let p = DVec3::new(i as f64 * 0.37, i as f64 * 0.11, -(i as f64) * 0.23);
+            assert!(perlin.noise(p).abs() <= 1.8);
+            assert!((0.0..=3f64.sqrt()).contains(&worley.noise(p)));
+        }
+        assert_eq!(perlin.noise(DVec3::new(1.0, 2.0, 3.0)), 0.0);
+    }
+
+    #[test]
+    fn test_srgb_round_trip() {
+        for c in [0.0, 0.002, 0.2, 0.5, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-12);
+        }
+    }
+}
@@ -1,14 +1,12 @@
 use std::sync::{mpsc, mpsc::Receiver, Arc, Mutex};
-use std::thread::{self, Thread};
+use std::thread;
 pub struct Worker {
-    id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
-    finished: bool,
 }
 
 impl ThreadPool {
@@ -23,7 +21,6 @@ impl ThreadPool {
         ThreadPool {
             workers,
             sender: Some(sender),
-            finished: false,
         }
     }
 
@@ -65,7 +62,6 @@ impl Worker {
             }
         });
         Worker {
-            id,
             thread: Some(thread),
         }
     }
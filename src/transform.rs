@@ -1,4 +1,6 @@
-use glam::DMat4;
+use glam::{DMat4, DQuat, DVec3};
+
+use crate::bbox::BBox;
 
 pub struct Transform {
     pub matrix: glam::DMat4,
@@ -31,4 +33,175 @@ impl Transform {
     pub fn point_to_world(&self, p: glam::DVec3) -> glam::DVec3 {
         self.matrix.transform_point3(p)
     }
-}
\ No newline at end of file
+
+    /// normal, or change of a normal, carried to world space by the inverse
+    /// transpose. Not normalized.
+    pub fn normal_to_world(&self, n: glam::DVec3) -> glam::DVec3 {
+        self.inverse.transpose().transform_vector3(n)
+    }
+}
+
+/// How an `AnimatedTransform` blends the rotations of neighbouring keyframes.
+/// Scale and translation are always blended linearly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// normalized linear blend of the quaternions, whose angular speed dips in
+    /// the middle of large rotations
+    Linear,
+    /// spherical linear interpolation at constant angular speed
+    Slerp,
+}
+
+struct Keyframe {
+    time: f64,
+    scale: DVec3,
+    rotation: DQuat,
+    translation: DVec3,
+}
+
+/// Transform moving through keyframes over time. Keyframe matrices are split
+/// into scale, rotation and translation, so they must not shear. Before the
+/// first and after the last keyframe the transform holds still.
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+}
+
+impl AnimatedTransform {
+    /// keyframes as (time, matrix) pairs in any order, there must be at least one
+    pub fn new(keyframes: Vec<(f64, DMat4)>, interpolation: Interpolation) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "an animated transform needs a keyframe");
+        let mut keyframes: Vec<Keyframe> = keyframes
+            .into_iter()
+            .map(|(time, matrix)| {
+                let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+                Keyframe { time, scale, rotation, translation }
+            })
+            .collect();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        // take the short way round between neighbouring rotations
+        for i in 1..keyframes.len() {
+            if keyframes[i].rotation.dot(keyframes[i - 1].rotation) < 0.0 {
+                keyframes[i].rotation = -keyframes[i].rotation;
+            }
+        }
+        AnimatedTransform { keyframes, interpolation }
+    }
+
+    /// transform that stays at `matrix`
+    pub fn fixed(matrix: DMat4) -> AnimatedTransform {
+        AnimatedTransform::new(vec![(0.0, matrix)], Interpolation::Slerp)
+    }
+
+    /// times of the first and last keyframe
+    pub fn time_range(&self) -> (f64, f64) {
+        (self.keyframes[0].time, self.keyframes[self.keyframes.len() - 1].time)
+    }
+
+    pub fn matrix_at(&self, time: f64) -> DMat4 {
+        let i = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        let (a, b) = match i {
+            0 => (&self.keyframes[0], &self.keyframes[0]),
+            i if i == self.keyframes.len() => (&self.keyframes[i - 1], &self.keyframes[i - 1]),
+            i => (&self.keyframes[i - 1], &self.keyframes[i]),
+        };
+        if a.time == b.time {
+            return DMat4::from_scale_rotation_translation(a.scale, a.rotation, a.translation);
+        }
+        let s = (time - a.time) / (b.time - a.time);
+        let rotation = match self.interpolation {
+            Interpolation::Linear => a.rotation.lerp(b.rotation, s),
+            Interpolation::Slerp => a.rotation.slerp(b.rotation, s),
+        };
+        DMat4::from_scale_rotation_translation(a.scale.lerp(b.scale, s), rotation, a.translation.lerp(b.translation, s))
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        Transform::new(self.matrix_at(time))
+    }
+
+    /// box holding `bbox` carried along the whole motion. The corners are
+    /// followed in small steps, and each step's box is padded by how far the
+    /// corners stray from a straight line halfway through it, which covers the
+    /// arcs rotations sweep in between.
+    pub fn motion_bounds(&self, bbox: &BBox) -> BBox {
+        const STEPS: usize = 32;
+        if bbox.min.cmpgt(bbox.max).any() {
+            return *bbox;
+        }
+        let corners: Vec<DVec3> = (0..8)
+            .map(|i| {
+                DVec3::new(
+                    if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+                    if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+                    if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+                )
+            })
+            .collect();
+        let at = |time: f64| {
+            let matrix = self.matrix_at(time);
+            corners.iter().map(|&corner| matrix.transform_point3(corner)).collect::<Vec<_>>()
+        };
+        let around = |points: &[DVec3], pad: f64| {
+            points.iter().fold(BBox::default(), |bounds, &p| bounds.union(&BBox::new(p - pad, p + pad)))
+        };
+        let mut bounds = around(&at(self.keyframes[0].time), 0.0);
+        for pair in self.keyframes.windows(2) {
+            let mut previous = at(pair[0].time);
+            let step_time = |step: f64| pair[0].time + (pair[1].time - pair[0].time) * step / STEPS as f64;
+            for step in 1..=STEPS {
+                let points = at(step_time(step as f64));
+                let middle = at(step_time(step as f64 - 0.5));
+                let stray = (0..8).map(|i| middle[i].distance((previous[i] + points[i]) / 2.0)).fold(0.0, f64::max);
+                bounds = bounds.union(&around(&previous, stray)).union(&around(&points, stray));
+                previous = points;
+            }
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    fn spin(angle: f64) -> DMat4 {
+        DMat4::from_translation(DVec3::new(2.0, 0.0, 0.0)) * DMat4::from_rotation_y(angle)
+    }
+
+    #[test]
+    fn test_animated_transform() {
+        let keyframes = vec![(1.0, spin(PI / 2.0)), (0.0, spin(0.0))];
+        let slerp = AnimatedTransform::new(keyframes.clone(), Interpolation::Slerp);
+        let linear = AnimatedTransform::new(keyframes, Interpolation::Linear);
+        assert_eq!(slerp.time_range(), (0.0, 1.0));
+        // halfway through a quarter turn slerp is at an eighth, held still outside the keyframes
+        let at = |motion: &AnimatedTransform, time| motion.at(time).point_to_world(DVec3::X);
+        let eighth = DVec3::new(2.0 + (PI / 4.0).cos(), 0.0, -(PI / 4.0).sin());
+        assert!((at(&slerp, 0.5) - eighth).length() < 1e-9);
+        assert!((at(&linear, 0.5) - eighth).length() < 1e-9);
+        assert!((at(&slerp, -1.0) - DVec3::new(3.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((at(&slerp, 2.0) - DVec3::new(2.0, 0.0, -1.0)).length() < 1e-9);
+        // off the middle linear blending lags behind the constant speed of slerp
+        let angle = |motion: &AnimatedTransform| {
+            let p = at(motion, 0.25) - DVec3::new(2.0, 0.0, 0.0);
+            (-p.z).atan2(p.x)
+        };
+        assert!((angle(&slerp) - PI / 8.0).abs() < 1e-9);
+        assert!(angle(&linear) < PI / 8.0 - 1e-3);
+    }
+
+    #[test]
+    fn test_motion_bounds() {
+        // a point swept around half a circle bulges past the keyframes
+        let motion = AnimatedTransform::new(vec![(0.0, spin(0.0)), (1.0, spin(PI))], Interpolation::Slerp);
+        let bounds = motion.motion_bounds(&BBox::new(DVec3::X, DVec3::X));
+        for i in 0..=100 {
+            let p = motion.at(i as f64 / 100.0).point_to_world(DVec3::X);
+            assert!(bounds.contains(&p), "{p}");
+        }
+        assert!(bounds.min.z < -0.99 && bounds.min.z > -1.1, "{bounds:?}");
+    }
+}
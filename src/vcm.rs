@@ -0,0 +1,556 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::sync::RwLock;
+
+use glam::DVec3;
+use rayon::prelude::*;
+
+use crate::{
+    accel::Accel,
+    bbox::BBox,
+    bdpt::{importance_correction, sample_emission},
+    camera::{Camera, Shutter},
+    film::Film,
+    hittable::HitRecord,
+    integrator::{Integrator, RenderContext},
+    light::Light,
+    material::Material,
+    photon::{Located, PhotonMap},
+    ray::Ray,
+    sampler::{RandomSampler, Sampler},
+    scene::Scene,
+};
+
+/// Vertex connection and merging. Light paths are traced ahead of rendering in
+/// `preprocess` and kept in a cache. Every camera path connects to the
+/// vertices of one cached light path like bidirectional path tracing, and
+/// merges with the vertices of all of them within `radius` like photon mapping.
+/// All strategies are weighted with the balance heuristic, using the recursive
+/// partial weights of Georgiev et al. so no path has to be revisited.
+///
+/// The cache is traced once and the radius never shrinks, so like
+/// `PhotonMapIntegrator` the result is biased: more camera samples converge to
+/// the image blurred by merging over `radius`, with the noise of the cached
+/// paths baked in, not to the exact one.
+pub struct VcmIntegrator {
+    /// light paths traced into the cache. With fewer than one per camera
+    /// sample, connections reuse the same paths and their error correlates
+    /// across pixels.
+    pub light_paths: usize,
+    /// merging radius, small against the detail of the lighting to keep the
+    /// blur it leaves invisible
+    pub radius: f64,
+    /// bounces of the cached light paths, camera paths longer than this lose
+    /// the strategies that would need longer light paths
+    pub depth: usize,
+    cache: RwLock<Option<LightPathCache>>,
+}
+
+impl VcmIntegrator {
+    pub fn new(light_paths: usize, radius: f64) -> VcmIntegrator {
+        VcmIntegrator { light_paths, radius, depth: 8, cache: RwLock::new(None) }
+    }
+}
+
+/// Non-specular vertex of a cached light path.
+struct LightVertex {
+    hit: HitRecord<'static>,
+    /// index of the object the vertex is on, in `Scene::objects`
+    object: usize,
+    /// direction towards the previous vertex of the light path
+    wo: DVec3,
+    /// throughput of the light path up to the vertex, divided by its density
+    throughput: DVec3,
+    /// number of segments from the light
+    path_length: usize,
+    /// partial MIS weights, see `PathState`
+    d_vcm: f64,
+    d_vc: f64,
+    d_vm: f64,
+}
+
+/// Position of a cached light vertex, for the merging lookups.
+struct VertexRef {
+    p: DVec3,
+    index: usize,
+}
+
+impl Located for VertexRef {
+    fn position(&self) -> DVec3 {
+        self.p
+    }
+}
+
+struct LightPathCache {
+    vertices: Vec<LightVertex>,
+    /// vertices of each light path, in order of their path length
+    paths: Vec<Range<usize>>,
+    lookup: PhotonMap<VertexRef>,
+    max_path_length: usize,
+}
+
+/// State of a subpath being traced. The partial weights carry the densities
+/// of the other strategies that could have sampled the path so far, relative
+/// to the one that did: `d_vcm` for the strategies ending at the current
+/// vertex, `d_vc` for connections and `d_vm` for merges further down.
+struct PathState {
+    throughput: DVec3,
+    path_length: usize,
+    d_vcm: f64,
+    d_vc: f64,
+    d_vm: f64,
+}
+
+/// Densities and normalization shared by every strategy of a render.
+struct Factors<'a> {
+    scene: &'a Scene,
+    accel: &'a dyn Accel,
+    bounds: BBox,
+    light_pdf: f64,
+    radius: f64,
+    /// relative weight of merging against connecting, pi r^2 times the number of light paths
+    vm_weight: f64,
+    vc_weight: f64,
+    vm_normalization: f64,
+}
+
+impl<'a> Factors<'a> {
+    fn new(scene: &'a Scene, accel: &'a dyn Accel, radius: f64, light_paths: usize) -> Factors<'a> {
+        let eta = PI * radius * radius * light_paths.max(1) as f64;
+        Factors {
+            scene,
+            accel,
+            bounds: accel.bbox(),
+            light_pdf: 1.0 / scene.lights.len().max(1) as f64,
+            radius,
+            vm_weight: eta,
+            vc_weight: 1.0 / eta,
+            vm_normalization: 1.0 / eta,
+        }
+    }
+}
+
+/// density of `light` emitting from `p` in direction `w`, per unit area and solid
+/// angle, with delta distributions counting as 1
+fn emission_pdf(light: &dyn Light, bounds: &BBox, p: DVec3, w: DVec3, normal: DVec3) -> f64 {
+    let (pdf_pos, pdf_dir) = light.pdf_le(bounds, &Ray::new(p, w), normal);
+    match (light.is_delta(), light.is_infinite()) {
+        (true, false) => pdf_dir,
+        (true, true) => pdf_pos,
+        _ => pdf_pos * pdf_dir,
+    }
+}
+
+impl PathState {
+    /// accounts for the segment of length `distance` that reached a surface
+    /// with `cosine` between it and the shading normal
+    fn arrive(&mut self, distance: f64, cosine: f64) {
+        if self.path_length > 1 || distance.is_finite() {
+            self.d_vcm *= distance * distance;
+        }
+        self.d_vcm /= cosine;
+        self.d_vc /= cosine;
+        self.d_vm /= cosine;
+    }
+
+    /// samples the next direction at `hit`, returning the ray to continue along
+    fn scatter(&mut self, factors: &Factors, hit: &HitRecord, wo: DVec3, material: &dyn Material, importance: bool, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let sample = material.sample(wo, hit, sampler)?;
+        if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+            return None;
+        }
+        let cos_out = sample.wi.dot(hit.shading_normal).abs();
+        if sample.flags.is_specular() {
+            // specular lobes are symmetric, the reverse density cancels the forward one
+            self.d_vcm = 0.0;
+            self.d_vc *= cos_out;
+            self.d_vm *= cos_out;
+        } else {
+            let pdf_rev = material.pdf(sample.wi, wo, hit);
+            self.d_vc = cos_out / sample.pdf * (self.d_vc * pdf_rev + self.d_vcm + factors.vm_weight);
+            self.d_vm = cos_out / sample.pdf * (self.d_vm * pdf_rev + self.d_vcm * factors.vc_weight + 1.0);
+            self.d_vcm = 1.0 / sample.pdf;
+        }
+        self.throughput *= sample.weight(hit.shading_normal);
+        if importance {
+            self.throughput *= importance_correction(hit, material, wo, sample.wi);
+        }
+        if self.throughput == DVec3::ZERO {
+            return None;
+        }
+        self.path_length += 1;
+        Some(hit.spawn_ray(sample.wi))
+    }
+}
+
+/// traces one light path, adding its non-specular vertices to `vertices`
+fn trace_light_path(factors: &Factors, shutter: &Shutter, sampler: &mut dyn Sampler, max_path_length: usize, vertices: &mut Vec<LightVertex>) {
+    let Some(emission) = sample_emission(factors.scene, &factors.bounds, sampler) else {
+        return;
+    };
+    let (light, cos_light, emission_pdf) = (emission.light, emission.cosine, emission.pdf());
+    // cached light paths get times of their own, independent of the camera paths
+    let mut ray = emission.sample.ray.with_time(shutter.sample(sampler.get_1d()));
+    let direction = ray.direction.normalize();
+    let d_vc = if light.is_delta() { 0.0 } else { cos_light / emission_pdf };
+    let mut state = PathState {
+        throughput: emission.beta,
+        path_length: 1,
+        d_vcm: 0.0,
+        d_vc,
+        d_vm: d_vc * factors.vc_weight,
+    };
+
+    while let Some(hit) = factors.accel.hit(&ray) {
+        let Some(object) = hit.object else {
+            break;
+        };
+        let wo = -ray.direction.normalize();
+        let cosine = wo.dot(hit.shading_normal).abs();
+        if cosine == 0.0 {
+            break;
+        }
+        let distance = if light.is_infinite() && state.path_length == 1 { f64::INFINITY } else { hit.t * ray.direction.length() };
+        if state.path_length == 1 {
+            // the density of sampling the light from this first vertex, the
+            // shapes with solid angle sampling only know it now
+            state.d_vcm = factors.light_pdf * match (light.is_delta(), light.is_infinite()) {
+                (true, _) => 1.0,
+                (false, true) => light.pdf_li(hit.p, -direction),
+                (false, false) => light.pdf_li(hit.p, -direction) * cos_light / (distance * distance),
+            } / emission_pdf;
+        }
+        state.arrive(distance, cosine);
+
+        let material = object.material.as_ref();
+        if material.flags(&hit).is_non_specular() {
+            vertices.push(LightVertex {
+                hit: hit.detached(),
+                object: object.id,
+                wo,
+                throughput: state.throughput,
+                path_length: state.path_length,
+                d_vcm: state.d_vcm,
+                d_vc: state.d_vc,
+                d_vm: state.d_vm,
+            });
+        }
+        // the next vertex still has to connect to the camera
+        if state.path_length + 2 > max_path_length {
+            break;
+        }
+        let Some(next) = state.scatter(factors, &hit, wo, material, true, sampler) else {
+            break;
+        };
+        ray = next;
+    }
+}
+
+impl VcmIntegrator {
+    fn factors<'a>(&self, scene: &'a Scene, accel: &'a dyn Accel, cache: &LightPathCache) -> Factors<'a> {
+        Factors::new(scene, accel, self.radius, cache.paths.len())
+    }
+
+    /// radiance scattered towards the camera, with light tracing through
+    /// `camera` onto `film` when both are given
+    fn trace(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, camera: Option<(&dyn Camera, &Film)>, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        let cache = self.cache.read().unwrap();
+        let Some(cache) = cache.as_ref() else {
+            return DVec3::ZERO;
+        };
+        if depth < 0 {
+            return DVec3::ZERO;
+        }
+        let factors = self.factors(scene, accel, cache);
+        let max_path_length = (depth as usize + 1).min(cache.max_path_length);
+
+        // the light path this camera path connects to
+        let light_path = match cache.paths.len() {
+            0 => &[][..],
+            count => &cache.vertices[cache.paths[((sampler.get_1d() * count as f64) as usize).min(count - 1)].clone()],
+        };
+
+        let pdf_camera = camera.map_or(0.0, |(camera, _)| camera.pdf_we(ray).1);
+        if let Some((camera, film)) = camera {
+            for vertex in light_path {
+                if vertex.path_length < max_path_length {
+                    connect_to_camera(&factors, vertex, camera, film, sampler.get_2d());
+                }
+            }
+        }
+
+        let mut state = PathState {
+            throughput: DVec3::ONE,
+            path_length: 1,
+            // without a camera there is no light tracing to weight against
+            d_vcm: if pdf_camera > 0.0 { 1.0 / pdf_camera } else { 0.0 },
+            d_vc: 0.0,
+            d_vm: 0.0,
+        };
+        let mut radiance = DVec3::ZERO;
+        let mut ray = ray.clone();
+        let mut previous = ray.origin;
+        loop {
+            let Some(hit) = accel.hit(&ray) else {
+                radiance += state.throughput * background(&factors, &state, &ray, previous);
+                break;
+            };
+            let Some(object) = hit.object else {
+                break;
+            };
+            let wo = -ray.direction.normalize();
+            let cosine = wo.dot(hit.shading_normal).abs();
+            if cosine == 0.0 {
+                break;
+            }
+            let distance = hit.t * ray.direction.length();
+            state.arrive(distance, cosine);
+
+            if let Some(light) = &object.area_light {
+                let emitted = object.emitted(&hit, wo);
+                if emitted != DVec3::ZERO {
+                    let weight = if state.path_length == 1 {
+                        1.0
+                    } else {
+                        let cos_light = wo.dot(hit.normal).abs();
+                        let direct_pdf = factors.light_pdf * light.pdf(previous, ray.direction) * cos_light / (distance * distance);
+                        let emission = factors.light_pdf * emission_pdf(light.as_ref(), &factors.bounds, hit.p, wo, hit.normal);
+                        1.0 / (1.0 + direct_pdf * state.d_vcm + emission * state.d_vc)
+                    };
+                    radiance += state.throughput * emitted * weight;
+                }
+            }
+            if state.path_length >= max_path_length {
+                break;
+            }
+
+            let material = object.material.as_ref();
+            if material.flags(&hit).is_non_specular() {
+                let mut contribution = direct_lighting(&factors, &state, &hit, wo, material, sampler);
+                for vertex in light_path {
+                    if vertex.path_length + state.path_length + 1 > max_path_length {
+                        break;
+                    }
+                    contribution += connect_vertices(&factors, &state, &hit, wo, material, vertex);
+                }
+                contribution += merge(&factors, &state, &hit, wo, material, cache, max_path_length);
+                radiance += state.throughput * contribution;
+            }
+
+            previous = hit.p;
+            let Some(next) = state.scatter(&factors, &hit, wo, material, false, sampler) else {
+                break;
+            };
+            ray = next;
+        }
+        radiance
+    }
+}
+
+/// light at infinity found by a camera path that left the scene
+fn background(factors: &Factors, state: &PathState, ray: &Ray, previous: DVec3) -> DVec3 {
+    let mut radiance = DVec3::ZERO;
+    for light in factors.scene.lights.iter().filter(|light| light.is_infinite()) {
+        let le = light.le(ray);
+        if le == DVec3::ZERO {
+            continue;
+        }
+        if state.path_length == 1 {
+            radiance += le;
+            continue;
+        }
+        let direction = ray.direction.normalize();
+        let direct_pdf = factors.light_pdf * light.pdf_li(previous, direction);
+        let emission = factors.light_pdf * emission_pdf(light.as_ref(), &factors.bounds, previous, -direction, DVec3::ZERO);
+        radiance += le / (1.0 + direct_pdf * state.d_vcm + emission * state.d_vc);
+    }
+    radiance
+}
+
+/// connects a camera vertex to a point sampled on a light
+fn direct_lighting(factors: &Factors, state: &PathState, hit: &HitRecord, wo: DVec3, material: &dyn Material, sampler: &mut dyn Sampler) -> DVec3 {
+    let Some((light, _)) = factors.scene.pick_light(sampler.get_1d()) else {
+        return DVec3::ZERO;
+    };
+    let Some(sample) = light.sample_li(hit.p, sampler.get_2d()) else {
+        return DVec3::ZERO;
+    };
+    if sample.pdf <= 0.0 || sample.li == DVec3::ZERO || !hit.shading_consistent(wo, sample.wi) {
+        return DVec3::ZERO;
+    }
+    let f = material.eval(wo, sample.wi, hit);
+    if f == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+    let cos_to_light = sample.wi.dot(hit.shading_normal).abs();
+    // solid angle density of the light sample, with delta positions counting
+    // as a unit area density
+    let (direct_pdf, cos_at_light) = if light.is_delta() && !light.is_infinite() {
+        (sample.distance * sample.distance, 1.0)
+    } else if sample.normal == DVec3::ZERO {
+        (sample.pdf, 1.0)
+    } else {
+        (sample.pdf, sample.normal.dot(sample.wi).abs())
+    };
+    let p_light = if sample.distance.is_finite() { hit.p + sample.wi * sample.distance } else { hit.p };
+    let emission = emission_pdf(light.as_ref(), &factors.bounds, p_light, -sample.wi, sample.normal);
+
+    let w_light = if light.is_delta() { 0.0 } else { material.pdf(wo, sample.wi, hit) / (factors.light_pdf * sample.pdf) };
+    let w_camera = emission * cos_to_light / (direct_pdf * cos_at_light)
+        * (factors.vm_weight + state.d_vcm + state.d_vc * material.pdf(sample.wi, wo, hit));
+    if factors.accel.hit(&hit.spawn_shadow_ray(sample.wi, sample.distance)).is_some() {
+        return DVec3::ZERO;
+    }
+    f * cos_to_light * sample.li / (factors.light_pdf * sample.pdf * (w_light + 1.0 + w_camera))
+}
+
+/// connects a camera vertex to a vertex of the light path
+fn connect_vertices(factors: &Factors, state: &PathState, hit: &HitRecord, wo: DVec3, material: &dyn Material, vertex: &LightVertex) -> DVec3 {
+    let d = vertex.hit.p - hit.p;
+    let distance_squared = d.length_squared();
+    if distance_squared == 0.0 {
+        return DVec3::ZERO;
+    }
+    let distance = distance_squared.sqrt();
+    let w = d / distance;
+    let light_material = factors.scene.objects[vertex.object].material.as_ref();
+    if !hit.shading_consistent(wo, w) || !vertex.hit.shading_consistent(vertex.wo, -w) {
+        return DVec3::ZERO;
+    }
+    let f_camera = material.eval(wo, w, hit);
+    let f_light = light_material.eval(vertex.wo, -w, &vertex.hit) * importance_correction(&vertex.hit, light_material, vertex.wo, -w);
+    if f_camera == DVec3::ZERO || f_light == DVec3::ZERO {
+        return DVec3::ZERO;
+    }
+    let cos_camera = w.dot(hit.shading_normal).abs();
+    let cos_light = w.dot(vertex.hit.shading_normal).abs();
+    let camera_pdf = material.pdf(wo, w, hit) * cos_light / distance_squared;
+    let light_pdf = light_material.pdf(vertex.wo, -w, &vertex.hit) * cos_camera / distance_squared;
+    let w_light = camera_pdf * (factors.vm_weight + vertex.d_vcm + vertex.d_vc * light_material.pdf(-w, vertex.wo, &vertex.hit));
+    let w_camera = light_pdf * (factors.vm_weight + state.d_vcm + state.d_vc * material.pdf(w, wo, hit));
+    if factors.accel.hit(&hit.spawn_shadow_ray(w, distance)).is_some() {
+        return DVec3::ZERO;
+    }
+    let g = cos_camera * cos_light / distance_squared;
+    f_camera * f_light * vertex.throughput * g / (w_light + 1.0 + w_camera)
+}
+
+/// merges a camera vertex with the light vertices around it
+fn merge(factors: &Factors, state: &PathState, hit: &HitRecord, wo: DVec3, material: &dyn Material, cache: &LightPathCache, max_path_length: usize) -> DVec3 {
+    let mut contribution = DVec3::ZERO;
+    cache.lookup.within(hit.p, factors.radius, |found| {
+        let vertex = &cache.vertices[found.index];
+        if vertex.path_length + state.path_length > max_path_length || !hit.shading_consistent(wo, vertex.wo) {
+            return;
+        }
+        let f = material.eval(wo, vertex.wo, hit);
+        if f == DVec3::ZERO {
+            return;
+        }
+        let w_light = vertex.d_vcm * factors.vc_weight + vertex.d_vm * material.pdf(wo, vertex.wo, hit);
+        let w_camera = state.d_vcm * factors.vc_weight + state.d_vm * material.pdf(vertex.wo, wo, hit);
+        contribution += f * vertex.throughput / (w_light + 1.0 + w_camera);
+    });
+    contribution * factors.vm_normalization
+}
+
+/// splats the light a light vertex scatters straight into the camera
+fn connect_to_camera(factors: &Factors, vertex: &LightVertex, camera: &dyn Camera, film: &Film, lens: (f64, f64)) {
+    let Some(sample) = camera.sample_wi(vertex.hit.p, lens) else {
+        return;
+    };
+    if sample.pdf <= 0.0 || sample.importance <= 0.0 || !vertex.hit.shading_consistent(vertex.wo, sample.wi) {
+        return;
+    }
+    let material = factors.scene.objects[vertex.object].material.as_ref();
+    let f = material.eval(vertex.wo, sample.wi, &vertex.hit) * importance_correction(&vertex.hit, material, vertex.wo, sample.wi);
+    if f == DVec3::ZERO {
+        return;
+    }
+    let to_camera = sample.position - vertex.hit.p;
+    let distance = to_camera.length();
+    let cos_to_camera = sample.wi.dot(vertex.hit.shading_normal).abs();
+    // density of the camera sampling the vertex, per unit area
+    let camera_pdf = camera.pdf_we(&Ray::new(sample.position, -sample.wi)).1 * cos_to_camera / (distance * distance);
+    let w_light = camera_pdf * (factors.vm_weight + vertex.d_vcm + vertex.d_vc * material.pdf(sample.wi, vertex.wo, &vertex.hit));
+    if factors.accel.hit(&vertex.hit.spawn_shadow_ray(sample.wi, distance)).is_some() {
+        return;
+    }
+    let value = vertex.throughput * f * cos_to_camera * sample.importance / (sample.pdf * (1.0 + w_light));
+    film.add_splat(sample.uv.x, sample.uv.y, value);
+}
+
+impl Integrator for VcmIntegrator {
+    fn preprocess(&self, scene: &Scene, accel: &dyn Accel, camera: &dyn Camera) {
+        let max_path_length = self.depth + 1;
+        let factors = Factors::new(scene, accel, self.radius, self.light_paths);
+        let paths: Vec<Vec<LightVertex>> = (0..self.light_paths)
+            .into_par_iter()
+            .map_init(RandomSampler::new, |sampler, _| {
+                let mut vertices = Vec::new();
+                trace_light_path(&factors, camera.shutter(), sampler, max_path_length, &mut vertices);
+                vertices
+            })
+            .collect();
+
+        let mut vertices = Vec::new();
+        let mut ranges = Vec::with_capacity(paths.len());
+        for path in paths {
+            let start = vertices.len();
+            vertices.extend(path);
+            ranges.push(start..vertices.len());
+        }
+        let refs = vertices.iter().enumerate().map(|(index, vertex)| VertexRef { p: vertex.hit.p, index }).collect();
+        let lookup = PhotonMap::new(refs, self.light_paths);
+        *self.cache.write().unwrap() = Some(LightPathCache { vertices, paths: ranges, lookup, max_path_length });
+    }
+
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        self.trace(ray, scene, accel, None, sampler, depth)
+    }
+
+    fn li_camera(&self, ray: &Ray, ctx: &RenderContext, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        let camera = ctx.camera.has_importance().then_some((ctx.camera, ctx.film));
+        self.trace(ray, ctx.scene, ctx.accel, camera, sampler, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        integrator::PathIntegrator,
+        material::Lambertian,
+        object::Object,
+        renderer::Renderer,
+        test_scenes::{average, floor, lamp, square},
+    };
+
+    #[test]
+    fn test_vcm_matches_path_tracing() {
+        // a floor lit directly by a lamp facing down and indirectly by the ceiling
+        let mut scene = Scene::new();
+        scene.set_environment(None);
+        scene.add(floor(0.5));
+        scene.add(Object::new(square(DVec3::new(0.0, 2.0, 0.0), 20.0, false), Arc::new(Lambertian::new(DVec3::splat(0.8)))));
+        scene.add(lamp(DVec3::new(0.0, 1.0, 0.0), false, 1.0));
+        let camera = PerspectiveCamera::new(DVec3::new(0.0, 0.7, 3.0), DVec3::new(0.0, 0.0, 0.0), DVec3::Y, 40.0, 4.0 / 3.0);
+
+        let mut reference = Renderer::new(16, 12, 1024, 5);
+        reference.render(&camera, &scene, &PathIntegrator::new());
+        let expected = average(&reference);
+        assert!(expected.x > 0.03, "{expected}");
+
+        // one cached path per camera sample, and a radius far below the size of
+        // the lamp, keep the bias of the fixed cache under the tolerance
+        let mut integrator = VcmIntegrator::new(16 * 12 * 256, 0.01);
+        integrator.depth = 5;
+        let mut vcm = Renderer::new(16, 12, 256, 5);
+        vcm.render(&camera, &scene, &integrator);
+        let value = average(&vcm);
+        assert!(((value - expected) / expected).abs().max_element() < 0.03, "{value} vs {expected}");
+    }
+}
@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    accel::Accel,
+    bsdf::BsdfFlags,
+    hittable::HitRecord,
+    integrator::{Integrator, sample_light_at},
+    material::Material,
+    medium::{HenyeyGreenstein, Medium},
+    ray::Ray,
+    sampler::Sampler,
+    sampling::power_heuristic,
+    scene::Scene,
+};
+
+/// Where a path scatters: on a surface through its BSDF, or inside a medium
+/// through its phase function.
+enum Scatter<'a> {
+    Surface { hit: &'a HitRecord<'a>, material: &'a dyn Material },
+    Medium { p: DVec3, phase: HenyeyGreenstein, time: f64 },
+}
+
+impl Scatter<'_> {
+    fn p(&self) -> DVec3 {
+        match self {
+            Scatter::Surface { hit, .. } => hit.p,
+            Scatter::Medium { p, .. } => *p,
+        }
+    }
+
+    /// f * |cos| on surfaces, the phase function in media
+    fn f(&self, wo: DVec3, wi: DVec3) -> DVec3 {
+        match self {
+            Scatter::Surface { hit, material } => material.eval(wo, wi, hit) * wi.dot(hit.shading_normal).abs(),
+            Scatter::Medium { phase, .. } => DVec3::splat(phase.p(wo, wi)),
+        }
+    }
+
+    fn pdf(&self, wo: DVec3, wi: DVec3) -> f64 {
+        match self {
+            Scatter::Surface { hit, material } => material.pdf(wo, wi, hit),
+            Scatter::Medium { phase, .. } => phase.p(wo, wi),
+        }
+    }
+
+    fn shadow_ray(&self, wi: DVec3, distance: f64) -> Ray {
+        match self {
+            Scatter::Surface { hit, .. } => hit.spawn_shadow_ray(wi, distance),
+            Scatter::Medium { p, time, .. } => {
+                let mut ray = Ray::new(*p, wi).with_time(*time);
+                ray.max_t = distance * (1.0 - 1e-4);
+                ray
+            }
+        }
+    }
+}
+
+/// whether `hit` only separates two media and lets light straight through
+fn is_interface(hit: &HitRecord) -> bool {
+    hit.object.is_some_and(|object| object.material.flags(hit) == BsdfFlags::NONE)
+}
+
+/// medium a ray leaving `hit` in `direction` travels through, given the medium
+/// `current` it arrived in
+fn next_medium(hit: &HitRecord, direction: DVec3, current: &Option<Arc<dyn Medium>>) -> Option<Arc<dyn Medium>> {
+    match hit.object.and_then(|object| object.media.as_ref()) {
+        Some(media) => media.next(direction, hit.normal),
+        None => current.clone(),
+    }
+}
+
+/// transmittance along `ray` up to its `max_t`, starting in `medium` and passing
+/// through interfaces between media. Zero if any other surface is in the way.
+pub(crate) fn transmittance(
+    accel: &dyn Accel,
+    ray: &Ray,
+    mut medium: Option<Arc<dyn Medium>>,
+    sampler: &mut dyn Sampler,
+) -> DVec3 {
+    let mut ray = ray.clone();
+    let mut tr = DVec3::ONE;
+    loop {
+        let hit = accel.hit(&ray);
+        if hit.as_ref().is_some_and(|hit| !is_interface(hit)) {
+            return DVec3::ZERO;
+        }
+        if let Some(medium) = &medium {
+            tr *= medium.tr(&ray, hit.as_ref().map_or(ray.max_t, |hit| hit.t), sampler);
+        }
+        let Some(hit) = hit else {
+            return tr;
+        };
+        if tr == DVec3::ZERO {
+            return tr;
+        }
+        medium = next_medium(&hit, ray.direction, &medium);
+        let max_t = ray.max_t - hit.t;
+        ray = hit.spawn_ray(ray.direction);
+        ray.max_t = max_t;
+    }
+}
+
+/// light arriving at a scattering point from one uniformly picked light, through
+/// the media on the way, weighted against finding the light by sampling `scatter`
+fn sample_light(
+    scene: &Scene,
+    accel: &dyn Accel,
+    scatter: &Scatter,
+    wo: DVec3,
+    medium: &Option<Arc<dyn Medium>>,
+    sampler: &mut dyn Sampler,
+) -> DVec3 {
+    sample_light_at(
+        scene,
+        scatter.p(),
+        |wi| match scatter {
+            Scatter::Surface { hit, .. } if !hit.shading_consistent(wo, wi) => DVec3::ZERO,
+            _ => scatter.f(wo, wi),
+        },
+        |wi| scatter.pdf(wo, wi),
+        |wi, distance, sampler| {
+            let medium = match scatter {
+                Scatter::Surface { hit, .. } => next_medium(hit, wi, medium),
+                Scatter::Medium { .. } => medium.clone(),
+            };
+            transmittance(accel, &scatter.shadow_ray(wi, distance), medium, sampler)
+        },
+        sampler,
+        true,
+    )
+}
+
+/// Path tracer through participating media. Free flights through a medium are
+/// sampled by the medium, either ending in a scattering event that samples a
+/// light and the phase function, or reaching the next surface which is handled
+/// like in `PathIntegrator`. Shadow rays estimate the transmittance through
+/// every medium they cross. Camera rays start in `scene.medium`, and surfaces
+/// with a `material::Interface` are crossed without counting as a bounce.
+pub struct VolPathIntegrator {
+    pub rr_depth: i32,
+}
+
+impl Default for VolPathIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VolPathIntegrator {
+    pub fn new() -> VolPathIntegrator {
+        VolPathIntegrator { rr_depth: 3 }
+    }
+}
+
+impl Integrator for VolPathIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, accel: &dyn Accel, sampler: &mut dyn Sampler, depth: i32) -> DVec3 {
+        let mut radiance = DVec3::ZERO;
+        let mut beta = DVec3::ONE;
+        let mut ray = ray.clone();
+        let mut medium = scene.medium.clone();
+        // state of the previous vertex, to weight light that BSDF or phase sampling finds
+        let mut specular_bounce = false;
+        let mut scatter_pdf = 0.0;
+        let mut previous = DVec3::ZERO;
+        let mut eta_scale = 1.0;
+        let select_pdf = 1.0 / scene.lights.len().max(1) as f64;
+        let mut bounce = 0;
+
+        loop {
+            let hit = accel.hit(&ray);
+            let mut interaction = None;
+            if let Some(medium) = &medium {
+                let sample = medium.sample(&ray, hit.as_ref().map_or(ray.max_t, |hit| hit.t), sampler);
+                radiance += beta * sample.emission;
+                beta *= sample.weight;
+                interaction = sample.interaction;
+            }
+            if beta == DVec3::ZERO {
+                break;
+            }
+
+            if let Some(interaction) = interaction {
+                if bounce >= depth {
+                    break;
+                }
+                let wo = -ray.direction.normalize();
+                let scatter = Scatter::Medium { p: interaction.p, phase: interaction.phase, time: ray.time };
+                radiance += beta * sample_light(scene, accel, &scatter, wo, &medium, sampler);
+                // the phase function is sampled exactly, so beta stays as it is
+                let (wi, pdf) = interaction.phase.sample_p(wo, sampler.get_2d());
+                specular_bounce = false;
+                scatter_pdf = pdf;
+                previous = interaction.p;
+                ray = Ray::new(interaction.p, wi).with_time(ray.time);
+            } else {
+                let Some(hit) = hit else {
+                    for light in &scene.lights {
+                        let le = light.le(&ray);
+                        if le == DVec3::ZERO {
+                            continue;
+                        }
+                        if bounce == 0 || specular_bounce {
+                            radiance += beta * le;
+                        } else {
+                            let weight = power_heuristic(scatter_pdf, select_pdf * light.pdf_li(previous, ray.direction));
+                            radiance += beta * le * weight;
+                        }
+                    }
+                    break;
+                };
+                let Some(object) = hit.object else {
+                    break;
+                };
+                let wo = -ray.direction.normalize();
+
+                let emitted = object.emitted(&hit, wo);
+                if emitted != DVec3::ZERO {
+                    if bounce == 0 || specular_bounce {
+                        radiance += beta * emitted;
+                    } else if let Some(light) = &object.area_light {
+                        let weight = power_heuristic(scatter_pdf, select_pdf * light.pdf(previous, ray.direction));
+                        radiance += beta * emitted * weight;
+                    }
+                }
+
+                let material = object.material.as_ref();
+                if is_interface(&hit) {
+                    medium = next_medium(&hit, ray.direction, &medium);
+                    ray = hit.spawn_ray(ray.direction);
+                    continue;
+                }
+                if bounce >= depth {
+                    break;
+                }
+
+                if material.flags(&hit).is_non_specular() {
+                    let scatter = Scatter::Surface { hit: &hit, material };
+                    radiance += beta * sample_light(scene, accel, &scatter, wo, &medium, sampler);
+                }
+
+                let Some(sample) = material.sample(wo, &hit, sampler) else {
+                    break;
+                };
+                if sample.pdf <= 0.0 || !hit.shading_consistent(wo, sample.wi) {
+                    break;
+                }
+                beta *= sample.weight(hit.shading_normal);
+                specular_bounce = sample.flags.is_specular();
+                scatter_pdf = sample.pdf;
+                if sample.flags.contains(BsdfFlags::TRANSMISSION) {
+                    eta_scale *= sample.eta * sample.eta;
+                }
+                previous = hit.p;
+                medium = next_medium(&hit, sample.wi, &medium);
+                ray = hit.spawn_ray_with_differentials(&ray, &sample);
+            }
+
+            let rr_beta = (beta * eta_scale).max_element();
+            if rr_beta < 1.0 && bounce >= self.rr_depth {
+                let q = 1.0 - rr_beta;
+                if sampler.get_1d() < q {
+                    break;
+                }
+                beta /= 1.0 - q;
+            }
+            if beta == DVec3::ZERO {
+                break;
+            }
+            bounce += 1;
+        }
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accel::BVH,
+        hittable::{Hittable, Sphere},
+        light::GradientSky,
+        material::Interface,
+        medium::HomogeneousMedium,
+        object::Object,
+        sampler::RandomSampler,
+    };
+
+    fn estimate(scene: &Scene, ray: &Ray, samples: usize, depth: i32) -> DVec3 {
+        let accel = BVH::new(&scene.objects);
+        let integrator = VolPathIntegrator::new();
+        let mut sampler = RandomSampler::seeded(9);
+        (0..samples).map(|_| integrator.li(ray, scene, &accel, &mut sampler, depth)).sum::<DVec3>() / samples as f64
+    }
+
+    fn fog_ball(medium: HomogeneousMedium) -> Scene {
+        let mut scene = Scene::new();
+        scene.set_environment(Some(Arc::new(GradientSky::new(DVec3::ONE, DVec3::ONE))));
+        let sphere = Arc::new(Sphere::new(DVec3::ZERO, 1.0)) as Arc<dyn Hittable>;
+        scene.add(Object::new(sphere, Arc::new(Interface)).with_media(Some(Arc::new(medium)), None));
+        scene
+    }
+
+    #[test]
+    fn test_absorbing_medium_transmittance() {
+        // looking through the middle of an absorbing ball gives exp(-2 sigma_a r)
+        let sigma_a = DVec3::new(0.2, 0.5, 1.0);
+        let scene = fog_ball(HomogeneousMedium::new(sigma_a, DVec3::ZERO, 0.0));
+        let value = estimate(&scene, &Ray::new(DVec3::new(0.0, 0.0, 5.0), -DVec3::Z), 4000, 8);
+        let expected = (-2.0 * sigma_a).exp();
+        assert!((value - expected).abs().max_element() < 1e-2, "{value} vs {expected}");
+    }
+
+    #[test]
+    fn test_scattering_medium_furnace() {
+        // a ball of non-absorbing fog under a uniform sky neither adds nor removes light
+        let scene = fog_ball(HomogeneousMedium::new(DVec3::ZERO, DVec3::splat(2.0), 0.5));
+        let value = estimate(&scene, &Ray::new(DVec3::new(0.3, 0.2, 5.0), -DVec3::Z), 20000, 100);
+        assert!((value - DVec3::ONE).abs().max_element() < 1e-2, "{value}");
+    }
+}
@@ -0,0 +1,649 @@
+//! Voxel grids and the heterogeneous medium built from them.
+//!
+//! Grids are stored on disk in a small binary format, all little endian:
+//!
+//! | bytes            | content                                                  |
+//! |------------------|----------------------------------------------------------|
+//! | 4                | magic `RVOL`                                             |
+//! | 4                | version, `u32` 1                                         |
+//! | 12               | resolution `nx`, `ny`, `nz` as `u32`                     |
+//! | 4                | number of channels as `u32`                              |
+//! | 16 per channel   | channel name in ASCII, padded with zero bytes            |
+//! | 4 nx ny nz each  | voxels of every channel in order, `f32` with x fastest   |
+//!
+//! `GridMedium::load` reads the channels `density` (required), `emission` and
+//! `temperature` in kelvin.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::DVec3;
+
+use crate::{
+    bbox::BBox,
+    medium::{HenyeyGreenstein, Medium, MediumInteraction, MediumSample},
+    ray::Ray,
+    sampler::Sampler,
+};
+
+const MAGIC: &[u8; 4] = b"RVOL";
+const VERSION: u32 = 1;
+const NAME_LENGTH: usize = 16;
+
+/// Scalar values on a regular grid of voxels covering the unit cube, with
+/// voxel (x, y, z) centered at ((x, y, z) + 0.5) / resolution.
+pub trait VoxelGrid : Send + Sync {
+    fn resolution(&self) -> [usize; 3];
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64;
+
+    /// largest voxel in the inclusive index range from `lo` to `hi`
+    fn max_in(&self, lo: [usize; 3], hi: [usize; 3]) -> f64 {
+        let mut max = f64::NEG_INFINITY;
+        for z in lo[2]..=hi[2] {
+            for y in lo[1]..=hi[1] {
+                for x in lo[0]..=hi[0] {
+                    max = max.max(self.voxel(x, y, z));
+                }
+            }
+        }
+        max
+    }
+
+    /// trilinear interpolation at `p` in the unit cube, clamped to the outer
+    /// voxels at its faces and zero outside
+    fn lookup(&self, p: DVec3) -> f64 {
+        if p.cmplt(DVec3::ZERO).any() || p.cmpgt(DVec3::ONE).any() {
+            return 0.0;
+        }
+        let resolution = self.resolution();
+        let mut index = [0usize; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let n = resolution[axis];
+            let x = (p[axis] * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            index[axis] = (x as usize).min(n.saturating_sub(2));
+            fraction[axis] = x - index[axis] as f64;
+        }
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut voxel = index;
+            for axis in 0..3 {
+                if corner & (1 << axis) != 0 {
+                    weight *= fraction[axis];
+                    voxel[axis] = (voxel[axis] + 1).min(resolution[axis] - 1);
+                } else {
+                    weight *= 1.0 - fraction[axis];
+                }
+            }
+            if weight > 0.0 {
+                value += weight * self.voxel(voxel[0], voxel[1], voxel[2]);
+            }
+        }
+        value
+    }
+}
+
+/// Grid storing every voxel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenseGrid {
+    pub resolution: [usize; 3],
+    /// voxels with x fastest, then y, then z
+    pub values: Vec<f32>,
+}
+
+impl DenseGrid {
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> DenseGrid {
+        assert!(resolution.iter().all(|&n| n > 0), "empty grid");
+        assert_eq!(values.len(), resolution.iter().product::<usize>(), "wrong number of voxels");
+        DenseGrid { resolution, values }
+    }
+}
+
+impl VoxelGrid for DenseGrid {
+    fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(z * ny + y) * nx + x] as f64
+    }
+}
+
+const BRICK: usize = 8;
+
+/// Grid split into bricks of 8^3 voxels where only bricks with a non-zero
+/// voxel are stored, for mostly empty volumes like smoke plumes.
+pub struct BrickGrid {
+    resolution: [usize; 3],
+    /// bricks along each axis
+    bricks: [usize; 3],
+    /// index into `data` for every brick, `None` for empty ones
+    lookup: Vec<Option<u32>>,
+    data: Vec<[f32; BRICK * BRICK * BRICK]>,
+}
+
+impl BrickGrid {
+    pub fn from_dense(grid: &DenseGrid) -> BrickGrid {
+        let resolution = grid.resolution;
+        let bricks = resolution.map(|n| n.div_ceil(BRICK));
+        let mut lookup = Vec::with_capacity(bricks.iter().product());
+        let mut data = Vec::new();
+        for bz in 0..bricks[2] {
+            for by in 0..bricks[1] {
+                for bx in 0..bricks[0] {
+                    let mut brick = [0.0; BRICK * BRICK * BRICK];
+                    for (i, value) in brick.iter_mut().enumerate() {
+                        let (x, y, z) = (bx * BRICK + i % BRICK, by * BRICK + i / BRICK % BRICK, bz * BRICK + i / (BRICK * BRICK));
+                        if x < resolution[0] && y < resolution[1] && z < resolution[2] {
+                            *value = grid.voxel(x, y, z) as f32;
+                        }
+                    }
+                    if brick.iter().any(|&value| value != 0.0) {
+                        lookup.push(Some(data.len() as u32));
+                        data.push(brick);
+                    } else {
+                        lookup.push(None);
+                    }
+                }
+            }
+        }
+        BrickGrid { resolution, bricks, lookup, data }
+    }
+
+    /// number of bricks actually stored
+    pub fn stored_bricks(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl VoxelGrid for BrickGrid {
+    fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        let brick = ((z / BRICK) * self.bricks[1] + y / BRICK) * self.bricks[0] + x / BRICK;
+        match self.lookup[brick] {
+            Some(index) => {
+                let (x, y, z) = (x % BRICK, y % BRICK, z % BRICK);
+                self.data[index as usize][(z * BRICK + y) * BRICK + x] as f64
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Named channels of a volume file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeFile {
+    pub channels: Vec<(String, DenseGrid)>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+impl VolumeFile {
+    pub fn channel(&self, name: &str) -> Option<&DenseGrid> {
+        self.channels.iter().find(|(channel, _)| channel == name).map(|(_, grid)| grid)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VolumeFile> {
+        VolumeFile::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<VolumeFile> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a volume file"));
+        }
+        if read_u32(reader)? != VERSION {
+            return Err(invalid("unsupported volume file version"));
+        }
+        let mut resolution = [0; 3];
+        for n in &mut resolution {
+            *n = read_u32(reader)? as usize;
+        }
+        if resolution.contains(&0) {
+            return Err(invalid("empty volume"));
+        }
+        // sizes come from the file, so nothing is reserved up front and the
+        // data grows as it is actually read
+        let count = read_u32(reader)? as usize;
+        let mut names = Vec::new();
+        for _ in 0..count {
+            let mut name = [0; NAME_LENGTH];
+            reader.read_exact(&mut name)?;
+            let end = name.iter().position(|&byte| byte == 0).unwrap_or(NAME_LENGTH);
+            let name = std::str::from_utf8(&name[..end]).map_err(|_| invalid("channel name is not ASCII"))?;
+            names.push(name.to_string());
+        }
+        let bytes = resolution
+            .iter()
+            .try_fold(4usize, |bytes, &n| bytes.checked_mul(n))
+            .ok_or_else(|| invalid("volume too large"))?;
+        let mut channels = Vec::new();
+        for name in names {
+            let mut data = Vec::new();
+            reader.by_ref().take(bytes as u64).read_to_end(&mut data)?;
+            if data.len() != bytes {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "volume file ends inside a channel"));
+            }
+            let values = data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+            channels.push((name, DenseGrid::new(resolution, values)));
+        }
+        Ok(VolumeFile { channels })
+    }
+
+    /// writes the channels, which all need the same resolution
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let resolution = self.channels.first().map_or([1; 3], |(_, grid)| grid.resolution);
+        if self.channels.iter().any(|(_, grid)| grid.resolution != resolution) {
+            return Err(invalid("channels differ in resolution"));
+        }
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        for n in resolution {
+            writer.write_all(&(n as u32).to_le_bytes())?;
+        }
+        writer.write_all(&(self.channels.len() as u32).to_le_bytes())?;
+        for (name, _) in &self.channels {
+            if name.len() > NAME_LENGTH || !name.is_ascii() {
+                return Err(invalid("channel names are at most 16 ASCII characters"));
+            }
+            let mut bytes = [0; NAME_LENGTH];
+            bytes[..name.len()].copy_from_slice(name.as_bytes());
+            writer.write_all(&bytes)?;
+        }
+        for (_, grid) in &self.channels {
+            for value in &grid.values {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spectral radiance of a blackbody at `temperature` kelvin in the red, green
+/// and blue, normalized so that the peak of its spectrum is one.
+pub fn blackbody(temperature: f64) -> DVec3 {
+    if temperature <= 0.0 {
+        return DVec3::ZERO;
+    }
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+    let planck = |lambda: f64| 2.0 * H * C * C / (lambda.powi(5) * ((H * C / (lambda * KB * temperature)).exp() - 1.0));
+    // Wien's displacement law gives the peak
+    let peak = planck(2.897_771_955e-3 / temperature);
+    DVec3::new(planck(610e-9), planck(550e-9), planck(465e-9)) / peak
+}
+
+const MAJORANT_RESOLUTION: usize = 16;
+
+/// Upper bounds of a grid over coarse cells of the unit cube, which delta and
+/// ratio tracking step through so that sparse regions take few steps.
+struct MajorantGrid {
+    resolution: [usize; 3],
+    max: Vec<f64>,
+}
+
+impl MajorantGrid {
+    fn new(grid: &dyn VoxelGrid) -> MajorantGrid {
+        let voxels = grid.resolution();
+        let resolution = voxels.map(|n| n.min(MAJORANT_RESOLUTION));
+        let mut max = Vec::with_capacity(resolution.iter().product());
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    // every voxel trilinear lookups in the cell can touch
+                    let cell = [x, y, z];
+                    let lo = [0, 1, 2].map(|a| (cell[a] * voxels[a] / resolution[a]).saturating_sub(1));
+                    let hi = [0, 1, 2].map(|a| ((cell[a] + 1) * voxels[a]).div_ceil(resolution[a]).min(voxels[a] - 1));
+                    max.push(grid.max_in(lo, hi).max(0.0));
+                }
+            }
+        }
+        MajorantGrid { resolution, max }
+    }
+
+    /// walks the cells `origin + t direction` passes through for t in [t0, t1],
+    /// calling `visit(start, end, majorant)` until it returns false
+    fn traverse(&self, origin: DVec3, direction: DVec3, t0: f64, t1: f64, mut visit: impl FnMut(f64, f64, f64) -> bool) {
+        let p = origin + direction * t0;
+        let mut cell = [0i64; 3];
+        let mut next = [f64::INFINITY; 3];
+        let mut delta = [f64::INFINITY; 3];
+        let mut step = [0i64; 3];
+        for axis in 0..3 {
+            let n = self.resolution[axis] as f64;
+            cell[axis] = ((p[axis] * n) as i64).clamp(0, self.resolution[axis] as i64 - 1);
+            if direction[axis] > 0.0 {
+                next[axis] = t0 + ((cell[axis] + 1) as f64 / n - p[axis]) / direction[axis];
+                delta[axis] = 1.0 / (n * direction[axis]);
+                step[axis] = 1;
+            } else if direction[axis] < 0.0 {
+                next[axis] = t0 + (cell[axis] as f64 / n - p[axis]) / direction[axis];
+                delta[axis] = -1.0 / (n * direction[axis]);
+                step[axis] = -1;
+            }
+        }
+        let mut t = t0;
+        loop {
+            let axis = if next[0] < next[1] && next[0] < next[2] { 0 } else if next[1] < next[2] { 1 } else { 2 };
+            let end = next[axis].min(t1);
+            let index = ((cell[2] as usize * self.resolution[1]) + cell[1] as usize) * self.resolution[0] + cell[0] as usize;
+            if !visit(t, end, self.max[index]) || end >= t1 {
+                return;
+            }
+            t = end;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.resolution[axis] as i64 {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+}
+
+/// Heterogeneous medium whose coefficients are scaled by a density grid
+/// stretched over `bounds`, and which can emit light from an emission or a
+/// temperature grid. Free flights use delta tracking and transmittance uses
+/// ratio tracking, both against the majorants of coarse cells of the grid.
+pub struct GridMedium {
+    pub bounds: BBox,
+    /// absorption and scattering at a density of one
+    pub sigma_a: DVec3,
+    pub sigma_s: DVec3,
+    pub phase: HenyeyGreenstein,
+    density: Arc<dyn VoxelGrid>,
+    majorants: MajorantGrid,
+    emission: Option<(Arc<dyn VoxelGrid>, DVec3)>,
+    temperature: Option<(Arc<dyn VoxelGrid>, f64)>,
+}
+
+impl GridMedium {
+    pub fn new(bounds: BBox, density: Arc<dyn VoxelGrid>, sigma_a: DVec3, sigma_s: DVec3, g: f64) -> GridMedium {
+        let majorants = MajorantGrid::new(density.as_ref());
+        GridMedium {
+            bounds,
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+            density,
+            majorants,
+            emission: None,
+            temperature: None,
+        }
+    }
+
+    /// emits `scale` times the grid's value, wherever the medium absorbs
+    pub fn with_emission(self, emission: Arc<dyn VoxelGrid>, scale: DVec3) -> GridMedium {
+        GridMedium { emission: Some((emission, scale)), ..self }
+    }
+
+    /// emits blackbody radiation for the grid's temperature in kelvin, with the
+    /// peak of the spectrum scaled to `scale`
+    pub fn with_temperature(self, temperature: Arc<dyn VoxelGrid>, scale: f64) -> GridMedium {
+        GridMedium { temperature: Some((temperature, scale)), ..self }
+    }
+
+    /// medium from the channels of a volume file, stored as brick grids when
+    /// `sparse`. Emission and temperature are used with a scale of one.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        bounds: BBox,
+        sigma_a: DVec3,
+        sigma_s: DVec3,
+        g: f64,
+        sparse: bool,
+    ) -> io::Result<GridMedium> {
+        let file = VolumeFile::load(path)?;
+        let grid = |name: &str| -> Option<Arc<dyn VoxelGrid>> {
+            let grid = file.channel(name)?;
+            Some(if sparse { Arc::new(BrickGrid::from_dense(grid)) } else { Arc::new(grid.clone()) })
+        };
+        let density = grid("density").ok_or_else(|| invalid("volume file has no density channel"))?;
+        let mut medium = GridMedium::new(bounds, density, sigma_a, sigma_s, g);
+        if let Some(emission) = grid("emission") {
+            medium = medium.with_emission(emission, DVec3::ONE);
+        }
+        if let Some(temperature) = grid("temperature") {
+            medium = medium.with_temperature(temperature, 1.0);
+        }
+        Ok(medium)
+    }
+
+    /// `p` in the unit cube of the grid
+    fn local(&self, p: DVec3) -> DVec3 {
+        (p - self.bounds.min) / self.bounds.diagonal()
+    }
+
+    fn le(&self, local: DVec3) -> DVec3 {
+        let mut le = DVec3::ZERO;
+        if let Some((grid, scale)) = &self.emission {
+            le += *scale * grid.lookup(local);
+        }
+        if let Some((grid, scale)) = &self.temperature {
+            le += blackbody(grid.lookup(local)) * *scale;
+        }
+        le
+    }
+
+    /// the part of `ray` up to `t_max` inside the bounds, with the ray's origin
+    /// and direction in grid space. Parameters along it match the world ray.
+    fn segment(&self, ray: &Ray, t_max: f64) -> Option<(DVec3, DVec3, f64, f64)> {
+        let mut clipped = ray.clone();
+        clipped.min_t = 0.0;
+        clipped.max_t = t_max;
+        let (t0, t1) = self.bounds.ray_interval(&clipped)?;
+        Some((self.local(ray.origin), ray.direction / self.bounds.diagonal(), t0, t1))
+    }
+}
+
+impl Medium for GridMedium {
+    fn tr(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> DVec3 {
+        let Some((origin, direction, t0, t1)) = self.segment(ray, t_max) else {
+            return DVec3::ONE;
+        };
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let length = ray.direction.length();
+        let mut tr = DVec3::ONE;
+        self.majorants.traverse(origin, direction, t0, t1, |start, end, majorant| {
+            let mu = majorant * sigma_t.max_element();
+            if mu <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= (1.0 - sampler.get_1d()).ln() / (mu * length);
+                if t >= end {
+                    return true;
+                }
+                let density = self.density.lookup(origin + direction * t);
+                tr *= DVec3::ONE - sigma_t * density / mu;
+                // Russian roulette keeps long rays through thick media cheap
+                if tr.max_element() < 0.1 {
+                    if sampler.get_1d() < 0.75 {
+                        tr = DVec3::ZERO;
+                        return false;
+                    }
+                    tr /= 0.25;
+                }
+            }
+        });
+        tr
+    }
+
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> MediumSample {
+        let mut result = MediumSample { weight: DVec3::ONE, emission: DVec3::ZERO, interaction: None };
+        let Some((origin, direction, t0, t1)) = self.segment(ray, t_max) else {
+            return result;
+        };
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let length = ray.direction.length();
+        self.majorants.traverse(origin, direction, t0, t1, |start, end, majorant| {
+            let mu = majorant * sigma_t.max_element();
+            if mu <= 0.0 {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= (1.0 - sampler.get_1d()).ln() / (mu * length);
+                if t >= end {
+                    return true;
+                }
+                let local = origin + direction * t;
+                let density = self.density.lookup(local);
+                let (sigma_a, sigma_s) = (self.sigma_a * density, self.sigma_s * density);
+                let sigma_n = DVec3::splat(mu) - sigma_a - sigma_s;
+                if self.emission.is_some() || self.temperature.is_some() {
+                    result.emission += result.weight * sigma_a * self.le(local) / mu;
+                }
+                // pick absorption, real or null scattering by their average share
+                let average = |v: DVec3| (v.x + v.y + v.z) / (3.0 * mu);
+                let (p_a, p_s) = (average(sigma_a), average(sigma_s));
+                let u = sampler.get_1d();
+                if u < p_a {
+                    result.weight = DVec3::ZERO;
+                    return false;
+                }
+                if u < p_a + p_s {
+                    result.weight *= sigma_s / (mu * p_s);
+                    result.interaction = Some(MediumInteraction { p: ray.at(t), phase: self.phase });
+                    return false;
+                }
+                let p_n = 1.0 - p_a - p_s;
+                if p_n <= 0.0 {
+                    result.weight = DVec3::ZERO;
+                    return false;
+                }
+                result.weight *= sigma_n / (mu * p_n);
+            }
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    fn ramp() -> DenseGrid {
+        let resolution = [20, 3, 11];
+        let values = (0..60 * 11).map(|i| if i % 20 < 9 { 0.0 } else { (i % 20 + i / 60) as f32 * 0.1 }).collect();
+        DenseGrid::new(resolution, values)
+    }
+
+    #[test]
+    fn test_volume_file_and_brick_grid() {
+        let file = VolumeFile { channels: vec![("density".to_string(), ramp()), ("temperature".to_string(), ramp())] };
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 4 + 12 + 4 + 2 * 16 + 2 * 4 * 20 * 3 * 11);
+        assert_eq!(VolumeFile::read(&mut bytes.as_slice()).unwrap(), file);
+        assert!(VolumeFile::read(&mut &bytes[..40]).is_err());
+
+        // headers claiming more than the file holds fail without allocating it
+        let header = |resolution: [u32; 3], count: u32| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(VERSION.to_le_bytes());
+            resolution.iter().chain([&count]).for_each(|n| bytes.extend(n.to_le_bytes()));
+            bytes
+        };
+        let error = VolumeFile::read(&mut header([u32::MAX; 3], 0).as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut huge = header([1 << 16, 1 << 16, 1 << 8], 1);
+        huge.extend([b'd'; NAME_LENGTH]);
+        let error = VolumeFile::read(&mut huge.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let error = VolumeFile::read(&mut header([1; 3], u32::MAX).as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let dense = ramp();
+        let bricks = BrickGrid::from_dense(&dense);
+        // the first of the three columns of bricks along x is empty
+        assert_eq!(bricks.stored_bricks(), 2 * 2);
+        let mut sampler = RandomSampler::seeded(1);
+        for _ in 0..100 {
+            let p = DVec3::new(sampler.get_1d(), sampler.get_1d(), sampler.get_1d());
+            assert_eq!(bricks.lookup(p), dense.lookup(p));
+        }
+        // trilinear lookups reproduce a linear ramp away from the faces
+        let (a, b) = (dense.lookup(DVec3::new(12.5 / 20.0, 0.5, 0.5)), dense.lookup(DVec3::new(13.5 / 20.0, 0.5, 0.5)));
+        assert!((b - a - 0.1).abs() < 1e-6);
+        assert!((dense.lookup(DVec3::new(13.0 / 20.0, 0.5, 0.5)) - (a + b) / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_grid_medium_tracking() {
+        // a constant grid behaves like a homogeneous medium: passing through
+        // weighs the transmittance on average, scattering and emission the
+        // integrals of sigma_s and sigma_a Le times the transmittance
+        let grid = Arc::new(DenseGrid::new([2, 2, 2], vec![0.5; 8]));
+        let (sigma_a, sigma_s) = (DVec3::new(0.2, 0.4, 0.6), DVec3::new(1.0, 0.8, 0.6));
+        let bounds = BBox::new(DVec3::splat(-1.0), DVec3::splat(1.0));
+        let medium = GridMedium::new(bounds, grid.clone(), sigma_a, sigma_s, 0.0).with_emission(grid, DVec3::new(1.0, 2.0, 3.0));
+        let ray = Ray::new(DVec3::new(0.2, -0.3, -3.0), DVec3::new(0.0, 0.0, 2.0));
+        let sigma_t = (sigma_a + sigma_s) * 0.5;
+        let expected_tr = (-sigma_t * 2.0).exp();
+
+        let mut sampler = RandomSampler::seeded(2);
+        let n = 100_000;
+        let (mut tr, mut through, mut scattered, mut emission) = (DVec3::ZERO, DVec3::ZERO, DVec3::ZERO, DVec3::ZERO);
+        for _ in 0..n {
+            tr += medium.tr(&ray, 10.0, &mut sampler) / n as f64;
+            let sample = medium.sample(&ray, 10.0, &mut sampler);
+            emission += sample.emission / n as f64;
+            match sample.interaction {
+                Some(interaction) => {
+                    assert!(bounds.contains(&interaction.p));
+                    scattered += sample.weight / n as f64;
+                }
+                None => through += sample.weight / n as f64,
+            }
+        }
+        let absorbed = DVec3::ONE - expected_tr;
+        let expected_emission = sigma_a * 0.5 * DVec3::new(0.5, 1.0, 1.5) / sigma_t * absorbed;
+        for (value, expected) in [
+            (tr, expected_tr),
+            (through, expected_tr),
+            (scattered, sigma_s * 0.5 / sigma_t * absorbed),
+            (emission, expected_emission),
+        ] {
+            assert!((value - expected).abs().max_element() < 1e-2, "{value} vs {expected}");
+        }
+        // a ray missing the bounds sees nothing
+        let miss = Ray::new(DVec3::new(2.0, 0.0, -3.0), DVec3::Z);
+        assert_eq!(medium.tr(&miss, 10.0, &mut sampler), DVec3::ONE);
+    }
+
+    #[test]
+    fn test_blackbody() {
+        // hotter bodies are bluer
+        let (warm, hot) = (blackbody(2000.0), blackbody(10000.0));
+        assert!(warm.x > warm.z && hot.z > hot.x);
+        assert!(warm.max_element() <= 1.0 && hot.max_element() <= 1.0);
+        assert!(blackbody(5800.0).min_element() > 0.8);
+        assert_eq!(blackbody(0.0), DVec3::ZERO);
+    }
+}